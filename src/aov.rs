@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use exr::prelude::{AnyChannel, AnyChannels, FlatSamples, Image, SmallVec, WritableImage};
+
+use crate::gpu_buffer;
+use crate::screenshot::HdrImage;
+
+/// What the viewport shows, the rendered image or one of the arbitrary output variables.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum AovChannel {
+    #[default]
+    Beauty = 0,
+    Albedo = 1,
+    Normal = 2,
+    Position = 3,
+    Depth = 4,
+    MaterialId = 5,
+    SphereId = 6,
+}
+
+impl AovChannel {
+    pub const ALL: [Self; 7] = [
+        Self::Beauty,
+        Self::Albedo,
+        Self::Normal,
+        Self::Position,
+        Self::Depth,
+        Self::MaterialId,
+        Self::SphereId,
+    ];
+}
+
+/// The arbitrary output variables of a pixel, laid out like `Aov` in the kernel. They
+/// come from the first hit of the first sample, so they aren't antialiased.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuAov {
+    pub albedo: [f32; 3],
+    pub material_idx: u32,
+    pub normal: [f32; 3],
+    pub sphere_idx: u32,
+    pub position: [f32; 3],
+    /// The distance along the view direction.
+    pub depth: f32,
+}
+
+/// The AOVs of an image, read back from the AOV buffer of the render targets.
+pub struct AovImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<GpuAov>,
+}
+
+impl AovImage {
+    pub fn read(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        aov_buffer: &wgpu::Buffer,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> Self {
+        let mut pixels = gpu_buffer::read_buffer::<GpuAov>(device, queue, aov_buffer);
+        pixels.truncate((size.width * size.height) as usize);
+        assert_eq!(pixels.len(), (size.width * size.height) as usize, "The targets were created without AOVs");
+        Self {
+            width: size.width,
+            height: size.height,
+            pixels,
+        }
+    }
+
+    /// Writes the image and the AOVs as the channels of a single EXR layer, named the way
+    /// compositing software expects them: `R`, `G`, `B`, `albedo.R`, `N.X`, `P.X`, `Z`,
+    /// `material_id` and `sphere_id`. The ids are unsigned integers, `u32::MAX` for the sky.
+    pub fn save_exr<P: AsRef<Path>>(&self, beauty: &HdrImage, path: P) -> Result<(), exr::error::Error> {
+        assert_eq!(beauty.size(), winit::dpi::PhysicalSize::new(self.width, self.height));
+
+        let f32_channel = |name: &str, value: &dyn Fn(usize) -> f32| {
+            let samples = (0..self.pixels.len()).map(value).collect();
+            AnyChannel::new(name, FlatSamples::F32(samples))
+        };
+        let u32_channel = |name: &str, value: &dyn Fn(&GpuAov) -> u32| {
+            AnyChannel::new(name, FlatSamples::U32(self.pixels.iter().map(value).collect()))
+        };
+        let channels = vec![
+            f32_channel("R", &|i| beauty.pixels()[i][0]),
+            f32_channel("G", &|i| beauty.pixels()[i][1]),
+            f32_channel("B", &|i| beauty.pixels()[i][2]),
+            f32_channel("albedo.R", &|i| self.pixels[i].albedo[0]),
+            f32_channel("albedo.G", &|i| self.pixels[i].albedo[1]),
+            f32_channel("albedo.B", &|i| self.pixels[i].albedo[2]),
+            f32_channel("N.X", &|i| self.pixels[i].normal[0]),
+            f32_channel("N.Y", &|i| self.pixels[i].normal[1]),
+            f32_channel("N.Z", &|i| self.pixels[i].normal[2]),
+            f32_channel("P.X", &|i| self.pixels[i].position[0]),
+            f32_channel("P.Y", &|i| self.pixels[i].position[1]),
+            f32_channel("P.Z", &|i| self.pixels[i].position[2]),
+            f32_channel("Z", &|i| self.pixels[i].depth),
+            u32_channel("material_id", &|aov| aov.material_idx),
+            u32_channel("sphere_id", &|aov| aov.sphere_idx),
+        ];
+
+        let size = (self.width as usize, self.height as usize);
+        Image::from_channels(size, AnyChannels::sort(SmallVec::from_vec(channels)))
+            .write()
+            .to_file(path)
+    }
+}
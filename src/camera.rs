@@ -1,24 +1,37 @@
 use cgmath::*;
 use winit::event::*;
 use winit::dpi::PhysicalPosition;
-use instant::Duration;
+use std::time::Duration;
 use std::f32::consts::FRAC_PI_2;
 
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
-    1.0, 0.0, 0.0, 0.0,
-    0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.0,
-    0.0, 0.0, 0.5, 1.0,
-);
-
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub position: Point3<f32>,
     yaw: Rad<f32>,
     pitch: Rad<f32>,
+    /// Vertical field of view.
+    pub vfov: Rad<f32>,
+    /// Radius of the thin lens. Zero gives a pinhole camera without defocus blur.
+    pub aperture: f32,
+    /// Distance along the view direction of the plane that is in perfect focus.
+    pub focus_distance: f32,
+    /// Number of aperture blades. Anything below three gives a circular aperture.
+    pub aperture_blades: u32,
+    /// Rotation of the polygonal aperture around the view direction.
+    pub aperture_rotation: Rad<f32>,
+    /// Time at which the shutter opens. Each ray samples a time in the open interval.
+    pub shutter_open: f32,
+    /// Time at which the shutter closes. Equal to `shutter_open` to disable motion blur.
+    pub shutter_close: f32,
+}
+
+/// The view the viewport and headless renders start with.
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(0.0), Deg(60.0))
+    }
 }
 
 impl Camera {
@@ -26,59 +39,145 @@ impl Camera {
         V: Into<Point3<f32>>,
         Y: Into<Rad<f32>>,
         P: Into<Rad<f32>>,
+        F: Into<Rad<f32>>,
     >(
         position: V,
         yaw: Y,
         pitch: P,
+        vfov: F,
     ) -> Self {
         Self {
             position: position.into(),
             yaw: yaw.into(),
             pitch: pitch.into(),
+            vfov: vfov.into(),
+            aperture: 0.0,
+            focus_distance: 1.0,
+            aperture_blades: 0,
+            aperture_rotation: Rad(0.0),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         }
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
+    pub fn yaw(&self) -> Rad<f32> {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> Rad<f32> {
+        self.pitch
+    }
+
+    /// Sets the view direction. The pitch is clamped just like when looking around.
+    pub fn set_orientation(&mut self, yaw: Rad<f32>, pitch: Rad<f32>) {
+        self.yaw = yaw;
+        self.pitch = Rad(pitch.0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+    }
+
+    pub fn forward(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
-        Matrix4::look_to_rh(
-            self.position,
-            Vector3::new(
-                cos_yaw * cos_pitch,
-                sin_pitch,
-                sin_yaw * cos_pitch,
-            ),
-            Vector3::unit_y(),
+        Vector3::new(
+            cos_yaw * cos_pitch,
+            sin_pitch,
+            sin_yaw * cos_pitch,
+        ).normalize()
+    }
+
+    /// Returns the orthonormal camera basis `(u, v, w)`, with `w` pointing away from the view direction.
+    fn basis(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let w = -self.forward();
+        let u = Vector3::unit_y().cross(w).normalize();
+        let v = w.cross(u);
+        (u, v, w)
+    }
+
+    /// Returns the origin and direction of the pinhole ray through the given
+    /// normalized screen coordinates, with `(0, 0)` at the top left corner.
+    pub fn primary_ray(&self, aspect: f32, s: f32, t: f32) -> (glm::Vec3, glm::Vec3) {
+        let (u, v, w) = self.basis();
+        let viewport_height = 2.0 * (0.5 * self.vfov.0).tan();
+        let viewport_width = aspect * viewport_height;
+        let horizontal = viewport_width * u;
+        let vertical = viewport_height * v;
+        let upper_left_corner = -0.5 * horizontal + 0.5 * vertical - w;
+
+        let direction = (upper_left_corner + s * horizontal - t * vertical).normalize();
+        (
+            glm::vec3(self.position.x, self.position.y, self.position.z),
+            glm::vec3(direction.x, direction.y, direction.z),
         )
     }
-}
 
-pub struct Projection {
-    aspect: f32,
-    fov: Rad<f32>,
-    znear: f32,
-    zfar: f32,
-}
+    /// Projects a point to normalized screen coordinates, the inverse of `primary_ray`.
+    /// Returns `None` for points behind the camera.
+    pub fn project(&self, aspect: f32, point: &glm::Vec3) -> Option<glm::Vec2> {
+        let (u, v, w) = self.basis();
+        let viewport_height = 2.0 * (0.5 * self.vfov.0).tan();
+        let viewport_width = aspect * viewport_height;
 
-impl Projection {
-    pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fov: F, znear: f32, zfar: f32) -> Self {
-        Self {
-            aspect: width as f32 / height as f32,
-            fov: fov.into(),
-            znear,
-            zfar,
+        let d = Vector3::new(point.x, point.y, point.z) - self.position.to_vec();
+        let depth = -d.dot(w);
+        if depth <= 0.0 {
+            return None;
         }
+
+        let x = d.dot(u) / depth;
+        let y = d.dot(v) / depth;
+        Some(glm::vec2(x / viewport_width + 0.5, 0.5 - y / viewport_height))
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
-        self.aspect = width as f32 / height as f32;
+    /// Moves the focus plane so that it contains the given point.
+    pub fn focus_at(&mut self, point: &glm::Vec3) {
+        let to_point = Vector3::new(point.x, point.y, point.z) - self.position.to_vec();
+        self.focus_distance = to_point.dot(self.forward()).max(0.01);
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fov, self.aspect, self.znear, self.zfar)
+    pub fn to_gpu(&self, aspect: f32) -> GpuCamera {
+        let (u, v, w) = self.basis();
+        let viewport_height = 2.0 * (0.5 * self.vfov.0).tan();
+        let viewport_width = aspect * viewport_height;
+        // The viewport is placed on the focus plane, so that rays starting anywhere on
+        // the lens converge there.
+        let horizontal = self.focus_distance * viewport_width * u;
+        let vertical = self.focus_distance * viewport_height * v;
+        let upper_left_corner =
+            self.position - 0.5 * horizontal + 0.5 * vertical - self.focus_distance * w;
+
+        GpuCamera {
+            eye: self.position.to_vec().extend(0.0).into(),
+            upper_left_corner: upper_left_corner.to_vec().extend(0.0).into(),
+            horizontal: horizontal.extend(0.0).into(),
+            vertical: vertical.extend(0.0).into(),
+            u: u.extend(0.0).into(),
+            v: v.extend(0.0).into(),
+            lens_radius: self.aperture,
+            aperture_blades: self.aperture_blades,
+            aperture_rotation: self.aperture_rotation.0,
+            shutter_open: self.shutter_open,
+            shutter_close: self.shutter_close,
+            _padding: [0; 3],
+        }
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuCamera {
+    pub eye: [f32; 4],
+    pub upper_left_corner: [f32; 4],
+    pub horizontal: [f32; 4],
+    pub vertical: [f32; 4],
+    pub u: [f32; 4],
+    pub v: [f32; 4],
+    pub lens_radius: f32,
+    pub aperture_blades: u32,
+    pub aperture_rotation: f32,
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+    _padding: [u32; 3],
+}
+
 pub struct CameraController {
     amount_left: f32,
     amount_right: f32,
@@ -110,7 +209,7 @@ impl CameraController {
         }
     }
 
-    pub fn process_keyboard(&mut seld, key: VirtualKeyCode, state: ElementState) -> bool {
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
         let amount = if state == ElementState::Pressed { 1.0 } else { 0.0 };
         match key {
             VirtualKeyCode::W | VirtualKeyCode::Up => {
@@ -145,7 +244,7 @@ impl CameraController {
         self.rotate_horizontal = mouse_dx as f32;
         self.rotate_vertical   = mouse_dy as f32;
     }
-    
+
     pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
         self.scroll = -match delta {
             // I'm assuming a line is about 100 pixels
@@ -157,8 +256,12 @@ impl CameraController {
         };
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+    /// Returns true if the camera moved, so that accumulated samples can be discarded.
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) -> bool {
         let dt = dt.as_secs_f32();
+        let old_position = camera.position;
+        let old_yaw = camera.yaw;
+        let old_pitch = camera.pitch;
 
         // Move forward/backward and left/right
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
@@ -196,7 +299,7 @@ impl CameraController {
         } else if camera.pitch > Rad(SAFE_FRAC_PI_2) {
             camera.pitch = Rad(SAFE_FRAC_PI_2);
         }
+
+        camera.position != old_position || camera.yaw != old_yaw || camera.pitch != old_pitch
     }
 }
-
-
@@ -0,0 +1,251 @@
+use std::f32::consts::TAU;
+
+use cgmath::Rad;
+
+use crate::camera::Camera;
+
+/// A camera pose at a point in time.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Keyframe {
+    /// Time in seconds from the start of the path.
+    pub time: f32,
+    pub position: [f32; 3],
+    /// Yaw, pitch and vertical field of view in radians.
+    pub yaw: f32,
+    pub pitch: f32,
+    pub vfov: f32,
+}
+
+impl Keyframe {
+    pub fn from_camera(camera: &Camera, time: f32) -> Self {
+        Self {
+            time,
+            position: camera.position.into(),
+            yaw: camera.yaw().0,
+            pitch: camera.pitch().0,
+            vfov: camera.vfov.0,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = self.position.into();
+        camera.set_orientation(Rad(self.yaw), Rad(self.pitch));
+        camera.vfov = Rad(self.vfov);
+    }
+
+    /// Index of the yaw in `to_array`.
+    const YAW: usize = 3;
+
+    fn to_array(self) -> [f32; 6] {
+        let [x, y, z] = self.position;
+        [x, y, z, self.yaw, self.pitch, self.vfov]
+    }
+
+    fn from_array(time: f32, a: [f32; 6]) -> Self {
+        Self {
+            time,
+            position: [a[0], a[1], a[2]],
+            yaw: a[3],
+            pitch: a[4],
+            vfov: a[5],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum Interpolation {
+    /// Passes through every keyframe.
+    CatmullRom,
+    /// Uses the keyframes as the control points of one Bézier curve, which starts and
+    /// ends at the first and last keyframe and only approaches the ones in between.
+    Bezier,
+}
+
+/// Keyframes sorted by time, and how to interpolate between them.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        Self {
+            keyframes: Vec::new(),
+            interpolation: Interpolation::CatmullRom,
+        }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Inserts a keyframe, replacing one at the same time.
+    pub fn insert(&mut self, keyframe: Keyframe) {
+        self.keyframes.retain(|k| k.time != keyframe.time);
+        self.keyframes.push(keyframe);
+        self.sort();
+    }
+
+    pub fn remove(&mut self, idx: usize) {
+        self.keyframes.remove(idx);
+    }
+
+    /// Changes the time of a keyframe, which may move it to a different index.
+    pub fn set_time(&mut self, idx: usize, time: f32) {
+        self.keyframes[idx].time = time;
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        self.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    /// The time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0_f32, |k| k.time)
+    }
+
+    /// Returns the interpolated pose at the given time, clamped to the ends of the path.
+    pub fn sample(&self, time: f32) -> Option<Keyframe> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if self.keyframes.len() == 1 || time <= first.time {
+            return Some(Keyframe { time, ..*first });
+        }
+        if time >= last.time {
+            return Some(Keyframe { time, ..*last });
+        }
+
+        let values = match self.interpolation {
+            Interpolation::CatmullRom => self.catmull_rom(time),
+            Interpolation::Bezier => self.bezier((time - first.time) / (last.time - first.time)),
+        };
+        Some(Keyframe::from_array(time, values))
+    }
+
+    fn catmull_rom(&self, time: f32) -> [f32; 6] {
+        let n = self.keyframes.len();
+        let i = self.keyframes.partition_point(|k| k.time <= time).clamp(1, n - 1) - 1;
+        let k1 = &self.keyframes[i];
+        let k2 = &self.keyframes[i + 1];
+
+        let t = (time - k1.time) / (k2.time - k1.time);
+        let points = self.unwrapped_points();
+        // The end segments reuse their own keyframes as the missing neighbours.
+        let (p0, p1, p2, p3) = (points[i.saturating_sub(1)], points[i], points[i + 1], points[(i + 2).min(n - 1)]);
+        std::array::from_fn(|c| {
+            0.5 * (2.0 * p1[c]
+                + (p2[c] - p0[c]) * t
+                + (2.0 * p0[c] - 5.0 * p1[c] + 4.0 * p2[c] - p3[c]) * t * t
+                + (3.0 * p1[c] - p0[c] - 3.0 * p2[c] + p3[c]) * t * t * t)
+        })
+    }
+
+    /// Evaluates the Bézier curve with De Casteljau's algorithm.
+    fn bezier(&self, t: f32) -> [f32; 6] {
+        let mut points = self.unwrapped_points();
+        for len in (1..points.len()).rev() {
+            for i in 0..len {
+                points[i] = std::array::from_fn(|c| (1.0 - t) * points[i][c] + t * points[i + 1][c]);
+            }
+        }
+        points[0]
+    }
+
+    /// The keyframes as arrays, with each yaw moved by whole turns to within half a turn of
+    /// the previous one, so that the camera turns the short way across ±180°.
+    fn unwrapped_points(&self) -> Vec<[f32; 6]> {
+        let mut points: Vec<[f32; 6]> = self.keyframes.iter().map(|k| k.to_array()).collect();
+        for i in 1..points.len() {
+            let turns = ((points[i][Keyframe::YAW] - points[i - 1][Keyframe::YAW]) / TAU).round();
+            points[i][Keyframe::YAW] -= turns * TAU;
+        }
+        points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, yaw_degrees: f32) -> Keyframe {
+        Keyframe {
+            time,
+            position: [time, 2.0 * time, -time * time],
+            yaw: yaw_degrees.to_radians(),
+            pitch: 0.1 * time,
+            vfov: 1.0 + 0.2 * time,
+        }
+    }
+
+    fn path(interpolation: Interpolation, keyframes: &[Keyframe]) -> CameraPath {
+        let mut path = CameraPath { keyframes: Vec::new(), interpolation };
+        for keyframe in keyframes {
+            path.insert(*keyframe);
+        }
+        path
+    }
+
+    /// The difference of two angles in radians, in [0, π].
+    fn angle_between(a: f32, b: f32) -> f32 {
+        let d = (a - b).rem_euclid(TAU);
+        d.min(TAU - d)
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_keyframes() {
+        let keyframes = [keyframe(0.0, 10.0), keyframe(1.0, 80.0), keyframe(2.5, -30.0), keyframe(4.0, 45.0)];
+        let path = path(Interpolation::CatmullRom, &keyframes);
+        for keyframe in &keyframes {
+            assert_eq!(path.sample(keyframe.time), Some(*keyframe));
+        }
+    }
+
+    #[test]
+    fn paths_start_and_end_at_their_keyframes() {
+        let keyframes = [keyframe(0.0, 10.0), keyframe(1.0, 80.0), keyframe(2.5, -30.0)];
+        for interpolation in [Interpolation::CatmullRom, Interpolation::Bezier] {
+            let path = path(interpolation, &keyframes);
+            assert_eq!(path.sample(0.0), Some(keyframes[0]));
+            assert_eq!(path.sample(2.5), Some(keyframes[2]));
+        }
+    }
+
+    #[test]
+    fn yaw_takes_the_short_way_across_the_seam() {
+        let keyframes = [keyframe(0.0, -179.0), keyframe(1.0, 179.0), keyframe(2.0, -178.0)];
+        for interpolation in [Interpolation::CatmullRom, Interpolation::Bezier] {
+            let path = path(interpolation, &keyframes);
+            for i in 0..=20 {
+                let yaw = path.sample(0.1 * i as f32).unwrap().yaw;
+                let from_seam = angle_between(yaw, 180_f32.to_radians()).to_degrees();
+                assert!(from_seam < 2.5, "{:?} turns {} degrees away from the seam", interpolation, from_seam);
+            }
+        }
+    }
+
+    #[test]
+    fn keyframes_on_the_seam_are_reached_up_to_whole_turns() {
+        let keyframes = [keyframe(0.0, 170.0), keyframe(1.0, -170.0), keyframe(2.0, 160.0)];
+        let path = path(Interpolation::CatmullRom, &keyframes);
+        for keyframe in &keyframes {
+            let sample = path.sample(keyframe.time).unwrap();
+            assert!(angle_between(sample.yaw, keyframe.yaw) < 1e-5);
+            assert_eq!(sample.position, keyframe.position);
+        }
+    }
+
+    #[test]
+    fn sampling_is_deterministic() {
+        let keyframes = [keyframe(0.0, 170.0), keyframe(0.7, -150.0), keyframe(1.9, 20.0), keyframe(3.0, -90.0)];
+        for interpolation in [Interpolation::CatmullRom, Interpolation::Bezier] {
+            let path = path(interpolation, &keyframes);
+            for i in 0..=30 {
+                let time = 0.1 * i as f32;
+                let bits = |k: Keyframe| k.to_array().map(f32::to_bits);
+                assert_eq!(bits(path.sample(time).unwrap()), bits(path.sample(time).unwrap()));
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::path::PathBuf;
+
+use clap::{CommandFactory, Parser, ValueEnum};
+
+use crate::renderer::RendererError;
+use crate::sampling::Sampler;
+
+/// Interactive GPU path tracer. With `--output` the scene is rendered without a window
+/// and saved instead.
+#[derive(Parser, Clone, Debug)]
+#[command(version)]
+pub struct Args {
+    /// The scene file to load.
+    #[arg(default_value = "assets/scene.ron")]
+    pub scene: PathBuf,
+
+    /// Width of the window in logical pixels, or of the output image in pixels.
+    #[arg(long, default_value_t = 800, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: u32,
+
+    /// Height of the window in logical pixels, or of the output image in pixels.
+    #[arg(long, default_value_t = 600, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: u32,
+
+    /// Open a borderless fullscreen window on the current monitor.
+    #[arg(long, conflicts_with_all = ["width", "height", "output"])]
+    pub fullscreen: bool,
+
+    /// How frames are presented to the window.
+    #[arg(long, value_enum, default_value_t = PresentMode::Vsync, conflicts_with = "output")]
+    pub present_mode: PresentMode,
+
+    /// Limit the frame rate of the window.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "output")]
+    pub max_fps: Option<u32>,
+
+    /// Only draw frames while something changes. Once `--max-samples` are reached the
+    /// window stops rendering until there is input.
+    #[arg(long, conflicts_with = "output")]
+    pub on_demand: bool,
+
+    /// The graphics API to use. By default every available one is considered.
+    #[arg(long, value_enum)]
+    pub backend: Option<Backend>,
+
+    /// Index of the GPU to use, in the order the backend lists them. By default the
+    /// adapter is picked by wgpu.
+    #[arg(long)]
+    pub adapter: Option<usize>,
+
+    /// Stop accumulating once this many samples per pixel were traced.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_samples: Option<u32>,
+
+    /// Clamp the brightest component of every sample to this radiance, which trades a
+    /// little energy for fewer fireflies.
+    #[arg(long, value_parser = parse_max_radiance)]
+    pub max_radiance: Option<f32>,
+
+    /// How the random numbers of each path are generated.
+    #[arg(long, value_enum, default_value_t = Sampler::Independent)]
+    pub sampler: Sampler,
+
+    /// Render without a window and save the image to this path. The extension selects
+    /// the format, either `.png` or `.exr`.
+    #[arg(long, requires = "max_samples")]
+    pub output: Option<PathBuf>,
+
+    /// Exposure of PNG output in stops, applied before tone mapping.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub exposure: f32,
+
+    /// Render `--output` with the CPU reference path tracer instead of the GPU. Without
+    /// `--backend` or `--adapter` it is also used when no GPU is found.
+    #[arg(long, requires = "output", conflicts_with_all = ["backend", "adapter"])]
+    pub cpu: bool,
+
+    /// Filter the noise out of the image, in the window from the start and in `--output`.
+    #[arg(long, conflicts_with = "cpu")]
+    pub denoise: bool,
+
+    /// Save the albedo, normal, position, depth, material id and sphere index of the
+    /// first hit as extra channels of the `--output` EXR.
+    #[arg(long, requires = "output", conflicts_with = "cpu")]
+    pub aovs: bool,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Backend {
+    Vulkan,
+    Gl,
+    Dx12,
+    Metal,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum PresentMode {
+    /// Wait for vertical blank, with whichever mode the surface supports.
+    Vsync,
+    /// Don't wait for vertical blank, with whichever mode the surface supports.
+    NoVsync,
+    Fifo,
+    FifoRelaxed,
+    Mailbox,
+    Immediate,
+}
+
+impl Args {
+    /// Parses the command line and exits with a usage error if it's invalid.
+    pub fn parse_and_validate() -> Self {
+        let args = Self::parse();
+        if let Some(output) = &args.output {
+            let extension = output.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+            if !matches!(extension.to_ascii_lowercase().as_str(), "png" | "exr") {
+                Self::command()
+                    .error(
+                        clap::error::ErrorKind::InvalidValue,
+                        format!("--output must end in .png or .exr, got '{}'", output.display()),
+                    )
+                    .exit();
+            }
+            if args.aovs && !extension.eq_ignore_ascii_case("exr") {
+                Self::command()
+                    .error(clap::error::ErrorKind::ArgumentConflict, "--aovs needs an --output ending in .exr")
+                    .exit();
+            }
+        }
+        args
+    }
+
+    pub fn backends(&self) -> wgpu::Backends {
+        match self.backend {
+            None => wgpu::Backends::all(),
+            Some(Backend::Vulkan) => wgpu::Backends::VULKAN,
+            Some(Backend::Gl) => wgpu::Backends::GL,
+            Some(Backend::Dx12) => wgpu::Backends::DX12,
+            Some(Backend::Metal) => wgpu::Backends::METAL,
+        }
+    }
+
+    /// Picks the adapter selected on the command line. If a surface is given, the
+    /// adapter must be able to present to it.
+    pub async fn request_adapter(
+        &self,
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface>,
+    ) -> Result<wgpu::Adapter, RendererError> {
+        let Some(idx) = self.adapter else {
+            return instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: surface,
+                    force_fallback_adapter: false,
+                })
+                .await
+                .ok_or_else(|| RendererError::NoAdapter {
+                    backend: self.backend.map_or("suitable".to_string(), |backend| format!("{:?}", backend)),
+                });
+        };
+
+        let mut adapters: Vec<_> = instance.enumerate_adapters(self.backends()).collect();
+        if idx >= adapters.len() {
+            let available = adapters
+                .iter()
+                .enumerate()
+                .map(|(i, adapter)| format!("\n  {}: {} ({:?})", i, adapter.get_info().name, adapter.get_info().backend))
+                .collect();
+            return Err(RendererError::InvalidAdapterIndex { idx, available });
+        }
+        let adapter = adapters.swap_remove(idx);
+        if surface.is_some_and(|surface| !adapter.is_surface_supported(surface)) {
+            return Err(RendererError::CantPresent { adapter: adapter.get_info().name });
+        }
+        Ok(adapter)
+    }
+
+    /// Returns the present mode to configure the surface with, if the surface supports it.
+    pub fn present_mode(&self, supported: &[wgpu::PresentMode]) -> Result<wgpu::PresentMode, RendererError> {
+        let mode = match self.present_mode {
+            // The automatic modes fall back to a supported mode themselves.
+            PresentMode::Vsync => return Ok(wgpu::PresentMode::AutoVsync),
+            PresentMode::NoVsync => return Ok(wgpu::PresentMode::AutoNoVsync),
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::FifoRelaxed => wgpu::PresentMode::FifoRelaxed,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        };
+        if supported.contains(&mode) {
+            Ok(mode)
+        } else {
+            Err(RendererError::UnsupportedPresentMode {
+                mode,
+                supported: supported.to_vec(),
+            })
+        }
+    }
+}
+
+fn parse_max_radiance(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(radiance) if radiance > 0.0 && radiance.is_finite() => Ok(radiance),
+        Ok(_) => Err("must be a positive number".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
@@ -0,0 +1,708 @@
+use std::f32::consts::{FRAC_1_PI, PI};
+
+use rayon::prelude::*;
+
+use crate::camera::{Camera, GpuCamera};
+use crate::path_tracer::NUM_BOUNCES;
+use crate::sampling::{pixar_onb, Rng, Sampler};
+use crate::scene::{GpuMaterial, Scene, TextureDescriptor};
+use crate::screenshot::HdrImage;
+use crate::sphere::Sphere;
+
+const EPSILON: f32 = 0.001;
+const MIN_T: f32 = 0.001;
+const MAX_T: f32 = 1000.0;
+
+const LUMINANCE: glm::Vec3 = glm::Vec3::new(0.2126, 0.7152, 0.0722);
+const MIN_ALPHA: f32 = 0.001;
+const SHEEN_TINT: f32 = 0.5;
+const CLEARCOAT_ALPHA: f32 = 0.01;
+
+/// Replaces samples that aren't finite by black and clamps the brightest component to
+/// `max_radiance`, like `main` in the kernel.
+fn suppress_outliers(color: glm::Vec3, max_radiance: Option<f32>) -> glm::Vec3 {
+    if !color.iter().all(|c| c.is_finite()) {
+        return glm::Vec3::zeros();
+    }
+    let brightest = color.max();
+    match max_radiance {
+        Some(max_radiance) if brightest > max_radiance => color * (max_radiance / brightest),
+        _ => color,
+    }
+}
+
+/// Tiles are square and rendered in parallel.
+const TILE_SIZE: u32 = 16;
+
+/// A CPU port of the ray tracing kernel. It reads the same encoded materials, texture
+/// data and camera as the kernel and follows it function by function, down to the order
+/// in which random numbers are drawn, so that its images are a reference for the GPU.
+/// Changes to the kernel have to be made here too.
+pub struct CpuPathTracer {
+    spheres: Vec<Sphere>,
+    materials: Vec<GpuMaterial>,
+    textures: Vec<[f32; 3]>,
+    lights: Vec<u32>,
+}
+
+#[derive(Clone, Copy)]
+struct Ray {
+    direction: glm::Vec3,
+    origin: glm::Vec3,
+    time: f32,
+}
+
+struct Intersection {
+    p: glm::Vec3,
+    /// The shading normal, which normal and bump maps perturb.
+    n: glm::Vec3,
+    /// The geometric normal.
+    ng: glm::Vec3,
+    tangent: glm::Vec3,
+    u: f32,
+    v: f32,
+    material_idx: u32,
+}
+
+struct Scatter {
+    ray: Ray,
+    throughput: glm::Vec3,
+}
+
+struct PrincipledParams {
+    base_color: glm::Vec3,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    specular_tint: f32,
+    clearcoat: f32,
+    sheen: f32,
+    transmission: f32,
+    anisotropy: f32,
+    refraction_index: f32,
+}
+
+impl CpuPathTracer {
+    pub fn new(scene: &Scene) -> Self {
+        let (materials, textures) = scene.gpu_materials();
+        Self {
+            spheres: scene.spheres.clone(),
+            materials,
+            textures,
+            lights: scene.light_indices(),
+        }
+    }
+
+    /// Renders the camera's view with the given number of samples per pixel. Sample `i`
+    /// uses the random numbers of frame `i` on the GPU. Samples are clamped to
+    /// `max_radiance`, if any.
+    pub fn render_image(
+        &self,
+        camera: &Camera,
+        size: winit::dpi::PhysicalSize<u32>,
+        samples: u32,
+        sampler: Sampler,
+        max_radiance: Option<f32>,
+    ) -> HdrImage {
+        let gpu_camera = camera.to_gpu(size.width as f32 / size.height as f32);
+        let tiles_x = size.width.div_ceil(TILE_SIZE);
+        let tiles_y = size.height.div_ceil(TILE_SIZE);
+
+        let tiles: Vec<(u32, u32, Vec<[f32; 3]>)> = (0..tiles_x * tiles_y)
+            .into_par_iter()
+            .map(|tile_idx| {
+                let x0 = (tile_idx % tiles_x) * TILE_SIZE;
+                let y0 = (tile_idx / tiles_x) * TILE_SIZE;
+                let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE) as usize);
+                for y in y0..(y0 + TILE_SIZE).min(size.height) {
+                    for x in x0..(x0 + TILE_SIZE).min(size.width) {
+                        let sum = (0..samples).fold(glm::Vec3::zeros(), |sum, frame_idx| {
+                            let color = self.sample_pixel(&gpu_camera, sampler, x, y, size, frame_idx);
+                            sum + suppress_outliers(color, max_radiance)
+                        });
+                        let color = sum / samples.max(1) as f32;
+                        pixels.push([color.x, color.y, color.z]);
+                    }
+                }
+                (x0, y0, pixels)
+            })
+            .collect();
+
+        let mut pixels = vec![[0_f32; 3]; (size.width * size.height) as usize];
+        for (x0, y0, tile) in tiles {
+            let tile_width = (x0 + TILE_SIZE).min(size.width) - x0;
+            for (row, tile_row) in tile.chunks(tile_width as usize).enumerate() {
+                let start = ((y0 + row as u32) * size.width + x0) as usize;
+                pixels[start..start + tile_row.len()].copy_from_slice(tile_row);
+            }
+        }
+        HdrImage::new(size.width, size.height, pixels)
+    }
+
+    /// `main` for a single invocation, without the accumulation.
+    fn sample_pixel(
+        &self,
+        camera: &GpuCamera,
+        sampler: Sampler,
+        x: u32,
+        y: u32,
+        size: winit::dpi::PhysicalSize<u32>,
+        frame_idx: u32,
+    ) -> glm::Vec3 {
+        let mut rng = Rng::with_sampler(sampler, x, y, size.width, frame_idx);
+
+        let u = (x as f32 + rng.next_float()) / size.width as f32;
+        let v = (y as f32 + rng.next_float()) / size.height as f32;
+        let ray = camera_ray(camera, u, v, &mut rng);
+        self.ray_color(ray, &mut rng)
+    }
+
+    fn ray_color(&self, primary_ray: Ray, rng: &mut Rng) -> glm::Vec3 {
+        let mut ray = primary_ray;
+        let mut color = glm::Vec3::zeros();
+        let mut throughput = glm::vec3(1_f32, 1_f32, 1_f32);
+
+        for _ in 0..NUM_BOUNCES {
+            match self.intersect(&ray) {
+                Some(mut intersection) => {
+                    let material = &self.materials[intersection.material_idx as usize];
+                    intersection.n = self.shading_normal(&ray, &intersection, material);
+
+                    if material.id == 4 {
+                        let emission_color = self.texture_lookup(&material.textures[0], intersection.u, intersection.v);
+                        color += throughput.component_mul(&emission_color);
+                        break;
+                    }
+
+                    let scatter = self.scatter_ray(&ray, &intersection, material, rng);
+                    // A shading normal can send rays through the surface, which would leak light.
+                    let direction = scatter.ray.direction;
+                    if direction.dot(&intersection.n) * direction.dot(&intersection.ng) < 0_f32 {
+                        break;
+                    }
+                    ray = scatter.ray;
+                    throughput.component_mul_assign(&scatter.throughput);
+                }
+                None => {
+                    // The ray missed. Output background color.
+                    let t = 0.5 * (ray.direction.y + 1.0);
+                    let sky_color = (1.0 - t) * glm::vec3(1.0, 1.0, 1.0) + t * glm::vec3(0.5, 0.7, 1.0);
+                    color += throughput.component_mul(&sky_color);
+                    break;
+                }
+            }
+        }
+
+        color
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let mut closest = None;
+        let mut closest_t = MAX_T;
+        for sphere in &self.spheres {
+            if let Some(t) = sphere.intersect(&ray.origin, &ray.direction, ray.time, MIN_T, closest_t) {
+                closest_t = t;
+                closest = Some(sphere_intersection(ray, sphere, t));
+            }
+        }
+        closest
+    }
+
+    fn scatter_ray(&self, wo: &Ray, hit: &Intersection, material: &GpuMaterial, rng: &mut Rng) -> Scatter {
+        match material.id {
+            0 => self.scatter_mixture_density(wo, hit, &material.textures[0], rng),
+            1 => self.scatter_metal(wo, hit, &material.textures[0], material.x, rng),
+            2 => scatter_dielectric(wo, hit, material.x, rng),
+            3 => self.scatter_mixture_density(wo, hit, checkerboard_texture(material, hit), rng),
+            5 => {
+                let params = self.principled_params(material, hit);
+                self.scatter_principled(wo, hit, &params, rng)
+            }
+            _ => scatter_missing_material(wo, hit, rng),
+        }
+    }
+
+    fn shading_normal(&self, ray: &Ray, hit: &Intersection, material: &GpuMaterial) -> glm::Vec3 {
+        let has_normal_map = material.normal_map.width != 0;
+        let has_bump_map = material.bump_map.width != 0;
+        if !has_normal_map && !has_bump_map {
+            return hit.n;
+        }
+
+        // A height field with the gradient g has the normal (-g, 1), so the gradients of
+        // the two maps add up.
+        let mut offset = glm::Vec2::zeros();
+        if has_normal_map {
+            let normal = 2_f32 * self.texture_lookup(&material.normal_map, hit.u, hit.v) - glm::vec3(1_f32, 1_f32, 1_f32);
+            offset = normal.xy() / EPSILON.max(normal.z);
+        }
+        if has_bump_map {
+            let map = &material.bump_map;
+            let height = self.texture_lookup(map, hit.u, hit.v).x;
+            // u wraps around the sphere.
+            let u = hit.u + 1_f32 / map.width as f32;
+            let height_u = self.texture_lookup(map, u - u.floor(), hit.v).x;
+            let height_v = self.texture_lookup(map, hit.u, hit.v + 1_f32 / map.height as f32).x;
+            offset -= material.bump_strength * glm::vec2(height_u - height, height_v - height);
+        }
+        let n = (shading_frame(hit) * glm::vec3(offset.x, offset.y, 1_f32)).normalize();
+
+        // The viewer has to be in front of both surfaces.
+        if ray.direction.dot(&n) * ray.direction.dot(&hit.n) <= 0_f32 {
+            return hit.n;
+        }
+        n
+    }
+
+    fn texture_lookup(&self, desc: &TextureDescriptor, u: f32, v: f32) -> glm::Vec3 {
+        let u = u.clamp(0_f32, 1_f32);
+        let v = 1_f32 - v.clamp(0_f32, 1_f32);
+
+        let j = (u * desc.width as f32) as u32;
+        let i = (v * desc.height as f32) as u32;
+        let idx = desc.offset.wrapping_add(i * desc.width + j) as usize;
+
+        // Out of bounds reads are clamped, like the robust buffer access on the GPU.
+        let elem = self.textures[idx.min(self.textures.len() - 1)];
+        glm::vec3(elem[0], elem[1], elem[2])
+    }
+
+    fn scatter_mixture_density(&self, wo: &Ray, hit: &Intersection, albedo: &TextureDescriptor, rng: &mut Rng) -> Scatter {
+        let scatter_direction = self.sample_mixture_density(hit, wo.time, rng);
+        let material_value = self.eval_lambertian(hit, albedo, &scatter_direction);
+        let material_pdf = pdf_lambertian(hit, &scatter_direction);
+        let light_pdf = self.pdf_light(hit, &scatter_direction, wo.time);
+        let throughput = material_value / EPSILON.max(0.5 * material_pdf + 0.5 * light_pdf);
+        Scatter {
+            ray: Ray {
+                direction: scatter_direction,
+                origin: hit.p,
+                time: wo.time,
+            },
+            throughput,
+        }
+    }
+
+    fn sample_mixture_density(&self, hit: &Intersection, time: f32, rng: &mut Rng) -> glm::Vec3 {
+        if rng.next_float() < 0.5 {
+            sample_lambertian(hit, rng)
+        } else {
+            self.sample_light(hit, time, rng)
+        }
+    }
+
+    fn eval_lambertian(&self, hit: &Intersection, texture: &TextureDescriptor, wi: &glm::Vec3) -> glm::Vec3 {
+        self.texture_lookup(texture, hit.u, hit.v) * FRAC_1_PI * EPSILON.max(hit.n.dot(wi))
+    }
+
+    fn sample_light(&self, hit: &Intersection, time: f32, rng: &mut Rng) -> glm::Vec3 {
+        // Select a random light using a uniform distribution.
+        let num_lights = self.lights.len() as u32;
+        let light_idx = rng.next_uint_in_range(0, num_lights);
+        let sphere = &self.spheres[self.lights[light_idx as usize] as usize];
+        sample_hemisphere(hit, sphere, time, rng)
+    }
+
+    /// The density of `sample_light` in the direction `wi`. Occluders don't change which
+    /// points on the lights are sampled, so only the lights are intersected.
+    fn pdf_light(&self, hit: &Intersection, wi: &glm::Vec3, time: f32) -> f32 {
+        let pdf: f32 = self
+            .lights
+            .iter()
+            .map(|&light_idx| {
+                let sphere = &self.spheres[light_idx as usize];
+                let center = sphere.center_at(time);
+                let oc = hit.p - center;
+                let a = wi.dot(wi);
+                let b = oc.dot(wi);
+                let c = oc.dot(&oc) - sphere.radius * sphere.radius;
+                let discriminant = b * b - a * c;
+                if discriminant < 0_f32 {
+                    return 0_f32;
+                }
+                // Both points where the ray crosses the sphere could have been sampled.
+                pdf_light_point(hit, wi, sphere, &center, (-b - discriminant.sqrt()) / a)
+                    + pdf_light_point(hit, wi, sphere, &center, (-b + discriminant.sqrt()) / a)
+            })
+            .sum();
+        pdf / self.lights.len() as f32
+    }
+
+    fn principled_params(&self, material: &GpuMaterial, hit: &Intersection) -> PrincipledParams {
+        let value = |idx: usize| self.texture_lookup(&material.textures[idx], hit.u, hit.v).x;
+        PrincipledParams {
+            base_color: self.texture_lookup(&material.textures[0], hit.u, hit.v),
+            metallic: value(1),
+            roughness: value(2),
+            specular: value(3),
+            specular_tint: value(4),
+            clearcoat: value(5),
+            sheen: value(6),
+            transmission: value(7),
+            anisotropy: value(8),
+            refraction_index: material.x,
+        }
+    }
+
+    fn scatter_principled(&self, wo: &Ray, hit: &Intersection, params: &PrincipledParams, rng: &mut Rng) -> Scatter {
+        let transmission_weight = (1_f32 - params.metallic) * params.transmission;
+        if rng.next_float() < transmission_weight {
+            return scatter_rough_dielectric(wo, hit, params, rng);
+        }
+
+        let frame = shading_frame(hit);
+        let to_local = frame.transpose();
+        let v = to_local * -wo.direction;
+        let scatter_direction = if rng.next_float() < 0.5 {
+            frame * sample_principled(params, &v, rng)
+        } else {
+            self.sample_light(hit, wo.time, rng)
+        };
+
+        let l = to_local * scatter_direction;
+        let material_value = eval_principled(params, &v, &l);
+        let material_pdf = pdf_principled(params, &v, &l);
+        let light_pdf = self.pdf_light(hit, &scatter_direction, wo.time);
+        let throughput = material_value / EPSILON.max(0.5 * material_pdf + 0.5 * light_pdf);
+        Scatter {
+            ray: Ray {
+                direction: scatter_direction,
+                origin: hit.p,
+                time: wo.time,
+            },
+            throughput,
+        }
+    }
+
+    fn scatter_metal(&self, wo: &Ray, hit: &Intersection, texture: &TextureDescriptor, fuzz: f32, rng: &mut Rng) -> Scatter {
+        let scatter_direction = reflect(&wo.direction, &hit.n) + fuzz * rng.next_vec3_in_unit_sphere();
+        let albedo = self.texture_lookup(texture, hit.u, hit.v);
+        Scatter {
+            ray: Ray {
+                direction: scatter_direction,
+                origin: hit.p,
+                time: wo.time,
+            },
+            throughput: albedo,
+        }
+    }
+}
+
+fn camera_ray(camera: &GpuCamera, s: f32, t: f32, rng: &mut Rng) -> Ray {
+    let vec3 = |v: [f32; 4]| glm::vec3(v[0], v[1], v[2]);
+
+    // Thin lens model: rays start on the lens and converge on the focus plane, which
+    // contains the viewport.
+    let lens_sample = camera.lens_radius * rng.next_in_aperture(camera.aperture_blades, camera.aperture_rotation);
+    let offset = lens_sample.x * vec3(camera.u) + lens_sample.y * vec3(camera.v);
+    let origin = vec3(camera.eye) + offset;
+    let focus_point = vec3(camera.upper_left_corner) + s * vec3(camera.horizontal) - t * vec3(camera.vertical);
+    let time = glm::lerp_scalar(camera.shutter_open, camera.shutter_close, rng.next_float());
+
+    Ray {
+        direction: (focus_point - origin).normalize(),
+        origin,
+        time,
+    }
+}
+
+fn sphere_intersection(ray: &Ray, sphere: &Sphere, t: f32) -> Intersection {
+    let p = ray.origin + t * ray.direction;
+    let n = (1_f32 / sphere.radius) * (p - sphere.center_at(ray.time));
+    let theta = (-n.y).acos();
+    // Spinning the sphere around its y axis only shifts the texture coordinates.
+    let phi = (-n.z).atan2(n.x) + PI - ray.time * sphere.velocity.w;
+    let u = 0.5 * FRAC_1_PI * phi;
+
+    Intersection {
+        p,
+        n,
+        ng: n,
+        tangent: sphere_tangent(&n),
+        u: u - u.floor(),
+        v: FRAC_1_PI * theta,
+        material_idx: sphere.material_idx,
+    }
+}
+
+fn sphere_tangent(n: &glm::Vec3) -> glm::Vec3 {
+    let tangent = glm::vec3(n.z, 0_f32, -n.x);
+    let tangent_length = tangent.norm();
+    // The poles have no line of latitude.
+    if tangent_length < EPSILON {
+        return pixar_onb(n).column(0).into();
+    }
+    tangent / tangent_length
+}
+
+fn scatter_missing_material(wo: &Ray, hit: &Intersection, rng: &mut Rng) -> Scatter {
+    let scatter_direction = hit.n + rng.next_vec3_in_unit_sphere();
+    Scatter {
+        ray: Ray {
+            direction: scatter_direction,
+            origin: hit.p,
+            time: wo.time,
+        },
+        throughput: glm::vec3(0.5, 0.7, 0.9),
+    }
+}
+
+/// Reflects or refracts at a smooth interface, choosing by the Fresnel term.
+fn scatter_dielectric(wo: &Ray, hit: &Intersection, refraction_index: f32, rng: &mut Rng) -> Scatter {
+    let d = wo.direction.normalize();
+    let entering = d.dot(&hit.ng) < 0_f32;
+    let n = if entering { hit.n } else { -hit.n };
+    let eta = if entering { 1_f32 / refraction_index } else { refraction_index };
+
+    let cos_i = 1_f32.min(-d.dot(&n));
+    let sin2_t = eta * eta * 0_f32.max(1_f32 - cos_i * cos_i);
+    let cos_t = 0_f32.max(1_f32 - sin2_t).sqrt();
+    let fresnel = if sin2_t >= 1_f32 { 1_f32 } else { fresnel_dielectric(cos_i, cos_t, eta) };
+
+    let scatter_direction = if rng.next_float() < fresnel {
+        reflect(&d, &n)
+    } else {
+        eta * d + (eta * cos_i - cos_t) * n
+    };
+    Scatter {
+        ray: Ray {
+            direction: scatter_direction,
+            origin: hit.p,
+            time: wo.time,
+        },
+        throughput: glm::vec3(1_f32, 1_f32, 1_f32),
+    }
+}
+
+/// Alternates between the even and odd texture in a 3D checker pattern.
+fn checkerboard_texture<'a>(material: &'a GpuMaterial, hit: &Intersection) -> &'a TextureDescriptor {
+    let sines = (10_f32 * hit.p.x).sin() * (10_f32 * hit.p.y).sin() * (10_f32 * hit.p.z).sin();
+    if sines < 0_f32 { &material.textures[1] } else { &material.textures[0] }
+}
+
+fn sample_lambertian(hit: &Intersection, rng: &mut Rng) -> glm::Vec3 {
+    let v = rng.next_in_cosine_weighted_hemisphere();
+    pixar_onb(&hit.n) * v
+}
+
+fn pdf_lambertian(hit: &Intersection, wi: &glm::Vec3) -> f32 {
+    EPSILON.max(hit.n.dot(wi) * FRAC_1_PI)
+}
+
+fn sample_hemisphere(hit: &Intersection, sphere: &Sphere, time: f32, rng: &mut Rng) -> glm::Vec3 {
+    let v = rng.next_in_unit_hemisphere();
+
+    // Sample the hemisphere facing the intersection point.
+    let center = sphere.center_at(time);
+    let dir = (hit.p - center).normalize();
+    let point_on_sphere = center + pixar_onb(&dir) * (sphere.radius * v);
+    (point_on_sphere - hit.p).normalize()
+}
+
+fn pdf_light_point(hit: &Intersection, wi: &glm::Vec3, sphere: &Sphere, center: &glm::Vec3, t: f32) -> f32 {
+    let to_light = t * wi;
+    let n = (hit.p + to_light - center) / sphere.radius;
+    // Only the hemisphere facing the intersection point is sampled.
+    if t <= MIN_T || n.dot(&(hit.p - center)) <= 0_f32 {
+        return 0_f32;
+    }
+
+    let length_sqr = to_light.dot(&to_light);
+    let cosine = wi.normalize().dot(&n).abs();
+    let area_half_sphere = 2_f32 * PI * sphere.radius * sphere.radius;
+
+    // lengthSqr / cosine is the inverse of the geometric factor, as defined in
+    // "MULTIPLE IMPORTANCE SAMPLING 101".
+    length_sqr / EPSILON.max(cosine * area_half_sphere)
+}
+
+fn reflect(d: &glm::Vec3, n: &glm::Vec3) -> glm::Vec3 {
+    d - 2_f32 * d.dot(n) * n
+}
+
+fn shading_frame(hit: &Intersection) -> glm::Mat3 {
+    let t = (hit.tangent - hit.tangent.dot(&hit.n) * hit.n).normalize();
+    glm::Mat3::from_columns(&[t, hit.n.cross(&t), hit.n])
+}
+
+fn anisotropic_alpha(params: &PrincipledParams) -> glm::Vec2 {
+    let aspect = (1_f32 - 0.9 * params.anisotropy).sqrt();
+    let alpha = params.roughness * params.roughness;
+    glm::vec2(MIN_ALPHA.max(alpha / aspect), MIN_ALPHA.max(alpha * aspect))
+}
+
+fn lobe_weights(params: &PrincipledParams) -> glm::Vec3 {
+    let weights = glm::vec3(1_f32 - params.metallic, 1_f32, 0.25 * params.clearcoat);
+    weights / (weights.x + weights.y + weights.z)
+}
+
+fn eval_principled(params: &PrincipledParams, v: &glm::Vec3, l: &glm::Vec3) -> glm::Vec3 {
+    if v.z <= 0_f32 || l.z <= 0_f32 {
+        return glm::Vec3::zeros();
+    }
+    let h = (l + v).normalize();
+    let l_dot_h = l.dot(&h);
+
+    let ones = glm::vec3(1_f32, 1_f32, 1_f32);
+    let luminance = params.base_color.dot(&LUMINANCE);
+    let tint = if luminance > 0_f32 { params.base_color / luminance } else { ones };
+    let specular_color = glm::lerp(
+        &(params.specular * 0.08 * glm::lerp(&ones, &tint, params.specular_tint)),
+        &params.base_color,
+        params.metallic,
+    );
+    let sheen_color = glm::lerp(&ones, &tint, SHEEN_TINT);
+
+    // Diffuse with retroreflection at grazing angles.
+    let fl = schlick_weight(l.z);
+    let fv = schlick_weight(v.z);
+    let fd90 = 0.5 + 2_f32 * l_dot_h * l_dot_h * params.roughness;
+    let fd = glm::lerp_scalar(1_f32, fd90, fl) * glm::lerp_scalar(1_f32, fd90, fv);
+
+    let fh = schlick_weight(l_dot_h);
+    let f_sheen = fh * params.sheen * sheen_color;
+
+    let alpha = anisotropic_alpha(params);
+    let ds = gtr2_anisotropic(&h, &alpha);
+    let fs = glm::lerp(&specular_color, &ones, fh);
+    let gs = smith_g_anisotropic(l, &alpha) * smith_g_anisotropic(v, &alpha);
+
+    let dr = gtr1(h.z, CLEARCOAT_ALPHA);
+    let fr = glm::lerp_scalar(0.04, 1_f32, fh);
+    let gr = smith_g_ggx(l.z, 0.25) * smith_g_ggx(v.z, 0.25);
+
+    let diffuse = (FRAC_1_PI * fd * params.base_color + f_sheen) * (1_f32 - params.metallic);
+    let f = diffuse + gs * ds * fs + ones * (0.25 * params.clearcoat * gr * fr * dr);
+    f * l.z
+}
+
+fn sample_principled(params: &PrincipledParams, v: &glm::Vec3, rng: &mut Rng) -> glm::Vec3 {
+    let weights = lobe_weights(params);
+    let lobe = rng.next_float();
+    if lobe < weights.x {
+        return rng.next_in_cosine_weighted_hemisphere();
+    }
+
+    let r1 = rng.next_float();
+    let r2 = rng.next_float();
+    let h = if lobe < weights.x + weights.y {
+        sample_ggx(&anisotropic_alpha(params), r1, r2)
+    } else {
+        sample_gtr1(CLEARCOAT_ALPHA, r1, r2)
+    };
+    reflect(&-v, &h)
+}
+
+fn pdf_principled(params: &PrincipledParams, v: &glm::Vec3, l: &glm::Vec3) -> f32 {
+    if v.z <= 0_f32 || l.z <= 0_f32 {
+        return 0_f32;
+    }
+    let weights = lobe_weights(params);
+    let h = (l + v).normalize();
+    // The density of the half vector, transformed to the density of the reflection.
+    let jacobian = 1_f32 / EPSILON.max(4_f32 * v.dot(&h));
+
+    let diffuse_pdf = l.z * FRAC_1_PI;
+    let specular_pdf = gtr2_anisotropic(&h, &anisotropic_alpha(params)) * h.z * jacobian;
+    let clearcoat_pdf = gtr1(h.z, CLEARCOAT_ALPHA) * h.z * jacobian;
+    weights.x * diffuse_pdf + weights.y * specular_pdf + weights.z * clearcoat_pdf
+}
+
+fn scatter_rough_dielectric(wo: &Ray, hit: &Intersection, params: &PrincipledParams, rng: &mut Rng) -> Scatter {
+    let v = -wo.direction;
+    let entering = v.dot(&hit.ng) > 0_f32;
+    let n = if entering { hit.n } else { -hit.n };
+    let eta = if entering { 1_f32 / params.refraction_index } else { params.refraction_index };
+
+    let alpha = MIN_ALPHA.max(params.roughness * params.roughness);
+    let r1 = rng.next_float();
+    let r2 = rng.next_float();
+    let h = pixar_onb(&n) * sample_ggx(&glm::vec2(alpha, alpha), r1, r2);
+
+    let cos_i = v.dot(&h);
+    let sin2_t = eta * eta * 0_f32.max(1_f32 - cos_i * cos_i);
+    let cos_t = 0_f32.max(1_f32 - sin2_t).sqrt();
+    let fresnel = if sin2_t >= 1_f32 { 1_f32 } else { fresnel_dielectric(cos_i, cos_t, eta) };
+
+    let (scatter_direction, tint) = if rng.next_float() < fresnel {
+        (reflect(&-v, &h), glm::vec3(1_f32, 1_f32, 1_f32))
+    } else {
+        (eta * -v + (eta * cos_i - cos_t) * h, params.base_color.map(f32::sqrt))
+    };
+
+    // Walter et al., "Microfacet Models for Refraction through Rough Surfaces", eq. 41.
+    let cos_l = scatter_direction.dot(&n);
+    let reflected = scatter_direction.dot(&hit.ng) * v.dot(&hit.ng) > 0_f32;
+    let valid = cos_i > 0_f32 && (scatter_direction.dot(&h) > 0_f32) == reflected;
+    let g = smith_g1_ggx(v.dot(&n), alpha) * smith_g1_ggx(cos_l.abs(), alpha);
+    let weight = cos_i.abs() * g / EPSILON.max(v.dot(&n) * h.dot(&n));
+    Scatter {
+        ray: Ray {
+            direction: scatter_direction,
+            origin: hit.p,
+            time: wo.time,
+        },
+        throughput: if valid { weight * tint } else { glm::Vec3::zeros() },
+    }
+}
+
+fn fresnel_dielectric(cos_i: f32, cos_t: f32, eta: f32) -> f32 {
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (eta * cos_t - cos_i) / (eta * cos_t + cos_i);
+    0.5 * (rs * rs + rp * rp)
+}
+
+fn schlick_weight(cosine: f32) -> f32 {
+    let m = (1_f32 - cosine).clamp(0_f32, 1_f32);
+    let m2 = m * m;
+    m2 * m2 * m
+}
+
+fn gtr1(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let t = 1_f32 + (alpha2 - 1_f32) * n_dot_h * n_dot_h;
+    (alpha2 - 1_f32) / (PI * alpha2.ln() * t)
+}
+
+fn gtr2_anisotropic(h: &glm::Vec3, alpha: &glm::Vec2) -> f32 {
+    let x = h.x / alpha.x;
+    let y = h.y / alpha.y;
+    let t = x * x + y * y + h.z * h.z;
+    1_f32 / (PI * alpha.x * alpha.y * t * t)
+}
+
+fn smith_g_anisotropic(w: &glm::Vec3, alpha: &glm::Vec2) -> f32 {
+    let x = w.x * alpha.x;
+    let y = w.y * alpha.y;
+    1_f32 / (w.z + (x * x + y * y + w.z * w.z).sqrt())
+}
+
+fn smith_g_ggx(n_dot_v: f32, alpha: f32) -> f32 {
+    let a = alpha * alpha;
+    let b = n_dot_v * n_dot_v;
+    1_f32 / (n_dot_v + (a + b - a * b).sqrt())
+}
+
+fn smith_g1_ggx(n_dot_v: f32, alpha: f32) -> f32 {
+    let cos2 = EPSILON.max(n_dot_v * n_dot_v);
+    let tan2 = (1_f32 - cos2) / cos2;
+    2_f32 / (1_f32 + (1_f32 + alpha * alpha * tan2).sqrt())
+}
+
+fn sample_ggx(alpha: &glm::Vec2, r1: f32, r2: f32) -> glm::Vec3 {
+    let phi = (alpha.y * (2_f32 * PI * r1).sin()).atan2(alpha.x * (2_f32 * PI * r1).cos());
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let inv_alpha2 = cos_phi * cos_phi / (alpha.x * alpha.x) + sin_phi * sin_phi / (alpha.y * alpha.y);
+    let tan2_theta = r2 / ((1_f32 - r2) * inv_alpha2);
+    let cos_theta = 1_f32 / (1_f32 + tan2_theta).sqrt();
+    let sin_theta = 0_f32.max(1_f32 - cos_theta * cos_theta).sqrt();
+    glm::vec3(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+}
+
+fn sample_gtr1(alpha: f32, r1: f32, r2: f32) -> glm::Vec3 {
+    let alpha2 = alpha * alpha;
+    let cos_theta = 0_f32.max((1_f32 - alpha2.powf(1_f32 - r2)) / (1_f32 - alpha2)).sqrt();
+    let sin_theta = 0_f32.max(1_f32 - cos_theta * cos_theta).sqrt();
+    let phi = 2_f32 * PI * r1;
+    glm::vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
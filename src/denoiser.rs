@@ -0,0 +1,339 @@
+use std::rc::Rc;
+
+use crate::path_tracer::{PathTracer, RenderTargets};
+use crate::pipeline_cache::PipelineCache;
+use crate::screenshot::HdrImage;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DenoiseParams {
+    num_samples: u32,
+    step_size: u32,
+    sigma_luminance: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+    _padding: [u32; 3],
+}
+
+/// How strongly the denoiser filters. Larger sigmas blur across larger differences.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DenoiseSettings {
+    /// The number of à-trous iterations, each doubling the filter footprint.
+    pub iterations: u32,
+    /// Scales the luminance difference that is tolerated by the standard deviation of the noise.
+    pub sigma_luminance: f32,
+    /// The exponent of the cosine between the normals.
+    pub sigma_normal: f32,
+    /// The depth difference that is tolerated, relative to the depth and the tap distance.
+    pub sigma_depth: f32,
+}
+
+impl DenoiseSettings {
+    pub const MAX_ITERATIONS: u32 = 8;
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 0.1,
+        }
+    }
+}
+
+/// Edge-aware à-trous wavelet filter for the accumulated image, guided by the albedo,
+/// normal and depth buffers the kernel writes. See `denoiser.wgsl`.
+pub struct Denoiser {
+    bind_group_layout: wgpu::BindGroupLayout,
+    prepare_pipeline: Rc<wgpu::ComputePipeline>,
+    atrous_pipeline: Rc<wgpu::ComputePipeline>,
+    compose_pipeline: Rc<wgpu::ComputePipeline>,
+    params_buffer: wgpu::Buffer,
+    /// The distance between the parameters of consecutive iterations in the params buffer.
+    params_stride: wgpu::BufferAddress,
+    params_bind_group: wgpu::BindGroup,
+}
+
+impl Denoiser {
+    const WORKGROUP_SIZE: u32 = 8;
+
+    pub fn new(device: &wgpu::Device, pipeline_cache: &mut PipelineCache) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let storage_texture_entry = |binding, format| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let storage_buffer_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Denoiser Bind Group Layout"),
+            entries: &[
+                storage_buffer_entry(0, true),
+                texture_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+                storage_texture_entry(4, DenoiserTargets::FILTER_FORMAT),
+                storage_texture_entry(5, PathTracer::STORAGE_FORMAT),
+                storage_buffer_entry(6, false),
+            ],
+        });
+
+        // Every iteration has its own parameters, picked with a dynamic offset, so that
+        // all iterations can be recorded into one encoder.
+        let params_size = std::mem::size_of::<DenoiseParams>() as wgpu::BufferAddress;
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress;
+        let params_stride = params_size.div_ceil(alignment) * alignment;
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            size: DenoiseSettings::MAX_ITERATIONS as wgpu::BufferAddress * params_stride,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            label: Some("denoise params buffer"),
+        });
+        let params_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("denoise params layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(params_size),
+                },
+                count: None,
+            }],
+        });
+        let params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("denoise params bind group"),
+            layout: &params_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &params_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(params_size),
+                }),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Denoiser Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, &params_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let mut pipeline = |label, entry_point| {
+            pipeline_cache
+                .compute_pipeline(device, label, &pipeline_layout, include_str!("denoiser.wgsl"), entry_point)
+                .expect("The embedded denoiser should be valid")
+        };
+
+        Self {
+            prepare_pipeline: pipeline("Denoise Prepare Pipeline", "prepare"),
+            atrous_pipeline: pipeline("Denoise À-Trous Pipeline", "atrous"),
+            compose_pipeline: pipeline("Denoise Compose Pipeline", "compose"),
+            bind_group_layout,
+            params_buffer,
+            params_stride,
+            params_bind_group,
+        }
+    }
+
+    /// Creates the intermediate buffers for denoising the given render targets.
+    pub fn create_targets(&self, device: &wgpu::Device, render_targets: &RenderTargets) -> DenoiserTargets {
+        DenoiserTargets::new(device, &self.bind_group_layout, render_targets)
+    }
+
+    /// Filters the mean of `samples` accumulated samples into the display texture and the
+    /// HDR buffer of the targets.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        targets: &DenoiserTargets,
+        samples: u32,
+        settings: &DenoiseSettings,
+    ) {
+        let iterations = settings.iterations.min(DenoiseSettings::MAX_ITERATIONS);
+        for i in 0..iterations.max(1) {
+            let params = DenoiseParams {
+                num_samples: samples,
+                step_size: 1 << i,
+                sigma_luminance: settings.sigma_luminance,
+                sigma_normal: settings.sigma_normal,
+                sigma_depth: settings.sigma_depth,
+                _padding: [0; 3],
+            };
+            queue.write_buffer(&self.params_buffer, i as wgpu::BufferAddress * self.params_stride, bytemuck::bytes_of(&params));
+        }
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Denoise Pass"),
+        });
+        let workgroups_x = targets.size.width.div_ceil(Self::WORKGROUP_SIZE);
+        let workgroups_y = targets.size.height.div_ceil(Self::WORKGROUP_SIZE);
+
+        // `prepare` writes the ping texture, then every iteration swaps the roles.
+        pass.set_pipeline(&self.prepare_pipeline);
+        pass.set_bind_group(0, &targets.pong_to_ping, &[]);
+        pass.set_bind_group(1, &self.params_bind_group, &[0]);
+        pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+
+        pass.set_pipeline(&self.atrous_pipeline);
+        for i in 0..iterations {
+            let bind_group = if i.is_multiple_of(2) { &targets.ping_to_pong } else { &targets.pong_to_ping };
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.set_bind_group(1, &self.params_bind_group, &[(i as wgpu::BufferAddress * self.params_stride) as u32]);
+            pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+        }
+
+        let result = if iterations.is_multiple_of(2) { &targets.ping_to_pong } else { &targets.pong_to_ping };
+        pass.set_pipeline(&self.compose_pipeline);
+        pass.set_bind_group(0, result, &[]);
+        pass.set_bind_group(1, &self.params_bind_group, &[0]);
+        pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+    }
+
+    /// Denoises the samples accumulated in the render targets the denoiser targets were
+    /// created for, and reads the result back.
+    pub fn denoise_image(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        targets: &DenoiserTargets,
+        samples: u32,
+        settings: &DenoiseSettings,
+    ) -> HdrImage {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Denoise Encoder"),
+        });
+        self.encode(&mut encoder, queue, targets, samples, settings);
+        queue.submit(std::iter::once(encoder.finish()));
+        // The output holds the denoised mean, not a sum.
+        HdrImage::read_accumulation(device, queue, &targets.hdr_buffer, targets.size, 1)
+    }
+}
+
+/// The filter buffers for one set of render targets, which depend on the image size.
+pub struct DenoiserTargets {
+    pub size: winit::dpi::PhysicalSize<u32>,
+    _ping: wgpu::Texture,
+    _pong: wgpu::Texture,
+    _display_buffer: wgpu::Texture,
+    /// The denoised image, for displaying it.
+    pub display_view: wgpu::TextureView,
+    hdr_buffer: wgpu::Buffer,
+    /// Reads the ping texture and writes the pong texture.
+    ping_to_pong: wgpu::BindGroup,
+    pong_to_ping: wgpu::BindGroup,
+}
+
+impl DenoiserTargets {
+    const FILTER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+    fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, render_targets: &RenderTargets) -> Self {
+        let size = render_targets.size;
+        let texture = |label, format| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size.width,
+                    height: size.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[format],
+            })
+        };
+        let ping = texture("Denoise Ping Buffer", Self::FILTER_FORMAT);
+        let pong = texture("Denoise Pong Buffer", Self::FILTER_FORMAT);
+        let display_buffer = texture("Denoised Color Buffer", PathTracer::STORAGE_FORMAT);
+        let ping_view = ping.create_view(&wgpu::TextureViewDescriptor::default());
+        let pong_view = pong.create_view(&wgpu::TextureViewDescriptor::default());
+        let display_view = display_buffer.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let hdr_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            size: render_targets.accumulation_buffer.handle().size(),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+            label: Some("denoised hdr buffer"),
+        });
+
+        let bind_group = |label, input, output| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: render_targets.accumulation_buffer.handle().as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&render_targets.albedo_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&render_targets.normal_depth_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(output),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::TextureView(&display_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: hdr_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let ping_to_pong = bind_group("Denoise Ping To Pong Bind Group", &ping_view, &pong_view);
+        let pong_to_ping = bind_group("Denoise Pong To Ping Bind Group", &pong_view, &ping_view);
+
+        Self {
+            size,
+            _ping: ping,
+            _pong: pong,
+            _display_buffer: display_buffer,
+            display_view,
+            hdr_buffer,
+            ping_to_pong,
+            pong_to_ping,
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// Edge-aware à-trous wavelet filter in the style of SVGF, without the temporal part:
+// the accumulation buffer already averages over time while the camera stands still.
+//
+// `prepare` divides the mean radiance by the albedo of the first hit, so that textures
+// aren't blurred, and estimates the variance of the mean. Every `atrous` iteration
+// filters with a 5x5 B3 spline kernel whose taps are twice as far apart as in the last
+// iteration, weighted by how similar the luminance, the normal and the depth of the
+// taps are. `compose` multiplies the albedo back in.
+
+struct DenoiseParams {
+    numSamples: u32,
+    stepSize: u32,
+    sigmaLuminance: f32,
+    sigmaNormal: f32,
+    sigmaDepth: f32,
+};
+
+@group(0) @binding(0) var<storage, read> accumulation_buffer: array<vec4<f32>>;
+@group(0) @binding(1) var albedoBuffer: texture_2d<f32>;
+@group(0) @binding(2) var normalDepthBuffer: texture_2d<f32>;
+// Demodulated radiance in xyz and the variance of its luminance in w.
+@group(0) @binding(3) var filterInput: texture_2d<f32>;
+@group(0) @binding(4) var filterOutput: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(5) var displayBuffer: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(6) var<storage, read_write> hdrOutput: array<vec4<f32>>;
+
+@group(1) @binding(0) var<uniform> params: DenoiseParams;
+
+const LUMINANCE = vec3(0.2126f, 0.7152f, 0.0722f);
+const MIN_ALBEDO = 1e-3f;
+// Below this many samples the variance of a single pixel is too noisy to guide the filter.
+const MIN_TEMPORAL_SAMPLES = 4u;
+
+// The weights of the B3 spline at the given distance from the center tap.
+fn b3Spline(distance: i32) -> f32 {
+    switch distance {
+        case 0: { return 0.375f; }
+        case 1: { return 0.25f; }
+        default: { return 0.0625f; }
+    }
+}
+
+fn demodulate(radiance: vec3<f32>, albedo: vec3<f32>) -> vec3<f32> {
+    return radiance / max(albedo, vec3(MIN_ALBEDO));
+}
+
+fn albedoAt(pos: vec2<i32>) -> vec3<f32> {
+    return textureLoad(albedoBuffer, pos, 0).xyz;
+}
+
+fn meanAt(pos: vec2<i32>, width: u32) -> vec4<f32> {
+    let idx = u32(pos.y) * width + u32(pos.x);
+    return accumulation_buffer[idx] / f32(max(params.numSamples, 1u));
+}
+
+fn inside(pos: vec2<i32>, size: vec2<u32>) -> bool {
+    return all(pos >= vec2(0)) && all(pos < vec2<i32>(size));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn prepare(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(albedoBuffer);
+    let pos = vec2<i32>(id.xy);
+    if !inside(pos, size) {
+        return;
+    }
+
+    let albedo = albedoAt(pos);
+    let mean = meanAt(pos, size.x);
+    let color = demodulate(mean.xyz, albedo);
+    let luminanceScale = max(dot(albedo, LUMINANCE), MIN_ALBEDO);
+
+    var variance = 0f;
+    if params.numSamples >= MIN_TEMPORAL_SAMPLES {
+        let luminance = dot(mean.xyz, LUMINANCE);
+        let sampleVariance = max(mean.w - luminance * luminance, 0f);
+        variance = sampleVariance / f32(params.numSamples);
+    } else {
+        // The spread of the neighbouring means stands in for the variance of this one.
+        var sum = 0f;
+        var sumSqr = 0f;
+        var count = 0f;
+        for (var dy = -2; dy <= 2; dy++) {
+            for (var dx = -2; dx <= 2; dx++) {
+                let q = pos + vec2(dx, dy);
+                if inside(q, size) {
+                    let luminance = dot(meanAt(q, size.x).xyz, LUMINANCE);
+                    sum += luminance;
+                    sumSqr += luminance * luminance;
+                    count += 1f;
+                }
+            }
+        }
+        let spatialMean = sum / count;
+        variance = max(sumSqr / count - spatialMean * spatialMean, 0f);
+    }
+
+    textureStore(filterOutput, pos, vec4(color, variance / (luminanceScale * luminanceScale)));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn atrous(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(filterInput);
+    let pos = vec2<i32>(id.xy);
+    if !inside(pos, size) {
+        return;
+    }
+
+    let center = textureLoad(filterInput, pos, 0);
+    let centerNormalDepth = textureLoad(normalDepthBuffer, pos, 0);
+    // The sky has no normal and is noise free.
+    if all(centerNormalDepth.xyz == vec3(0f)) {
+        textureStore(filterOutput, pos, center);
+        return;
+    }
+
+    let centerLuminance = dot(center.xyz, LUMINANCE);
+    let luminanceScale = params.sigmaLuminance * sqrt(center.w) + 1e-4f;
+    let step = i32(params.stepSize);
+
+    var colorSum = vec3(0f);
+    var varianceSum = 0f;
+    var weightSum = 0f;
+    for (var dy = -2; dy <= 2; dy++) {
+        for (var dx = -2; dx <= 2; dx++) {
+            let q = pos + step * vec2(dx, dy);
+            if !inside(q, size) {
+                continue;
+            }
+            let tap = textureLoad(filterInput, q, 0);
+            let normalDepth = textureLoad(normalDepthBuffer, q, 0);
+
+            let kernel = b3Spline(abs(dx)) * b3Spline(abs(dy));
+            let luminanceWeight = exp(-abs(dot(tap.xyz, LUMINANCE) - centerLuminance) / luminanceScale);
+            let normalWeight = pow(max(dot(centerNormalDepth.xyz, normalDepth.xyz), 0f), params.sigmaNormal);
+            let depthDifference = abs(centerNormalDepth.w - normalDepth.w);
+            let depthScale = params.sigmaDepth * centerNormalDepth.w * f32(step) * length(vec2(f32(dx), f32(dy)));
+            let depthWeight = exp(-depthDifference / (depthScale + 1e-4f));
+
+            var weight = kernel * luminanceWeight * normalWeight * depthWeight;
+            if dx == 0 && dy == 0 {
+                weight = kernel;
+            }
+            colorSum += weight * tap.xyz;
+            varianceSum += weight * weight * tap.w;
+            weightSum += weight;
+        }
+    }
+
+    textureStore(filterOutput, pos, vec4(colorSum / weightSum, varianceSum / (weightSum * weightSum)));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn compose(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(filterInput);
+    let pos = vec2<i32>(id.xy);
+    if !inside(pos, size) {
+        return;
+    }
+
+    let color = textureLoad(filterInput, pos, 0).xyz * max(albedoAt(pos), vec3(MIN_ALBEDO));
+    hdrOutput[id.y * size.x + id.x] = vec4(color, 1f);
+    textureStore(displayBuffer, pos, vec4(color, 1f));
+}
@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Polls the modification times of a set of files.
+///
+/// Polling is cheap for the handful of files a scene references, and unlike OS
+/// notifications it survives editors that save by replacing the file.
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    pub fn new<I: IntoIterator<Item = PathBuf>>(paths: I) -> Self {
+        let mut watcher = Self {
+            files: Vec::new(),
+            last_poll: Instant::now(),
+        };
+        watcher.watch(paths);
+        watcher
+    }
+
+    /// Replaces the set of watched files.
+    pub fn watch<I: IntoIterator<Item = PathBuf>>(&mut self, paths: I) {
+        self.files = paths
+            .into_iter()
+            .map(|path| {
+                let modified = Self::modified(&path);
+                (path, modified)
+            })
+            .collect();
+    }
+
+    /// Returns true if any of the files changed since the last time it returned true.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < Self::POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = false;
+        for (path, last_modified) in self.files.iter_mut() {
+            let modified = Self::modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+}
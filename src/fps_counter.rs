@@ -25,6 +25,11 @@ impl FpsCounter {
         self.frame_times.len() as f32 / sum
     }
 
+    /// The most recent frame times, oldest first.
+    pub fn frame_times(&self) -> impl Iterator<Item = f32> + '_ {
+        self.frame_times.iter().copied()
+    }
+
     pub fn average_frame_time(&self) -> f32 {
         let sum: f32 = self.frame_times.iter().sum();
         sum / self.frame_times.len() as f32
@@ -0,0 +1,268 @@
+use crate::camera::Camera;
+use crate::sphere::Sphere;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Scale,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GizmoVertex {
+    /// Position in normalized device coordinates.
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+impl GizmoVertex {
+    /// Enough for three axes with a handle each.
+    pub const MAX_VERTICES: usize = 64;
+
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4];
+
+    pub fn layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// The view the gizmo is seen through, to convert between pixels and world space.
+pub struct GizmoView<'a> {
+    camera: &'a Camera,
+    width: f32,
+    height: f32,
+}
+
+impl<'a> GizmoView<'a> {
+    pub fn new(camera: &'a Camera, size: winit::dpi::PhysicalSize<u32>) -> Self {
+        Self {
+            camera,
+            width: size.width as f32,
+            height: size.height as f32,
+        }
+    }
+
+    fn project(&self, point: &glm::Vec3) -> Option<glm::Vec2> {
+        self.camera
+            .project(self.width / self.height, point)
+            .map(|p| glm::vec2(p.x * self.width, p.y * self.height))
+    }
+
+    fn ray(&self, cursor: &glm::Vec2) -> (glm::Vec3, glm::Vec3) {
+        self.camera
+            .primary_ray(self.width / self.height, cursor.x / self.width, cursor.y / self.height)
+    }
+
+    fn to_ndc(&self, pixel: glm::Vec2) -> [f32; 2] {
+        [2.0 * pixel.x / self.width - 1.0, 1.0 - 2.0 * pixel.y / self.height]
+    }
+}
+
+struct Drag {
+    axis: usize,
+    /// The gizmo center when the drag started, which the axis line stays anchored to.
+    origin: glm::Vec3,
+    start_param: f32,
+    start_center: glm::Vec4,
+    start_radius: f32,
+}
+
+/// Translate and scale handles for the selected sphere. Dragging a handle moves the
+/// sphere along that axis, or grows and shrinks it in scale mode.
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    hovered: Option<usize>,
+    drag: Option<Drag>,
+}
+
+impl Gizmo {
+    /// Length of the axes as a fraction of the viewport height.
+    const SIZE: f32 = 0.15;
+    /// How close to an axis in pixels the cursor has to be to grab it.
+    const GRAB_DISTANCE: f32 = 8.0;
+    const LINE_WIDTH: f32 = 3.0;
+    const HANDLE_SIZE: f32 = 10.0;
+
+    const AXIS_COLORS: [[f32; 4]; 3] = [
+        [0.9, 0.2, 0.2, 1.0],
+        [0.2, 0.9, 0.2, 1.0],
+        [0.2, 0.4, 1.0, 1.0],
+    ];
+    const ACTIVE_COLOR: [f32; 4] = [1.0, 0.9, 0.1, 1.0];
+
+    pub fn new() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            hovered: None,
+            drag: None,
+        }
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Returns the center of the gizmo and the world space length of its axes, which
+    /// keeps them the same size on screen at any distance.
+    fn frame(view: &GizmoView, sphere: &Sphere) -> (glm::Vec3, f32) {
+        let center = sphere.center_at(view.camera.shutter_open);
+        let forward = view.camera.forward();
+        let depth = glm::dot(
+            &(center - glm::vec3(view.camera.position.x, view.camera.position.y, view.camera.position.z)),
+            &glm::vec3(forward.x, forward.y, forward.z),
+        );
+        let viewport_height = 2.0 * (0.5 * view.camera.vfov.0).tan();
+        (center, Self::SIZE * depth.max(0.0) * viewport_height)
+    }
+
+    fn axis(idx: usize) -> glm::Vec3 {
+        let mut axis = glm::Vec3::zeros();
+        axis[idx] = 1.0;
+        axis
+    }
+
+    /// Returns the axes as line segments in pixels.
+    fn screen_axes(view: &GizmoView, sphere: &Sphere) -> Option<[(glm::Vec2, glm::Vec2); 3]> {
+        let (center, length) = Self::frame(view, sphere);
+        let origin = view.project(&center)?;
+        let mut axes = [(origin, origin); 3];
+        for (idx, axis) in axes.iter_mut().enumerate() {
+            axis.1 = view.project(&(center + length * Self::axis(idx)))?;
+        }
+        Some(axes)
+    }
+
+    /// Highlights the axis under the cursor.
+    pub fn hover(&mut self, view: &GizmoView, sphere: &Sphere, cursor: &glm::Vec2) {
+        self.hovered = Self::screen_axes(view, sphere).and_then(|axes| {
+            axes.iter()
+                .enumerate()
+                .map(|(idx, (start, end))| (idx, distance_to_segment(cursor, start, end)))
+                .filter(|(_, distance)| *distance < Self::GRAB_DISTANCE)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(idx, _)| idx)
+        });
+    }
+
+    /// Starts dragging the hovered axis. Returns false if the cursor isn't on an axis.
+    pub fn begin_drag(&mut self, view: &GizmoView, sphere: &Sphere, cursor: &glm::Vec2) -> bool {
+        self.hover(view, sphere, cursor);
+        let Some(axis) = self.hovered else {
+            return false;
+        };
+        let (origin, _) = Self::frame(view, sphere);
+        let Some(start_param) = Self::axis_param(view, &origin, axis, cursor) else {
+            return false;
+        };
+
+        self.drag = Some(Drag {
+            axis,
+            origin,
+            start_param,
+            start_center: sphere.center,
+            start_radius: sphere.radius,
+        });
+        true
+    }
+
+    /// Moves or scales the sphere along the dragged axis. Returns true if it changed.
+    pub fn drag(&mut self, view: &GizmoView, sphere: &mut Sphere, cursor: &glm::Vec2) -> bool {
+        let Some(drag) = &self.drag else {
+            return false;
+        };
+        let Some(param) = Self::axis_param(view, &drag.origin, drag.axis, cursor) else {
+            return false;
+        };
+
+        let offset = param - drag.start_param;
+        match self.mode {
+            GizmoMode::Translate => {
+                sphere.center = drag.start_center + glm::vec3_to_vec4(&(offset * Self::axis(drag.axis)));
+            }
+            GizmoMode::Scale => {
+                sphere.radius = (drag.start_radius + offset).max(0.01);
+            }
+        }
+        true
+    }
+
+    /// Returns true if a drag ended.
+    pub fn end_drag(&mut self) -> bool {
+        self.drag.take().is_some()
+    }
+
+    /// Returns the parameter along the axis through `center` that is closest to the ray
+    /// through the cursor, or `None` if the axis points at the camera.
+    fn axis_param(view: &GizmoView, center: &glm::Vec3, axis: usize, cursor: &glm::Vec2) -> Option<f32> {
+        let (origin, direction) = view.ray(cursor);
+        let axis = Self::axis(axis);
+
+        let w0 = center - origin;
+        let b = glm::dot(&axis, &direction);
+        let denom = 1.0 - b * b;
+        if denom < 1e-4 {
+            return None;
+        }
+        Some((b * glm::dot(&direction, &w0) - glm::dot(&axis, &w0)) / denom)
+    }
+
+    /// Returns the triangles to draw the gizmo with.
+    pub fn vertices(&self, view: &GizmoView, sphere: &Sphere) -> Vec<GizmoVertex> {
+        let mut vertices = Vec::with_capacity(GizmoVertex::MAX_VERTICES);
+        let Some(axes) = Self::screen_axes(view, sphere) else {
+            return vertices;
+        };
+
+        let active = self.drag.as_ref().map(|drag| drag.axis).or(self.hovered);
+        for (idx, (start, end)) in axes.iter().enumerate() {
+            let color = if active == Some(idx) {
+                Self::ACTIVE_COLOR
+            } else {
+                Self::AXIS_COLORS[idx]
+            };
+            let mut quad = |a: glm::Vec2, b: glm::Vec2, c: glm::Vec2, d: glm::Vec2| {
+                for p in [a, b, c, a, c, d] {
+                    vertices.push(GizmoVertex { position: view.to_ndc(p), color });
+                }
+            };
+
+            let dir = end - start;
+            if glm::length(&dir) < 1e-3 {
+                continue;
+            }
+            let dir = glm::normalize(&dir);
+            let side = glm::vec2(-dir.y, dir.x);
+
+            let half_width = 0.5 * Self::LINE_WIDTH * side;
+            quad(start - half_width, start + half_width, end + half_width, end - half_width);
+
+            let half_size = 0.5 * Self::HANDLE_SIZE;
+            match self.mode {
+                GizmoMode::Translate => {
+                    // an arrow head
+                    let tip = end + 2.0 * half_size * dir;
+                    for p in [end - half_size * side, end + half_size * side, tip] {
+                        vertices.push(GizmoVertex { position: view.to_ndc(p), color });
+                    }
+                }
+                GizmoMode::Scale => {
+                    let (x, y) = (half_size * dir, half_size * side);
+                    let center = end + x;
+                    quad(center - x - y, center - x + y, center + x + y, center + x - y);
+                }
+            }
+        }
+
+        vertices
+    }
+}
+
+fn distance_to_segment(p: &glm::Vec2, a: &glm::Vec2, b: &glm::Vec2) -> f32 {
+    let ab = b - a;
+    let t = (glm::dot(&(p - a), &ab) / glm::dot(&ab, &ab).max(1e-6)).clamp(0.0, 1.0);
+    glm::distance(p, &(a + t * ab))
+}
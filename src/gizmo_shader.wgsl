@@ -0,0 +1,19 @@
+// Draws the transform gizmo, which is built in normalized device coordinates on the CPU.
+
+struct VertexOutput {
+    @builtin(position) Position : vec4<f32>,
+    @location(0) Color : vec4<f32>,
+}
+
+@vertex
+fn vert_main(@location(0) position : vec2<f32>, @location(1) color : vec4<f32>) -> VertexOutput {
+    var output : VertexOutput;
+    output.Position = vec4<f32>(position, 0.0, 1.0);
+    output.Color = color;
+    return output;
+}
+
+@fragment
+fn frag_main(@location(0) Color : vec4<f32>) -> @location(0) vec4<f32> {
+    return Color;
+}
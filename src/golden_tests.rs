@@ -0,0 +1,263 @@
+//! Golden image tests. Every case renders a scene from `tests/golden` at a low resolution
+//! and compares it with the reference EXR of the same name. Sample `i` always uses the
+//! random numbers of frame `i`, so the renders are deterministic.
+//!
+//! The CPU reference path tracer always runs. The kernel runs too if wgpu finds an
+//! adapter, which can be a software one like llvmpipe or WARP. Set `REQUIRE_GPU` to fail
+//! instead of skipping the kernel when there is none.
+//!
+//! The furnace scene has no reference. Its solution is known, so it catches bias in both
+//! the path tracer and the references.
+//!
+//! After an intended change, write new references with `UPDATE_GOLDEN=1 cargo test golden`
+//! and look at them before committing. Failing cases leave the render and an image of
+//! the per pixel error in `target/golden`.
+
+use std::path::{Path, PathBuf};
+
+use cgmath::Rad;
+
+use crate::camera::Camera;
+use crate::cpu_path_tracer::CpuPathTracer;
+use crate::gpu_scene::GpuScene;
+use crate::path_tracer::PathTracer;
+use crate::pipeline_cache::PipelineCache;
+use crate::renderer::request_device;
+use crate::sampling::Sampler;
+use crate::scene::Scene;
+use crate::screenshot::HdrImage;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+const SAMPLES: u32 = 32;
+
+/// Limit for the mean of the per pixel relative error.
+const MAX_MEAN_ERROR: f32 = 0.02;
+/// Pixels with a larger relative error count as visibly different.
+const VISIBLE_ERROR: f32 = 0.1;
+/// Limit for the fraction of visibly different pixels.
+const MAX_VISIBLE_FRACTION: f32 = 0.02;
+
+struct Case {
+    name: &'static str,
+    scene: &'static str,
+    camera: fn() -> Camera,
+    sampler: Sampler,
+    max_radiance: Option<f32>,
+}
+
+const CASES: &[Case] = &[
+    Case { name: "lambertian", scene: "lambertian", camera: Camera::default, sampler: Sampler::Independent, max_radiance: None },
+    Case { name: "metal", scene: "metal", camera: Camera::default, sampler: Sampler::Independent, max_radiance: None },
+    Case { name: "dielectric", scene: "dielectric", camera: Camera::default, sampler: Sampler::Independent, max_radiance: None },
+    Case { name: "checkerboard", scene: "checkerboard", camera: Camera::default, sampler: Sampler::Independent, max_radiance: None },
+    Case { name: "textures", scene: "textures", camera: Camera::default, sampler: Sampler::Independent, max_radiance: None },
+    Case { name: "principled", scene: "principled", camera: Camera::default, sampler: Sampler::Independent, max_radiance: None },
+    Case { name: "mapped", scene: "mapped", camera: Camera::default, sampler: Sampler::Independent, max_radiance: None },
+    Case { name: "lights", scene: "lights", camera: Camera::default, sampler: Sampler::Independent, max_radiance: None },
+    Case { name: "motion", scene: "motion", camera: open_shutter, sampler: Sampler::Independent, max_radiance: None },
+    Case { name: "defocus", scene: "metal", camera: hexagonal_aperture, sampler: Sampler::Independent, max_radiance: None },
+    Case { name: "sobol", scene: "lights", camera: hexagonal_aperture, sampler: Sampler::Sobol, max_radiance: None },
+    Case { name: "blue_noise", scene: "lights", camera: hexagonal_aperture, sampler: Sampler::BlueNoise, max_radiance: None },
+    Case { name: "clamped", scene: "lights", camera: Camera::default, sampler: Sampler::Independent, max_radiance: Some(1.0) },
+];
+
+fn open_shutter() -> Camera {
+    let mut camera = Camera::default();
+    camera.shutter_close = 1.0;
+    camera
+}
+
+fn hexagonal_aperture() -> Camera {
+    let mut camera = Camera::default();
+    camera.aperture = 0.1;
+    camera.focus_distance = 2.0;
+    camera.aperture_blades = 6;
+    camera.aperture_rotation = Rad(0.3);
+    camera
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+fn load_scene(case: &Case) -> Scene {
+    let path = golden_dir().join(case.scene).with_extension("ron");
+    Scene::load(&path).unwrap_or_else(|err| panic!("failed to load {}: {}", path.display(), err))
+}
+
+fn size() -> winit::dpi::PhysicalSize<u32> {
+    winit::dpi::PhysicalSize::new(WIDTH, HEIGHT)
+}
+
+/// Relative error of a pixel, averaged over the channels. The offset keeps dark pixels
+/// from dominating.
+fn relative_error(actual: &[f32; 3], expected: &[f32; 3]) -> f32 {
+    let error: f32 = actual
+        .iter()
+        .zip(expected)
+        .map(|(a, e)| (a - e).abs() / (e.abs() + 0.01))
+        .sum();
+    // NaNs count as the largest error.
+    if error.is_nan() { f32::INFINITY } else { error / 3_f32 }
+}
+
+/// Compares a render with the reference of the case and returns a description of the
+/// failure, if any. Failing renders and their error images are written to `target/golden`.
+fn check(case: &Case, label: &str, image: &HdrImage) -> Result<(), String> {
+    let reference_path = golden_dir().join(case.name).with_extension("exr");
+    let reference = HdrImage::open_exr(&reference_path)
+        .map_err(|err| format!("{}: failed to open {}: {}", case.name, reference_path.display(), err))?;
+    if reference.size() != image.size() {
+        return Err(format!(
+            "{}: the reference is {}x{}, the render {}x{}",
+            case.name,
+            reference.size().width,
+            reference.size().height,
+            image.size().width,
+            image.size().height
+        ));
+    }
+
+    let errors: Vec<f32> = image
+        .pixels()
+        .iter()
+        .zip(reference.pixels())
+        .map(|(actual, expected)| relative_error(actual, expected))
+        .collect();
+    // Clamped so that a single NaN or firefly doesn't hide how many pixels differ.
+    let mean_error = errors.iter().map(|e| e.min(1_f32)).sum::<f32>() / errors.len() as f32;
+    let visible_fraction = errors.iter().filter(|e| **e > VISIBLE_ERROR).count() as f32 / errors.len() as f32;
+    if mean_error <= MAX_MEAN_ERROR && visible_fraction <= MAX_VISIBLE_FRACTION {
+        return Ok(());
+    }
+
+    let prefix = output_dir().join(format!("{}-{}", case.name, label));
+    let write_outputs = || -> Result<(), image::ImageError> {
+        std::fs::create_dir_all(output_dir())?;
+        image.save(&prefix, 0_f32)?;
+        // White pixels are visibly different.
+        let error_pixels = errors.iter().map(|e| (255_f32 * (e / VISIBLE_ERROR).min(1_f32)) as u8).collect();
+        image::GrayImage::from_raw(WIDTH, HEIGHT, error_pixels)
+            .expect("The pixel count matches the dimensions")
+            .save(prefix.with_extension("error.png"))
+    };
+    if let Err(err) = write_outputs() {
+        eprintln!("failed to write the outputs of {}: {}", case.name, err);
+    }
+
+    Err(format!(
+        "{}: mean relative error {:.4} (limit {}), {:.2}% of pixels visibly different (limit {}%), see {}.png",
+        case.name,
+        mean_error,
+        MAX_MEAN_ERROR,
+        100_f32 * visible_fraction,
+        100_f32 * MAX_VISIBLE_FRACTION,
+        prefix.display()
+    ))
+}
+
+/// Skips the GPU golden images, unless `REQUIRE_GPU` is set.
+fn skip_gpu(reason: &str) {
+    assert!(std::env::var_os("REQUIRE_GPU").is_none(), "REQUIRE_GPU is set, but {}", reason);
+    eprintln!("skipping the GPU golden images, {}", reason);
+}
+
+fn assert_all_pass(failures: Vec<String>) {
+    assert!(failures.is_empty(), "{} golden images differ:\n{}", failures.len(), failures.join("\n"));
+}
+
+#[test]
+fn golden_images_cpu() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+    for case in CASES {
+        let image = CpuPathTracer::new(&load_scene(case)).render_image(
+            &(case.camera)(),
+            size(),
+            SAMPLES,
+            case.sampler,
+            case.max_radiance,
+        );
+        if update {
+            let path = golden_dir().join(case.name).with_extension("exr");
+            image.save_exr(&path).expect("failed to write the reference");
+            continue;
+        }
+        if let Err(failure) = check(case, "cpu", &image) {
+            failures.push(failure);
+        }
+    }
+    assert_all_pass(failures);
+}
+
+#[test]
+fn furnace_cpu() {
+    let scene = Scene::load(golden_dir().join("furnace.ron")).expect("failed to load the furnace");
+    let camera = Camera::default();
+    let image = CpuPathTracer::new(&scene).render_image(&camera, size(), SAMPLES, Sampler::Independent, None);
+
+    // Only pixels that are entirely covered by one of the spheres are compared.
+    let aspect = WIDTH as f32 / HEIGHT as f32;
+    let material_at = |s: f32, t: f32| {
+        let (origin, direction) = camera.primary_ray(aspect, s, t);
+        scene.raycast(&origin, &direction, 0_f32).map(|hit| hit.material_idx)
+    };
+    let mut grey_sum = 0_f32;
+    let mut num_grey = 0;
+    for (idx, pixel) in image.pixels().iter().enumerate() {
+        let x = (idx as u32 % WIDTH) as f32;
+        let y = (idx as u32 / WIDTH) as f32;
+        let corners = [(x, y), (x + 1_f32, y), (x, y + 1_f32), (x + 1_f32, y + 1_f32)]
+            .map(|(x, y)| material_at(x / WIDTH as f32, y / HEIGHT as f32));
+        if corners.iter().all(|m| *m == Some(1)) {
+            assert_eq!(*pixel, [1_f32; 3], "the emitter at ({}, {}) isn't white", x, y);
+        } else if corners.iter().all(|m| *m == Some(0)) {
+            grey_sum += pixel.iter().sum::<f32>() / 3_f32;
+            num_grey += 1;
+        }
+    }
+
+    assert!(num_grey > 100, "the grey sphere covers only {} pixels", num_grey);
+    let mean = grey_sum / num_grey as f32;
+    assert!((mean - 0.5).abs() < 0.01, "the grey sphere has the mean radiance {}, not 0.5", mean);
+}
+
+#[test]
+fn golden_images_gpu() {
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        // The references come from the CPU path tracer.
+        return;
+    }
+
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let Some(adapter) = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())) else {
+        skip_gpu("no adapter was found");
+        return;
+    };
+    let (device, queue) = match pollster::block_on(request_device(&adapter, wgpu::Features::empty())) {
+        Ok(device) => device,
+        Err(err) => {
+            skip_gpu(&format!("no device was created: {}", err));
+            return;
+        }
+    };
+
+    let mut pipeline_cache = PipelineCache::new();
+    let mut failures = Vec::new();
+    for case in CASES {
+        let gpu_scene = GpuScene::new(&device, &load_scene(case));
+        let path_tracer = PathTracer::new(&device, &mut pipeline_cache, &gpu_scene);
+        let image = path_tracer
+            .render_image(&device, &queue, &gpu_scene, &(case.camera)(), size(), SAMPLES, case.sampler, case.max_radiance)
+            .expect("the golden images are small enough for any GPU");
+        if let Err(failure) = check(case, "gpu", &image) {
+            failures.push(failure);
+        }
+    }
+    assert_all_pass(failures);
+}
@@ -1,3 +1,6 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
 use wgpu::util::DeviceExt;
 
 pub struct UniformBuffer {
@@ -74,6 +77,25 @@ pub struct StorageBuffer {
 }
 
 impl StorageBuffer {
+    pub fn new(
+        device: &wgpu::Device,
+        buffer_size: wgpu::BufferAddress,
+        binding_idx: u32,
+        label: Option<&str>,
+    ) -> Self {
+        let handle = device.create_buffer(&wgpu::BufferDescriptor {
+            size: buffer_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+            label,
+        });
+
+        Self {
+            handle,
+            binding_idx,
+        }
+    }
+
     pub fn new_from_bytes(
         device: &wgpu::Device,
         bytes: &[u8],
@@ -120,3 +142,96 @@ impl StorageBuffer {
         }
     }
 }
+
+/// Copies a small buffer back to the CPU without stalling the frame. Only one copy is in
+/// flight at a time, frames that finish while it is mapped are skipped.
+pub struct AsyncReadback {
+    buffer: wgpu::Buffer,
+    state: ReadbackState,
+}
+
+enum ReadbackState {
+    Idle,
+    Copied,
+    Mapping(Arc<AtomicBool>),
+}
+
+impl AsyncReadback {
+    pub fn new(device: &wgpu::Device, buffer_size: wgpu::BufferAddress, label: Option<&str>) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            size: buffer_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            label,
+        });
+
+        Self {
+            buffer,
+            state: ReadbackState::Idle,
+        }
+    }
+
+    /// Records a copy of `source` unless the previous copy hasn't been read yet.
+    pub fn copy_from(&mut self, encoder: &mut wgpu::CommandEncoder, source: &wgpu::Buffer) {
+        if let ReadbackState::Idle = self.state {
+            encoder.copy_buffer_to_buffer(source, 0, &self.buffer, 0, self.buffer.size());
+            self.state = ReadbackState::Copied;
+        }
+    }
+
+    /// Starts mapping the buffer. Call this after submitting the copy.
+    pub fn map(&mut self) {
+        if let ReadbackState::Copied = self.state {
+            let mapped = Arc::new(AtomicBool::new(false));
+            let flag = mapped.clone();
+            self.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    flag.store(true, Ordering::Release);
+                }
+            });
+            self.state = ReadbackState::Mapping(mapped);
+        }
+    }
+
+    /// Returns the contents of the last copy once it is mapped.
+    pub fn try_read<T: bytemuck::Pod>(&mut self, device: &wgpu::Device) -> Option<Vec<T>> {
+        let ReadbackState::Mapping(mapped) = &self.state else {
+            return None;
+        };
+        device.poll(wgpu::Maintain::Poll);
+        if !mapped.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let data = bytemuck::cast_slice(&self.buffer.slice(..).get_mapped_range()).to_vec();
+        self.buffer.unmap();
+        self.state = ReadbackState::Idle;
+        Some(data)
+    }
+}
+
+/// Copies a buffer back to the CPU and waits for it. For exports, not for every frame.
+pub fn read_buffer<T: bytemuck::Pod>(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<T> {
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        size: buffer.size(),
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+        label: Some("readback buffer"),
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, buffer.size());
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = staging_buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| {
+        result.expect("Failed to map the readback buffer");
+    });
+    device.poll(wgpu::Maintain::Wait);
+
+    let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    staging_buffer.unmap();
+    data
+}
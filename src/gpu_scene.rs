@@ -0,0 +1,151 @@
+use crate::gpu_buffer::StorageBuffer;
+use crate::scene::Scene;
+use crate::sphere::Sphere;
+
+/// The scene storage buffers the kernel reads in bind group 1.
+pub struct GpuScene {
+    sphere_buffer: StorageBuffer,
+    material_buffer: StorageBuffer,
+    texture_buffer: StorageBuffer,
+    light_buffer: StorageBuffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GpuScene {
+    pub fn new(device: &wgpu::Device, scene: &Scene) -> Self {
+        let [sphere_buffer, material_buffer, texture_buffer, light_buffer] =
+            Self::create_buffers(device, scene);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                sphere_buffer.layout(wgpu::ShaderStages::COMPUTE, true),
+                material_buffer.layout(wgpu::ShaderStages::COMPUTE, true),
+                texture_buffer.layout(wgpu::ShaderStages::COMPUTE, true),
+                light_buffer.layout(wgpu::ShaderStages::COMPUTE, true),
+            ],
+            label: Some("scene layout"),
+        });
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            [&sphere_buffer, &material_buffer, &texture_buffer, &light_buffer],
+        );
+
+        Self {
+            sphere_buffer,
+            material_buffer,
+            texture_buffer,
+            light_buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Uploads the scene again. The buffers are written in place if their sizes didn't
+    /// change, which is the common case while dragging a value in the editor.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, scene: &Scene) {
+        let (materials, textures) = scene.gpu_materials();
+        let lights = scene.light_indices();
+        let contents: [&[u8]; 4] = [
+            bytemuck::cast_slice(scene.spheres.as_slice()),
+            bytemuck::cast_slice(materials.as_slice()),
+            bytemuck::cast_slice(textures.as_slice()),
+            bytemuck::cast_slice(lights.as_slice()),
+        ];
+        let buffers = [
+            &self.sphere_buffer,
+            &self.material_buffer,
+            &self.texture_buffer,
+            &self.light_buffer,
+        ];
+
+        let same_size = buffers
+            .iter()
+            .zip(contents.iter())
+            .all(|(buffer, bytes)| buffer.handle().size() == bytes.len() as wgpu::BufferAddress);
+
+        if same_size {
+            for (buffer, bytes) in buffers.iter().zip(contents.iter()) {
+                queue.write_buffer(buffer.handle(), 0, bytes);
+            }
+        } else {
+            let [sphere_buffer, material_buffer, texture_buffer, light_buffer] =
+                Self::create_buffers(device, scene);
+            self.bind_group = Self::create_bind_group(
+                device,
+                &self.bind_group_layout,
+                [&sphere_buffer, &material_buffer, &texture_buffer, &light_buffer],
+            );
+            self.sphere_buffer = sphere_buffer;
+            self.material_buffer = material_buffer;
+            self.texture_buffer = texture_buffer;
+            self.light_buffer = light_buffer;
+        }
+    }
+
+    /// Uploads a single sphere, e.g. while it is dragged around in the viewport.
+    pub fn update_sphere(&self, queue: &wgpu::Queue, scene: &Scene, idx: usize) {
+        queue.write_buffer(
+            self.sphere_buffer.handle(),
+            (idx * std::mem::size_of::<Sphere>()) as wgpu::BufferAddress,
+            bytemuck::bytes_of(&scene.spheres[idx]),
+        );
+    }
+
+    fn create_buffers(device: &wgpu::Device, scene: &Scene) -> [StorageBuffer; 4] {
+        let (materials, textures) = scene.gpu_materials();
+
+        [
+            StorageBuffer::new_from_bytes(
+                device,
+                bytemuck::cast_slice(scene.spheres.as_slice()),
+                0_u32,
+                Some("scene buffer"),
+            ),
+            StorageBuffer::new_from_bytes(
+                device,
+                bytemuck::cast_slice(materials.as_slice()),
+                1_u32,
+                Some("materials buffer"),
+            ),
+            StorageBuffer::new_from_bytes(
+                device,
+                bytemuck::cast_slice(textures.as_slice()),
+                2_u32,
+                Some("textures buffer"),
+            ),
+            StorageBuffer::new_from_bytes(
+                device,
+                bytemuck::cast_slice(scene.light_indices().as_slice()),
+                3_u32,
+                Some("lights buffer"),
+            ),
+        ]
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffers: [&StorageBuffer; 4],
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                buffers[0].binding(),
+                buffers[1].binding(),
+                buffers[2].binding(),
+                buffers[3].binding(),
+            ],
+            label: Some("scene bind group"),
+        })
+    }
+}
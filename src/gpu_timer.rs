@@ -0,0 +1,89 @@
+use crate::gpu_buffer::AsyncReadback;
+
+/// The passes that are timed on the GPU.
+#[derive(Clone, Copy)]
+pub enum TimedPass {
+    RayTracing = 0,
+    Screen = 1,
+    Denoise = 2,
+}
+
+/// GPU durations of the timed passes in seconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GpuTimings {
+    pub ray_tracing: f32,
+    pub screen: f32,
+    pub denoise: f32,
+}
+
+/// Measures passes with timestamp queries written before and after them. Only available
+/// when the device supports `Features::TIMESTAMP_QUERY`.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback: AsyncReadback,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+}
+
+impl GpuTimer {
+    const NUM_QUERIES: u32 = 6;
+
+    /// Returns `None` if timestamp queries aren't enabled on the device.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Pass Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: Self::NUM_QUERIES,
+        });
+        let size = (Self::NUM_QUERIES as usize * std::mem::size_of::<u64>()) as wgpu::BufferAddress;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+            label: Some("timestamp resolve buffer"),
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback: AsyncReadback::new(device, size, Some("timestamp readback buffer")),
+            period: queue.get_timestamp_period(),
+        })
+    }
+
+    pub fn begin(&self, encoder: &mut wgpu::CommandEncoder, pass: TimedPass) {
+        encoder.write_timestamp(&self.query_set, 2 * pass as u32);
+    }
+
+    pub fn end(&self, encoder: &mut wgpu::CommandEncoder, pass: TimedPass) {
+        encoder.write_timestamp(&self.query_set, 2 * pass as u32 + 1);
+    }
+
+    /// Resolves the timestamps of this frame. Call after the passes were recorded.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(&self.query_set, 0..Self::NUM_QUERIES, &self.resolve_buffer, 0);
+        self.readback.copy_from(encoder, &self.resolve_buffer);
+    }
+
+    /// Call after submitting the frame.
+    pub fn map(&mut self) {
+        self.readback.map();
+    }
+
+    /// Returns the timings of an earlier frame once they arrived.
+    pub fn try_read(&mut self, device: &wgpu::Device) -> Option<GpuTimings> {
+        let timestamps = self.readback.try_read::<u64>(device)?;
+        let seconds = |begin: u64, end: u64| end.wrapping_sub(begin) as f32 * self.period * 1e-9;
+
+        Some(GpuTimings {
+            ray_tracing: seconds(timestamps[0], timestamps[1]),
+            screen: seconds(timestamps[2], timestamps[3]),
+            denoise: seconds(timestamps[4], timestamps[5]),
+        })
+    }
+}
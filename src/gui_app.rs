@@ -1,11 +1,169 @@
-pub struct GuiApp {}
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::aov::AovChannel;
+use crate::camera::Camera;
+use crate::camera_path::{CameraPath, Interpolation, Keyframe};
+use crate::denoiser::DenoiseSettings;
+use crate::gizmo::{Gizmo, GizmoMode};
+use crate::gpu_timer::GpuTimings;
+use crate::path_tracer::DebugMode;
+use crate::sampling::Sampler;
+use crate::scene::{Material, Scene, Texture};
+use crate::sphere::Sphere;
+
+/// Everything the statistics overlay shows.
+pub struct FrameStats {
+    pub fps: f32,
+    pub frame_time: f32,
+    /// The most recent frame times in seconds, oldest first.
+    pub frame_times: Vec<f32>,
+    /// `None` if the device doesn't support timestamp queries.
+    pub gpu_timings: Option<GpuTimings>,
+    pub samples_per_pixel: u32,
+    pub rays_per_second: Option<f32>,
+    /// Average number of ray segments per path.
+    pub average_path_length: Option<f32>,
+    /// NaN or infinite samples since the accumulation restarted.
+    pub discarded_samples: Option<u32>,
+    /// Samples clamped to the maximum radiance, `None` if nothing is clamped.
+    pub clamped_samples: Option<u32>,
+}
+
+/// What the user changed in the last frame.
+#[derive(Default)]
+pub struct GuiChanges {
+    pub camera: bool,
+    pub scene: bool,
+    /// Where the user asked to save the scene to.
+    pub save_to: Option<PathBuf>,
+    pub screenshot: bool,
+    /// Render an offscreen still with `still_scale` and `still_samples`.
+    pub render_still: bool,
+    /// Render the camera path to an image sequence with the `sequence_*` settings.
+    pub render_sequence: bool,
+    pub cancel_sequence: bool,
+    /// Reconfigure the surface with `present_mode`.
+    pub present_mode: bool,
+    /// Restart the accumulation with the new `sampler`.
+    pub sampler: bool,
+    /// Show `aov_channel` in the viewport.
+    pub aov_channel: bool,
+    /// Restart the accumulation with the new `debug_mode`.
+    pub debug_mode: bool,
+    /// Restart the accumulation with the new `max_radiance`.
+    pub max_radiance: bool,
+    /// Render an offscreen still like `render_still` and save it with its AOVs.
+    pub export_aovs: bool,
+}
+
+pub struct GuiApp {
+    /// When set, the next click in the viewport picks the focus distance.
+    pub pick_focus: bool,
+    /// The error from the last failed scene reload, if any.
+    pub scene_error: Option<String>,
+    /// The compile error from the last failed kernel reload, if any.
+    pub shader_error: Option<String>,
+    /// Why the edited scene can't be uploaded, if it can't.
+    pub edit_error: Option<String>,
+    /// The error from the last failed save, if any.
+    pub save_error: Option<String>,
+    /// The path typed into the "Save As" prompt while it is open.
+    save_as_path: Option<String>,
+    /// The sphere picked in the viewport, shown in the inspector and outlined.
+    pub selected_sphere: Option<usize>,
+    /// The selection the inspector last scrolled to.
+    shown_selection: Option<usize>,
+    /// The handles for dragging the selected sphere around.
+    pub gizmo: Gizmo,
+    /// Resolution of offscreen stills as a multiple of the window size.
+    pub still_scale: u32,
+    pub still_samples: u32,
+    /// Exposure of saved PNGs in stops.
+    pub exposure: f32,
+    /// Where the last screenshot was saved to, or why it failed.
+    pub export_status: Option<Result<String, String>>,
+    pub camera_path: CameraPath,
+    /// The time on the camera path the camera is at.
+    pub playhead: f32,
+    /// Whether the playhead advances in real time.
+    pub playing: bool,
+    pub sequence_fps: f32,
+    pub sequence_samples: u32,
+    pub sequence_dir: String,
+    /// The number of frames rendered and the total while a sequence is being rendered.
+    pub sequence_progress: Option<(u32, u32)>,
+    /// How the last sequence render ended.
+    pub sequence_status: Option<Result<String, String>>,
+    /// The present modes that can be picked in the display settings.
+    pub present_modes: Vec<wgpu::PresentMode>,
+    pub present_mode: wgpu::PresentMode,
+    /// Upper limit for the frame rate of the window, if any.
+    pub max_fps: Option<u32>,
+    /// Accumulation stops once this many samples per pixel were traced.
+    pub max_samples: Option<u32>,
+    /// Stop drawing frames once the image converged and nothing changes.
+    pub on_demand: bool,
+    pub sampler: Sampler,
+    /// Show the denoised image in the viewport and denoise stills.
+    pub denoise: bool,
+    pub denoise_settings: DenoiseSettings,
+    /// What the viewport shows.
+    pub aov_channel: AovChannel,
+    /// What the kernel writes into the image instead of the radiance, if anything.
+    pub debug_mode: DebugMode,
+    /// The brightest component of a sample is clamped to this, if set.
+    pub max_radiance: Option<f32>,
+}
 
 impl GuiApp {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            pick_focus: false,
+            scene_error: None,
+            shader_error: None,
+            edit_error: None,
+            save_error: None,
+            save_as_path: None,
+            selected_sphere: None,
+            shown_selection: None,
+            gizmo: Gizmo::new(),
+            still_scale: 2,
+            still_samples: 256,
+            exposure: 0.0,
+            export_status: None,
+            camera_path: CameraPath::new(),
+            playhead: 0.0,
+            playing: false,
+            sequence_fps: 30.0,
+            sequence_samples: 64,
+            sequence_dir: "renders/sequence".to_string(),
+            sequence_progress: None,
+            sequence_status: None,
+            present_modes: vec![wgpu::PresentMode::AutoVsync, wgpu::PresentMode::AutoNoVsync],
+            present_mode: wgpu::PresentMode::AutoVsync,
+            max_fps: None,
+            max_samples: None,
+            on_demand: false,
+            sampler: Sampler::default(),
+            denoise: false,
+            denoise_settings: DenoiseSettings::default(),
+            aov_channel: AovChannel::default(),
+            debug_mode: DebugMode::default(),
+            max_radiance: None,
+        }
     }
 
-    pub fn ui(&mut self, ctx: &egui::Context, fps: f32, frame_time: f32) {
+    pub fn ui(
+        &mut self,
+        ctx: &egui::Context,
+        stats: &FrameStats,
+        camera: &mut Camera,
+        scene: &mut Scene,
+        scene_path: &Path,
+    ) -> GuiChanges {
         egui::Window::new("FPS")
         .anchor(egui::Align2::LEFT_TOP, egui::Vec2::new(10.0, 10.0))
         .title_bar(false)
@@ -14,8 +172,820 @@ impl GuiApp {
         .collapsible(false)
         .frame(egui::Frame::none())
         .show(ctx, |ui| {
-            ui.label(format!("FPS: {:.2}", fps));
-            ui.label(format!("Frame Time: {:.2} ms", frame_time * 1000.0));
+            ui.label(format!("FPS: {:.2}", stats.fps));
+            ui.label(format!("Frame Time: {:.2} ms", stats.frame_time * 1000.0));
+            match stats.gpu_timings {
+                Some(timings) => {
+                    ui.label(format!("Ray Tracing: {:.2} ms", timings.ray_tracing * 1000.0));
+                    ui.label(format!("Screen: {:.2} ms", timings.screen * 1000.0));
+                    if self.denoise {
+                        ui.label(format!("Denoise: {:.2} ms", timings.denoise * 1000.0));
+                    }
+                }
+                None => {
+                    ui.label("GPU timings unavailable");
+                }
+            }
+            ui.label(format!("Samples: {} spp", stats.samples_per_pixel));
+            if let Some(rays_per_second) = stats.rays_per_second {
+                ui.label(format!("Rays: {:.1} M/s", rays_per_second * 1e-6));
+            }
+            if let Some(path_length) = stats.average_path_length {
+                ui.label(format!("Path Length: {:.2}", path_length));
+            }
+            if let Some(discarded) = stats.discarded_samples.filter(|&discarded| discarded > 0) {
+                ui.colored_label(egui::Color32::RED, format!("NaN/Inf samples: {}", discarded));
+            }
+            if let Some(clamped) = stats.clamped_samples {
+                ui.label(format!("Clamped samples: {}", clamped));
+            }
+
+            let frame_times_ms: Vec<f32> = stats.frame_times.iter().map(|t| t * 1000.0).collect();
+            egui::plot::Plot::new("frame_times")
+            .width(200.0)
+            .height(60.0)
+            .show_axes([false, true])
+            .include_y(0.0)
+            .show_background(false)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .allow_boxed_zoom(false)
+            .allow_double_click_reset(false)
+            .show(ui, |plot_ui| {
+                plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from_ys_f32(&frame_times_ms)).name("ms"));
+            });
+        });
+
+        if let Some(error) = &self.scene_error {
+            egui::Window::new("Scene error")
+            .anchor(egui::Align2::CENTER_BOTTOM, egui::Vec2::new(0.0, -10.0))
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.colored_label(egui::Color32::RED, error);
+                ui.label("Showing the last scene that loaded successfully.");
+            });
+        }
+
+        if let Some(error) = &self.shader_error {
+            egui::Window::new("Shader error")
+            .anchor(egui::Align2::LEFT_BOTTOM, egui::Vec2::new(10.0, -10.0))
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label("The previous kernel keeps running until this is fixed.");
+                egui::ScrollArea::both().max_height(300.0).show(ui, |ui| {
+                    ui.label(egui::RichText::new(error).monospace().color(egui::Color32::RED));
+                });
+            });
+        }
+
+        let mut changes = GuiChanges::default();
+
+        egui::Window::new("Export")
+        .anchor(egui::Align2::LEFT_TOP, egui::Vec2::new(10.0, 260.0))
+        .resizable(false)
+        .default_open(false)
+        .show(ctx, |ui| {
+            changes.screenshot = ui
+                .button("Screenshot")
+                .on_hover_text("Saves the samples accumulated so far (F12)")
+                .clicked();
+            ui.separator();
+
+            egui::Grid::new("export_grid").num_columns(2).show(ui, |ui| {
+                ui.label("Resolution");
+                ui.add(egui::Slider::new(&mut self.still_scale, 1..=8).suffix("×"));
+                ui.end_row();
+
+                ui.label("Samples per pixel");
+                ui.add(egui::DragValue::new(&mut self.still_samples).clamp_range(1..=65536));
+                ui.end_row();
+
+                ui.label("PNG exposure");
+                ui.add(egui::DragValue::new(&mut self.exposure).speed(0.05).suffix(" EV"))
+                    .on_hover_text("Scales the radiance by 2^EV before tone mapping");
+                ui.end_row();
+            });
+            ui.horizontal(|ui| {
+                changes.render_still = ui
+                    .button("Render still")
+                    .on_hover_text("Renders the current view offscreen, independent of the window size")
+                    .clicked();
+                changes.export_aovs = ui
+                    .button("Export AOVs")
+                    .on_hover_text("Renders a still and saves it with albedo, normal, position, depth and ids as a multilayer EXR")
+                    .clicked();
+            });
+
+            status_label(ui, &self.export_status);
+        });
+
+        egui::Window::new("Display")
+        .anchor(egui::Align2::LEFT_BOTTOM, egui::Vec2::new(10.0, -10.0))
+        .resizable(false)
+        .default_open(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("display_grid").num_columns(2).show(ui, |ui| {
+                ui.label("View");
+                egui::ComboBox::from_id_source("aov_channel")
+                .selected_text(format!("{:?}", self.aov_channel))
+                .show_ui(ui, |ui| {
+                    for channel in AovChannel::ALL {
+                        changes.aov_channel |= ui
+                            .selectable_value(&mut self.aov_channel, channel, format!("{:?}", channel))
+                            .changed();
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Debug mode");
+                egui::ComboBox::from_id_source("debug_mode")
+                .selected_text(format!("{:?}", self.debug_mode))
+                .show_ui(ui, |ui| {
+                    for mode in DebugMode::ALL {
+                        changes.debug_mode |= ui
+                            .selectable_value(&mut self.debug_mode, mode, format!("{:?}", mode))
+                            .changed();
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Present mode");
+                egui::ComboBox::from_id_source("present_mode")
+                .selected_text(format!("{:?}", self.present_mode))
+                .show_ui(ui, |ui| {
+                    for &mode in &self.present_modes {
+                        changes.present_mode |= ui
+                            .selectable_value(&mut self.present_mode, mode, format!("{:?}", mode))
+                            .changed();
+                    }
+                });
+                ui.end_row();
+
+                limit_ui(ui, "Frame rate cap", &mut self.max_fps, 60, 1..=1000);
+                ui.end_row();
+
+                limit_ui(ui, "Sample limit", &mut self.max_samples, 1024, 1..=1 << 20);
+                ui.end_row();
+
+                changes.max_radiance |= limit_ui(ui, "Clamp radiance", &mut self.max_radiance, 10.0, 0.01..=1e4);
+                ui.end_row();
+
+                ui.label("Sampler");
+                egui::ComboBox::from_id_source("sampler")
+                .selected_text(format!("{:?}", self.sampler))
+                .show_ui(ui, |ui| {
+                    for &sampler in Sampler::value_variants() {
+                        changes.sampler |= ui
+                            .selectable_value(&mut self.sampler, sampler, format!("{:?}", sampler))
+                            .changed();
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Denoise");
+                ui.checkbox(&mut self.denoise, "")
+                    .on_hover_text("Filters the accumulated image, guided by the albedo, normal and depth of the first hit");
+                ui.end_row();
+
+                ui.label("Iterations");
+                ui.add_enabled(
+                    self.denoise,
+                    egui::Slider::new(&mut self.denoise_settings.iterations, 0..=DenoiseSettings::MAX_ITERATIONS),
+                );
+                ui.end_row();
+
+                ui.label("Luminance sigma");
+                ui.add_enabled(
+                    self.denoise,
+                    egui::Slider::new(&mut self.denoise_settings.sigma_luminance, 0.1..=100.0).logarithmic(true),
+                );
+                ui.end_row();
+
+                ui.label("Normal sigma");
+                ui.add_enabled(
+                    self.denoise,
+                    egui::Slider::new(&mut self.denoise_settings.sigma_normal, 1.0..=1024.0).logarithmic(true),
+                );
+                ui.end_row();
+
+                ui.label("Depth sigma");
+                ui.add_enabled(
+                    self.denoise,
+                    egui::Slider::new(&mut self.denoise_settings.sigma_depth, 0.001..=10.0).logarithmic(true),
+                );
+                ui.end_row();
+            });
+            ui.checkbox(&mut self.on_demand, "Render only when dirty")
+                .on_hover_text("Stops drawing once the sample limit is reached, until something changes");
         });
+
+        let mut camera_changed = false;
+
+        egui::Window::new("Camera")
+        .anchor(egui::Align2::RIGHT_TOP, egui::Vec2::new(-10.0, 10.0))
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("camera_grid").num_columns(2).show(ui, |ui| {
+                ui.label("Field of view");
+                camera_changed |= ui.drag_angle(&mut camera.vfov.0).changed();
+                ui.end_row();
+
+                ui.label("Aperture");
+                camera_changed |= ui
+                    .add(egui::Slider::new(&mut camera.aperture, 0.0..=1.0).logarithmic(true))
+                    .changed();
+                ui.end_row();
+
+                ui.label("Focus distance");
+                camera_changed |= ui
+                    .add(egui::DragValue::new(&mut camera.focus_distance).speed(0.05).clamp_range(0.01..=1000.0))
+                    .changed();
+                ui.end_row();
+
+                ui.label("Blades");
+                camera_changed |= ui
+                    .add(egui::Slider::new(&mut camera.aperture_blades, 0..=12))
+                    .on_hover_text("Less than three blades gives a circular aperture")
+                    .changed();
+                ui.end_row();
+
+                ui.label("Blade rotation");
+                camera_changed |= ui.drag_angle(&mut camera.aperture_rotation.0).changed();
+                ui.end_row();
+
+                ui.label("Shutter open");
+                camera_changed |= ui
+                    .add(egui::DragValue::new(&mut camera.shutter_open).speed(0.01).clamp_range(f32::MIN..=camera.shutter_close))
+                    .changed();
+                ui.end_row();
+
+                ui.label("Shutter close");
+                camera_changed |= ui
+                    .add(egui::DragValue::new(&mut camera.shutter_close).speed(0.01).clamp_range(camera.shutter_open..=f32::MAX))
+                    .changed();
+                ui.end_row();
+            });
+
+            ui.toggle_value(&mut self.pick_focus, "Click to focus")
+                .on_hover_text("Click on an object in the viewport to focus on it");
+        });
+        changes.camera = camera_changed;
+
+        egui::Window::new("Camera path")
+        .anchor(egui::Align2::CENTER_TOP, egui::Vec2::new(0.0, 10.0))
+        .default_open(false)
+        .show(ctx, |ui| {
+            changes.camera |= self.camera_path_ui(ui, camera, &mut changes);
+        });
+
+        egui::Window::new("Scene")
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::Vec2::new(-10.0, -10.0))
+        .show(ctx, |ui| {
+            changes.save_to = self.save_ui(ui, scene_path);
+
+            if let Some(error) = &self.edit_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+
+            if let Some(idx) = self.selected_sphere {
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "Selected sphere {} with material {}",
+                        idx, scene.spheres[idx].material_idx
+                    ));
+                    if ui.button("Deselect").clicked() {
+                        self.selected_sphere = None;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Gizmo");
+                    ui.selectable_value(&mut self.gizmo.mode, GizmoMode::Translate, "Translate");
+                    ui.selectable_value(&mut self.gizmo.mode, GizmoMode::Scale, "Scale");
+                });
+            }
+
+            // Open and scroll to the selected sphere and its material once per selection.
+            let reveal = self.selected_sphere.filter(|_| self.selected_sphere != self.shown_selection);
+            self.shown_selection = self.selected_sphere;
+            let reveal_material = reveal.map(|idx| scene.spheres[idx].material_idx as usize);
+
+            egui::ScrollArea::vertical().max_height(500.0).show(ui, |ui| {
+                changes.scene |= self.spheres_ui(ui, scene, camera, reveal);
+                ui.separator();
+                changes.scene |= Self::materials_ui(ui, scene, reveal_material);
+            });
+        });
+
+        changes
     }
-}
\ No newline at end of file
+
+    /// Shows the keyframes, the playhead and the sequence export. Returns true if the
+    /// camera was moved to the path.
+    fn camera_path_ui(&mut self, ui: &mut egui::Ui, camera: &mut Camera, changes: &mut GuiChanges) -> bool {
+        let mut camera_changed = false;
+        let mut path_changed = false;
+
+        ui.horizontal(|ui| {
+            ui.label("Interpolation");
+            path_changed |= ui
+                .selectable_value(&mut self.camera_path.interpolation, Interpolation::CatmullRom, "Catmull-Rom")
+                .changed();
+            path_changed |= ui
+                .selectable_value(&mut self.camera_path.interpolation, Interpolation::Bezier, "Bézier")
+                .changed();
+        });
+
+        ui.horizontal(|ui| {
+            let play_label = if self.playing { "Pause" } else { "Play" };
+            if ui.add_enabled(self.camera_path.keyframes().len() > 1, egui::Button::new(play_label)).clicked() {
+                self.playing = !self.playing;
+                if self.playing && self.playhead >= self.camera_path.duration() {
+                    self.playhead = 0.0;
+                }
+            }
+            let duration = self.camera_path.duration().max(f32::EPSILON);
+            path_changed |= ui
+                .add(egui::Slider::new(&mut self.playhead, 0.0..=duration).suffix(" s"))
+                .changed();
+        });
+
+        if ui.button("Add keyframe").on_hover_text("Adds the current view at the playhead").clicked() {
+            self.camera_path.insert(Keyframe::from_camera(camera, self.playhead));
+        }
+
+        let mut deleted = None;
+        let mut retimed = None;
+        egui::Grid::new("keyframe_grid").num_columns(3).show(ui, |ui| {
+            for (idx, keyframe) in self.camera_path.keyframes().iter().enumerate() {
+                let mut time = keyframe.time;
+                if ui
+                    .add(egui::DragValue::new(&mut time).speed(0.05).clamp_range(0.0..=f32::MAX).suffix(" s"))
+                    .changed()
+                {
+                    retimed = Some((idx, time));
+                }
+                if ui.button("View").clicked() {
+                    self.playhead = keyframe.time;
+                    path_changed = true;
+                }
+                if ui.button("Delete").clicked() {
+                    deleted = Some(idx);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some((idx, time)) = retimed {
+            self.camera_path.set_time(idx, time);
+        }
+        if let Some(idx) = deleted {
+            self.camera_path.remove(idx);
+        }
+
+        if path_changed {
+            if let Some(keyframe) = self.camera_path.sample(self.playhead) {
+                keyframe.apply(camera);
+                camera_changed = true;
+            }
+        }
+
+        ui.separator();
+        match self.sequence_progress {
+            Some((done, total)) => {
+                ui.add(egui::ProgressBar::new(done as f32 / total as f32).text(format!("Frame {} of {}", done, total)));
+                changes.cancel_sequence = ui.button("Cancel").clicked();
+            }
+            None => {
+                egui::Grid::new("sequence_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Frames per second");
+                    ui.add(egui::DragValue::new(&mut self.sequence_fps).clamp_range(1.0..=240.0));
+                    ui.end_row();
+
+                    ui.label("Samples per pixel");
+                    ui.add(egui::DragValue::new(&mut self.sequence_samples).clamp_range(1..=65536));
+                    ui.end_row();
+
+                    ui.label("Output directory");
+                    ui.text_edit_singleline(&mut self.sequence_dir);
+                    ui.end_row();
+                });
+                changes.render_sequence = ui
+                    .add_enabled(self.camera_path.keyframes().len() > 1, egui::Button::new("Render sequence"))
+                    .on_hover_text("Renders every frame of the path to numbered PNG files")
+                    .clicked();
+                status_label(ui, &self.sequence_status);
+            }
+        }
+
+        camera_changed
+    }
+
+    /// Shows the save buttons. Returns the path to save to if one was clicked.
+    fn save_ui(&mut self, ui: &mut egui::Ui, scene_path: &Path) -> Option<PathBuf> {
+        let mut save_to = None;
+
+        ui.horizontal(|ui| {
+            if ui.button("Save").on_hover_text(scene_path.display().to_string()).clicked() {
+                save_to = Some(scene_path.to_path_buf());
+            }
+            if ui.button("Save As…").clicked() {
+                self.save_as_path = Some(scene_path.display().to_string());
+            }
+        });
+
+        let mut close_prompt = false;
+        if let Some(path) = &mut self.save_as_path {
+            ui.horizontal(|ui| {
+                let response = ui.text_edit_singleline(path);
+                let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if ui.button("Save").clicked() || entered {
+                    save_to = Some(PathBuf::from(path.as_str()));
+                }
+                close_prompt = ui.button("Cancel").clicked();
+            });
+        }
+        if close_prompt || save_to.is_some() {
+            self.save_as_path = None;
+        }
+
+        if let Some(error) = &self.save_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        save_to
+    }
+
+    fn spheres_ui(&mut self, ui: &mut egui::Ui, scene: &mut Scene, camera: &Camera, reveal: Option<usize>) -> bool {
+        let mut changed = false;
+        let mut deleted = None;
+        let num_spheres = scene.spheres.len();
+        let max_material_idx = scene.materials.len() as u32 - 1;
+
+        ui.heading("Spheres");
+        for (idx, sphere) in scene.spheres.iter_mut().enumerate() {
+            let mut title = egui::RichText::new(format!("Sphere {}", idx));
+            if self.selected_sphere == Some(idx) {
+                title = title.strong().color(egui::Color32::from_rgb(255, 165, 0));
+            }
+
+            let header = egui::CollapsingHeader::new(title)
+            .id_source(("sphere", idx))
+            .open((reveal == Some(idx)).then_some(true))
+            .show(ui, |ui| {
+                egui::Grid::new(("sphere_grid", idx)).num_columns(2).show(ui, |ui| {
+                    ui.label("Center");
+                    changed |= vec3_ui(ui, &mut sphere.center.as_mut_slice()[..3]);
+                    ui.end_row();
+
+                    ui.label("Radius");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut sphere.radius).speed(0.01).clamp_range(0.001..=f32::MAX))
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Material");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut sphere.material_idx).clamp_range(0..=max_material_idx))
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Velocity");
+                    changed |= vec3_ui(ui, &mut sphere.velocity.as_mut_slice()[..3]);
+                    ui.end_row();
+
+                    ui.label("Spin");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut sphere.velocity.w).speed(0.01))
+                        .on_hover_text("Angular velocity around the y axis in radians per unit of time")
+                        .changed();
+                    ui.end_row();
+                });
+
+                if ui.add_enabled(num_spheres > 1, egui::Button::new("Delete")).clicked() {
+                    deleted = Some(idx);
+                }
+            });
+            if reveal == Some(idx) {
+                header.header_response.scroll_to_me(Some(egui::Align::TOP));
+            }
+        }
+
+        if let Some(idx) = deleted {
+            scene.spheres.remove(idx);
+            self.selected_sphere = match self.selected_sphere {
+                Some(selected) if selected == idx => None,
+                Some(selected) if selected > idx => Some(selected - 1),
+                selected => selected,
+            };
+            self.shown_selection = self.selected_sphere;
+            changed = true;
+        }
+
+        if ui.button("Add sphere").on_hover_text("Adds a sphere in front of the camera").clicked() {
+            let forward = camera.forward();
+            let center = glm::vec3(camera.position.x, camera.position.y, camera.position.z)
+                + 3.0 * glm::vec3(forward.x, forward.y, forward.z);
+            scene.spheres.push(Sphere::new(center, 0.5, 0));
+            changed = true;
+        }
+
+        changed
+    }
+
+    /// The rows of the material's grid.
+    fn material_ui(ui: &mut egui::Ui, material: &mut Material) -> bool {
+        let mut changed = false;
+        match material {
+            Material::Lambertian { albedo } => {
+                ui.label("Albedo");
+                changed |= texture_ui(ui, albedo);
+                ui.end_row();
+            }
+            Material::Metal { albedo, fuzz } => {
+                ui.label("Albedo");
+                changed |= texture_ui(ui, albedo);
+                ui.end_row();
+
+                ui.label("Fuzz");
+                changed |= ui.add(egui::Slider::new(fuzz, 0.0..=1.0)).changed();
+                ui.end_row();
+            }
+            Material::Dielectric { refraction_index } => {
+                ui.label("IOR");
+                changed |= ui
+                    .add(egui::DragValue::new(refraction_index).speed(0.01).clamp_range(1.0..=4.0))
+                    .changed();
+                ui.end_row();
+            }
+            Material::Checkerboard { even, odd } => {
+                ui.label("Even");
+                changed |= texture_ui(ui, even);
+                ui.end_row();
+
+                ui.label("Odd");
+                changed |= texture_ui(ui, odd);
+                ui.end_row();
+            }
+            Material::Emissive { emit } => {
+                changed |= emission_ui(ui, emit);
+            }
+            Material::Mapped { material, normal_map, bump_map, bump_strength } => {
+                ui.label("Material");
+                ui.label(material.name());
+                ui.end_row();
+
+                changed |= Self::material_ui(ui, material);
+
+                for (label, map) in [("Normal map", normal_map), ("Bump map", bump_map)] {
+                    ui.label(label);
+                    match map {
+                        Some(map) => image_label(ui, map),
+                        None => {
+                            ui.label("None");
+                        }
+                    }
+                    ui.end_row();
+                }
+
+                ui.label("Bump strength");
+                changed |= ui
+                    .add(egui::DragValue::new(bump_strength).speed(0.01).clamp_range(0.0..=f32::MAX))
+                    .changed();
+                ui.end_row();
+            }
+            Material::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                specular_tint,
+                clearcoat,
+                sheen,
+                transmission,
+                anisotropy,
+                refraction_index,
+            } => {
+                ui.label("Base color");
+                changed |= texture_ui(ui, base_color);
+                ui.end_row();
+
+                let values = [
+                    ("Metallic", metallic),
+                    ("Roughness", roughness),
+                    ("Specular", specular),
+                    ("Specular tint", specular_tint),
+                    ("Clearcoat", clearcoat),
+                    ("Sheen", sheen),
+                    ("Transmission", transmission),
+                    ("Anisotropy", anisotropy),
+                ];
+                for (label, value) in values {
+                    ui.label(label);
+                    changed |= value_ui(ui, value);
+                    ui.end_row();
+                }
+
+                ui.label("IOR");
+                changed |= ui
+                    .add(egui::DragValue::new(refraction_index).speed(0.01).clamp_range(1.0..=4.0))
+                    .changed();
+                ui.end_row();
+            }
+        }
+        changed
+    }
+
+    fn materials_ui(ui: &mut egui::Ui, scene: &mut Scene, reveal: Option<usize>) -> bool {
+        let mut changed = false;
+        let mut deleted = None;
+
+        ui.heading("Materials");
+        for (idx, material) in scene.materials.iter_mut().enumerate() {
+            let in_use = scene.spheres.iter().any(|s| s.material_idx as usize == idx);
+
+            egui::CollapsingHeader::new(format!("{} {}", idx, material.name()))
+            .id_source(("material", idx))
+            .open((reveal == Some(idx)).then_some(true))
+            .show(ui, |ui| {
+                egui::Grid::new(("material_grid", idx)).num_columns(2).show(ui, |ui| {
+                    changed |= Self::material_ui(ui, material);
+                });
+
+                if ui
+                    .add_enabled(!in_use, egui::Button::new("Delete"))
+                    .on_disabled_hover_text("The material is used by a sphere")
+                    .clicked()
+                {
+                    deleted = Some(idx);
+                }
+            });
+        }
+
+        if let Some(idx) = deleted {
+            scene.materials.remove(idx);
+            for sphere in scene.spheres.iter_mut() {
+                if sphere.material_idx as usize > idx {
+                    sphere.material_idx -= 1;
+                }
+            }
+            changed = true;
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Add");
+            let white = || Texture::new_from_color(glm::vec3(0.8, 0.8, 0.8));
+            let new_material = if ui.button("Lambertian").clicked() {
+                Some(Material::Lambertian { albedo: white() })
+            } else if ui.button("Metal").clicked() {
+                Some(Material::Metal { albedo: white(), fuzz: 0.0 })
+            } else if ui.button("Dielectric").clicked() {
+                Some(Material::Dielectric { refraction_index: 1.5 })
+            } else if ui.button("Principled").clicked() {
+                Some(Material::principled(white()))
+            } else if ui.button("Emissive").clicked() {
+                Some(Material::Emissive { emit: Texture::new_from_color(glm::vec3(10.0, 10.0, 10.0)) })
+            } else {
+                None
+            };
+
+            if let Some(material) = new_material {
+                scene.materials.push(material);
+                changed = true;
+            }
+        });
+
+        changed
+    }
+}
+
+/// A checkbox that enables a limit, followed by the value of the limit.
+/// Returns whether the limit changed.
+fn limit_ui<T: egui::emath::Numeric>(
+    ui: &mut egui::Ui,
+    text: &str,
+    limit: &mut Option<T>,
+    default: T,
+    range: RangeInclusive<T>,
+) -> bool {
+    let mut enabled = limit.is_some();
+    ui.checkbox(&mut enabled, text);
+    let mut value = limit.unwrap_or(default);
+    ui.add_enabled(enabled, egui::DragValue::new(&mut value).clamp_range(range));
+    let changed = *limit != enabled.then_some(value);
+    *limit = enabled.then_some(value);
+    changed
+}
+
+fn status_label(ui: &mut egui::Ui, status: &Option<Result<String, String>>) {
+    match status {
+        Some(Ok(status)) => {
+            ui.label(status);
+        }
+        Some(Err(error)) => {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        None => {}
+    }
+}
+
+fn vec3_ui(ui: &mut egui::Ui, values: &mut [f32]) -> bool {
+    ui.horizontal(|ui| {
+        let mut changed = false;
+        for value in values.iter_mut() {
+            changed |= ui.add(egui::DragValue::new(value).speed(0.01)).changed();
+        }
+        changed
+    })
+    .inner
+}
+
+/// Shows a color picker for constant textures and the file name for images.
+fn texture_ui(ui: &mut egui::Ui, texture: &mut Texture) -> bool {
+    match texture.color() {
+        Some(color) => {
+            let mut rgb = [color.x, color.y, color.z];
+            let changed = ui.color_edit_button_rgb(&mut rgb).changed();
+            if changed {
+                texture.set_color(glm::make_vec3(&rgb));
+            }
+            changed
+        }
+        None => {
+            image_label(ui, texture);
+            false
+        }
+    }
+}
+
+/// Shows a slider in [0, 1] for constant textures and the file name for images.
+fn value_ui(ui: &mut egui::Ui, texture: &mut Texture) -> bool {
+    match texture.color() {
+        Some(color) => {
+            let mut value = color.x;
+            let changed = ui.add(egui::Slider::new(&mut value, 0.0..=1.0)).changed();
+            if changed {
+                texture.set_value(value);
+            }
+            changed
+        }
+        None => {
+            image_label(ui, texture);
+            false
+        }
+    }
+}
+
+/// Emission is edited as a color and a strength, so that the color picker stays in [0, 1].
+fn emission_ui(ui: &mut egui::Ui, emit: &mut Texture) -> bool {
+    let mut changed = false;
+
+    match emit.color() {
+        Some(color) => {
+            let mut strength = color.max();
+            let mut rgb = if strength > 0.0 {
+                [color.x / strength, color.y / strength, color.z / strength]
+            } else {
+                [1.0, 1.0, 1.0]
+            };
+
+            ui.label("Color");
+            changed |= ui.color_edit_button_rgb(&mut rgb).changed();
+            ui.end_row();
+
+            ui.label("Strength");
+            changed |= ui
+                .add(egui::DragValue::new(&mut strength).speed(0.1).clamp_range(0.0..=f32::MAX))
+                .changed();
+            ui.end_row();
+
+            if changed {
+                emit.set_color(strength * glm::make_vec3(&rgb));
+            }
+        }
+        None => {
+            ui.label("Image");
+            image_label(ui, emit);
+            ui.end_row();
+
+            let mut strength = emit.scale();
+            ui.label("Strength");
+            changed |= ui
+                .add(egui::DragValue::new(&mut strength).speed(0.1).clamp_range(0.001..=f32::MAX))
+                .changed();
+            ui.end_row();
+
+            if changed {
+                emit.set_scale(strength);
+            }
+        }
+    }
+
+    changed
+}
+
+fn image_label(ui: &mut egui::Ui, texture: &Texture) {
+    let path = texture.path().unwrap_or_else(|| std::path::Path::new(""));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    ui.label(name).on_hover_text(path.display().to_string());
+}
@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use crate::aov::AovImage;
+
+use crate::camera::Camera;
+use crate::cli::Args;
+use crate::cpu_path_tracer::CpuPathTracer;
+use crate::denoiser::{DenoiseSettings, Denoiser};
+use crate::gpu_scene::GpuScene;
+use crate::gpu_buffer;
+use crate::path_tracer::{PathTracer, RenderStats};
+use crate::pipeline_cache::PipelineCache;
+use crate::renderer::{request_device, RendererError};
+use crate::screenshot::HdrImage;
+use crate::scene::Scene;
+
+/// Renders the scene from the initial camera without opening a window and saves it to
+/// `output`, as a PNG or an EXR depending on the extension.
+pub fn render(args: &Args, scene: &Scene, samples: u32, output: &Path) -> Result<(), String> {
+    let size = winit::dpi::PhysicalSize::new(args.width, args.height);
+    let (image, aov_image) = if args.cpu {
+        (render_cpu(args, scene, size, samples), None)
+    } else {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: args.backends(),
+            dx12_shader_compiler: Default::default(),
+        });
+        match pollster::block_on(args.request_adapter(&instance, None)) {
+            Ok(adapter) => render_gpu(&adapter, args, scene, size, samples)?,
+            // Only fall back if no particular GPU was asked for.
+            Err(RendererError::NoAdapter { .. }) if args.backend.is_none() && args.adapter.is_none() => {
+                log::warn!("No GPU found, falling back to the CPU path tracer");
+                if args.denoise {
+                    log::warn!("The denoiser needs a GPU, saving the noisy image");
+                }
+                if args.aovs {
+                    log::warn!("The AOVs need a GPU, saving only the image");
+                }
+                (render_cpu(args, scene, size, samples), None)
+            }
+            Err(err) => return Err(err.to_string()),
+        }
+    };
+
+    if let Some(dir) = output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).map_err(|err| format!("failed to create {}: {}", dir.display(), err))?;
+    }
+    let is_exr = output.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("exr"));
+    let saved = match &aov_image {
+        Some(aov_image) => aov_image.save_exr(&image, output).map_err(|err| err.to_string()),
+        None if is_exr => image.save_exr(output).map_err(|err| err.to_string()),
+        None => image.save_png(output, args.exposure).map_err(|err| err.to_string()),
+    };
+    saved.map_err(|err| format!("failed to save {}: {}", output.display(), err))?;
+    log::info!("Saved {}", output.display());
+    Ok(())
+}
+
+fn render_gpu(
+    adapter: &wgpu::Adapter,
+    args: &Args,
+    scene: &Scene,
+    size: winit::dpi::PhysicalSize<u32>,
+    samples: u32,
+) -> Result<(HdrImage, Option<AovImage>), String> {
+    log::info!("Using {} ({:?})", adapter.get_info().name, adapter.get_info().backend);
+    let (device, queue) = pollster::block_on(request_device(adapter, wgpu::Features::empty()))
+        .map_err(|err| err.to_string())?;
+
+    let gpu_scene = GpuScene::new(&device, scene);
+    let mut pipeline_cache = PipelineCache::new();
+    let path_tracer = PathTracer::new(&device, &mut pipeline_cache, &gpu_scene);
+
+    log::info!("Rendering a {}x{} image with {} samples per pixel", size.width, size.height, samples);
+    let targets = path_tracer.accumulate(
+        &device,
+        &queue,
+        &gpu_scene,
+        &Camera::default(),
+        size,
+        samples,
+        args.sampler,
+        args.max_radiance,
+        args.aovs,
+    )?;
+    let stats = gpu_buffer::read_buffer::<RenderStats>(&device, &queue, targets.stats_buffer.handle())[0];
+    if stats.num_discarded > 0 {
+        log::warn!("Replaced {} NaN or infinite samples by black", stats.num_discarded);
+    }
+    if let Some(max_radiance) = args.max_radiance {
+        log::info!("Clamped {} samples to a radiance of {}", stats.num_clamped, max_radiance);
+    }
+
+    let image = if args.denoise {
+        let denoiser = Denoiser::new(&device, &mut pipeline_cache);
+        let denoiser_targets = denoiser.create_targets(&device, &targets);
+        log::info!("Denoising");
+        denoiser.denoise_image(&device, &queue, &denoiser_targets, samples, &DenoiseSettings::default())
+    } else {
+        HdrImage::read_accumulation(&device, &queue, targets.accumulation_buffer.handle(), size, samples)
+    };
+    let aov_image = args.aovs.then(|| AovImage::read(&device, &queue, targets.aov_buffer.handle(), size));
+    Ok((image, aov_image))
+}
+
+fn render_cpu(args: &Args, scene: &Scene, size: winit::dpi::PhysicalSize<u32>, samples: u32) -> HdrImage {
+    log::info!(
+        "Rendering a {}x{} image with {} samples per pixel on the CPU",
+        size.width,
+        size.height,
+        samples
+    );
+    CpuPathTracer::new(scene).render_image(&Camera::default(), size, samples, args.sampler, args.max_radiance)
+}
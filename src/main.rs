@@ -1,47 +1,68 @@
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Fullscreen, WindowBuilder},
 };
 
 pub extern crate nalgebra_glm as glm;
 
 mod renderer;
+mod aov;
+mod camera;
+mod camera_path;
+mod cli;
+mod cpu_path_tracer;
+mod denoiser;
+mod file_watcher;
 mod fps_counter;
+mod gizmo;
+#[cfg(test)]
+mod golden_tests;
 mod gui_app;
 mod sphere;
 mod gpu_buffer;
+mod gpu_scene;
+mod gpu_timer;
+mod headless;
+mod path_tracer;
+mod pipeline_cache;
+mod sampling;
 mod scene;
-use renderer::Renderer;
+mod screenshot;
+mod shader_watcher;
+use std::rc::Rc;
 
-use scene::{Material, Scene, Texture};
-use sphere::Sphere;
+use renderer::{Renderer, RendererError};
 
-use wgpu;
+use scene::Scene;
+
+/// How often the scene and kernel files are checked for changes while no frames are drawn.
+const IDLE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
 
 fn main() {
     env_logger::init();
+    let args = cli::Args::parse_and_validate();
+
+    let scene = Scene::load(&args.scene)
+        .unwrap_or_else(|err| exit_with_error(format!("failed to load {}: {}", args.scene.display(), err)));
+
+    if let (Some(output), Some(samples)) = (&args.output, args.max_samples) {
+        if let Err(err) = headless::render(&args, &scene, samples, output) {
+            exit_with_error(err);
+        }
+        return;
+    }
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("GPU Ray Tracer")
-        .with_inner_size(winit::dpi::LogicalSize::new(800, 600))
+        .with_inner_size(winit::dpi::LogicalSize::new(args.width, args.height))
+        .with_fullscreen(args.fullscreen.then_some(Fullscreen::Borderless(None)))
         .build(&event_loop)
-        .unwrap();
-
-    let spheres = vec![
-        sphere::Sphere::new(glm::vec3(0.0, 0.0, -1.0), 0.5, 1),
-        sphere::Sphere::new(glm::vec3(-1.0, 0.0, -2.0), 1.0, 1),
-        sphere::Sphere::new(glm::vec3(3.0, 2.0, -4.0), 1.0, 1),
-        sphere::Sphere::new(glm::vec3(-0.2, 0.0, -0.3), 0.3, 1),
-        //sphere::Sphere::new(glm::vec3(0.0, 0.0, 8.0), 1.0, 1),
-        //sphere::Sphere::new(glm::vec3(0.0, 0.0, 10.0), 1.0, 1),
-        //sphere::Sphere::new(glm::vec3(0.0, 0.0, 12.0), 1.0, 1),
-        //sphere::Sphere::new(glm::vec3(0.0, 0.0, 12.0), 1.0, 1),
-    ];
-    let scene = setup_scene();
-
-    let mut renderer = pollster::block_on(Renderer::new(window, scene));
+        .unwrap_or_else(|err| exit_with_error(format!("failed to open a window: {}", err)));
+
+    let mut renderer = pollster::block_on(Renderer::new(Rc::new(window), scene, args.scene.clone(), &args))
+        .unwrap_or_else(|err| exit_with_error(err));
 
     let start_time = std::time::Instant::now();
     let mut last_time = std::time::Instant::now();
@@ -53,7 +74,7 @@ fn main() {
             Event::WindowEvent {
                 ref event,
                 window_id
-            } if window_id == renderer.window.id() => {
+            } if window_id == renderer.window.id() && !renderer.input(event) => {
                 match event {
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                     WindowEvent::Resized(physical_size) => {
@@ -65,16 +86,30 @@ fn main() {
                     _ => {}
                 }
             }
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => {
+                renderer.mouse_motion(delta);
+            }
             Event::RedrawRequested(_) => {
                 renderer.platform.update_time(start_time.elapsed().as_secs_f64());//TODO: maybe this can be moved to renderer.update()?
-                match renderer.render() {
-                    Ok(_) => {}
-                    // Recreate the swap_chain if lost
-                    Err(wgpu::SurfaceError::Lost) => {renderer.resize(renderer.size)}
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => {*control_flow = ControlFlow::Exit}
-                    // All other errors (Outdated, Timeout) should be resolved by the next frame
-                    Err(e) => eprintln!("{:?}", e),
+                if let Err(err) = renderer.render() {
+                    match RenderErrorAction::for_error(&err) {
+                        RenderErrorAction::Resize => renderer.resize(renderer.size),
+                        RenderErrorAction::Exit => {
+                            eprintln!("error: {}", err);
+                            *control_flow = ControlFlow::Exit;
+                        }
+                        RenderErrorAction::Recover => {
+                            log::warn!("The GPU device was lost, creating it again");
+                            if let Err(err) = renderer.recover() {
+                                eprintln!("error: failed to recover from a lost GPU device: {}", err);
+                                *control_flow = ControlFlow::Exit;
+                            }
+                        }
+                        RenderErrorAction::Skip => log::warn!("{}", err),
+                    }
                 }
             }
             Event::MainEventsCleared => {
@@ -83,72 +118,62 @@ fn main() {
                     last_time = std::time::Instant::now();
                     renderer.update(delta_time);
                 }
-                renderer.window.request_redraw();
+                let now = std::time::Instant::now();
+                control_flow.set_wait_until(match renderer.next_frame_time() {
+                    Some(time) if time <= now => {
+                        renderer.window.request_redraw();
+                        now
+                    }
+                    Some(time) => time,
+                    // Keep polling the file watchers while idle.
+                    None => now + IDLE_POLL_INTERVAL,
+                });
             }
             _ => {}
         }
     });
 }
 
+/// How the event loop handles a frame that failed to render.
+#[derive(Debug, PartialEq)]
+enum RenderErrorAction {
+    /// Recreate the swapchain.
+    Resize,
+    /// The system is out of memory, we should probably quit.
+    Exit,
+    /// Create the device again.
+    Recover,
+    /// The other errors (Outdated, Timeout) should be resolved by the next frame.
+    Skip,
+}
+
+impl RenderErrorAction {
+    fn for_error(err: &RendererError) -> Self {
+        match err {
+            RendererError::Surface(wgpu::SurfaceError::Lost) => Self::Resize,
+            RendererError::Surface(wgpu::SurfaceError::OutOfMemory) => Self::Exit,
+            RendererError::DeviceLost => Self::Recover,
+            _ => Self::Skip,
+        }
+    }
+}
+
+fn exit_with_error(message: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-fn setup_scene() -> scene::Scene {
-    let materials = vec![
-        Material::Checkerboard {
-            even: Texture::new_from_color(glm::vec3(0.5_f32, 0.7_f32, 0.8_f32)),
-            odd: Texture::new_from_color(glm::vec3(0.9_f32, 0.9_f32, 0.9_f32)),
-        },
-        Material::Lambertian {
-            albedo: Texture::new_from_image("assets/moon.jpeg")
-                .expect("Hardcoded path should be valid"),
-        },
-        Material::Metal {
-            albedo: Texture::new_from_color(glm::vec3(1_f32, 0.85_f32, 0.57_f32)),
-            fuzz: 0.3_f32,
-        },
-        Material::Metal {
-            albedo: Texture::new_from_color(glm::vec3(0.5_f32, 0.85_f32, 1_f32)),
-            fuzz: 0.0_f32,
-        },
-        Material::Dielectric {
-            refraction_index: 1.5_f32,
-        },
-        Material::Lambertian {
-            albedo: Texture::new_from_image("assets/earthmap.jpeg")
-                .expect("Hardcoded path should be valid"),
-        },
-        Material::Emissive {
-            emit: Texture::new_from_scaled_image("assets/sun.jpeg", 50.0)
-                .expect("Hardcoded path should be valid"),
-        },
-        Material::Lambertian {
-            albedo: Texture::new_from_color(glm::vec3(0.3_f32, 0.9_f32, 0.9_f32)),
-        },
-        Material::Emissive {
-            emit: Texture::new_from_color(glm::vec3(50.0_f32, 0.0_f32, 0.0_f32)),
-        },
-        Material::Emissive {
-            emit: Texture::new_from_color(glm::vec3(0.0_f32, 50.0_f32, 0.0_f32)),
-        },
-        Material::Emissive {
-            emit: Texture::new_from_color(glm::vec3(0.0, 0.0, 50.0)),
-        },
-    ];
-
-    let spheres = vec![
-        Sphere::new(glm::vec3(0.0, -510.0, -1.0), 500.0, 10_u32),
-        // left row
-        Sphere::new(glm::vec3(-2.0, 0.0, -3.0), 1.0, 2_u32),
-        //Sphere::new(glm::vec3(0.0, 0.0, -3.0), 1.0, 1_u32),
-        Sphere::new(glm::vec3(2.0, 0.0, -3.0), 1.0, 3_u32),
-        // middle row
-        //Sphere::new(glm::vec3(-5.0, 1.0, 0.0), 1.0, 2_u32),
-        //Sphere::new(glm::vec3(0.0, 1.0, 0.0), 1.0, 3_u32),
-        //Sphere::new(glm::vec3(5.0, 1.0, 0.0), 1.0, 6_u32),
-        // right row
-        //Sphere::new(glm::vec3(-5.0, 0.8, 4.0), 0.8, 1_u32),
-        //Sphere::new(glm::vec3(0.0, 1.2, 4.0), 1.2, 4_u32),
-        //Sphere::new(glm::vec3(5.0, 2.0, 4.0), 2.0, 5_u32),
-    ];
-
-    Scene { spheres, materials }
+    #[test]
+    fn render_errors_map_to_actions() {
+        let action = |err| RenderErrorAction::for_error(&err);
+        assert_eq!(action(RendererError::DeviceLost), RenderErrorAction::Recover);
+        assert_eq!(action(RendererError::Surface(wgpu::SurfaceError::Lost)), RenderErrorAction::Resize);
+        assert_eq!(action(RendererError::Surface(wgpu::SurfaceError::OutOfMemory)), RenderErrorAction::Exit);
+        assert_eq!(action(RendererError::Surface(wgpu::SurfaceError::Outdated)), RenderErrorAction::Skip);
+        assert_eq!(action(RendererError::Surface(wgpu::SurfaceError::Timeout)), RenderErrorAction::Skip);
+    }
 }
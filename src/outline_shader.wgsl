@@ -0,0 +1,82 @@
+// Draws an outline around the silhouette of the selected sphere on top of the image.
+
+struct Camera {
+    eye: vec4<f32>,
+    upperLeftCorner: vec4<f32>,
+    horizontal: vec4<f32>,
+    vertical: vec4<f32>,
+    u: vec4<f32>,
+    v: vec4<f32>,
+    lensRadius: f32,
+    apertureBlades: u32,
+    apertureRotation: f32,
+    shutterOpen: f32,
+    shutterClose: f32,
+}
+
+struct Outline {
+    // xyz is the center, w the radius
+    sphere: vec4<f32>,
+    color: vec4<f32>,
+    screenHeight: f32,
+    width: f32,
+}
+
+@group(0) @binding(0) var<uniform> camera : Camera;
+@group(0) @binding(1) var<uniform> outline : Outline;
+
+struct VertexOutput {
+    @builtin(position) Position : vec4<f32>,
+    @location(0) TexCoord : vec2<f32>,
+}
+
+@vertex
+fn vert_main(@builtin(vertex_index) VertexIndex : u32) -> VertexOutput {
+
+    var positions = array<vec2<f32>, 6>(
+        vec2<f32>( 1.0,  1.0),
+        vec2<f32>( 1.0, -1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>( 1.0,  1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(-1.0,  1.0)
+    );
+
+    var texCoords = array<vec2<f32>, 6>(
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 0.0)
+    );
+
+    var output : VertexOutput;
+    output.Position = vec4<f32>(positions[VertexIndex], 0.0, 1.0);
+    output.TexCoord = texCoords[VertexIndex];
+    return output;
+}
+
+@fragment
+fn frag_main(@location(0) TexCoord : vec2<f32>) -> @location(0) vec4<f32> {
+    // The same pinhole ray as the kernel, without the lens offset.
+    let toViewport = camera.upperLeftCorner.xyz - camera.eye.xyz;
+    let direction = normalize(toViewport + TexCoord.x * camera.horizontal.xyz - TexCoord.y * camera.vertical.xyz);
+
+    let toCenter = outline.sphere.xyz - camera.eye.xyz;
+    let tClosest = dot(toCenter, direction);
+    if tClosest <= 0f {
+        discard;
+    }
+
+    // The distance between the ray and the center grows past the radius at the
+    // silhouette. Outside of it, measure the width in pixels at the sphere's depth.
+    let focusDistance = length(toViewport + 0.5 * camera.horizontal.xyz - 0.5 * camera.vertical.xyz);
+    let pixelSize = tClosest * length(camera.vertical.xyz) / (focusDistance * outline.screenHeight);
+    let distance = length(toCenter - tClosest * direction) - outline.sphere.w;
+    if distance < 0f || distance > outline.width * pixelSize {
+        discard;
+    }
+
+    return outline.color;
+}
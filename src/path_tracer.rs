@@ -0,0 +1,471 @@
+use std::rc::Rc;
+
+use crate::aov::GpuAov;
+use crate::camera::Camera;
+use crate::gpu_buffer::{StorageBuffer, UniformBuffer};
+use crate::gpu_scene::GpuScene;
+use crate::pipeline_cache::PipelineCache;
+use crate::sampling::{self, Sampler};
+#[cfg(test)]
+use crate::screenshot::HdrImage;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SamplingParams {
+    num_bounces: u32,
+    frame_idx: u32,
+    sampler: u32,
+    debug_mode: u32,
+    /// 0 disables the clamping.
+    max_radiance: f32,
+    _padding: [u32; 3],
+}
+
+/// The maximum number of ray segments per path.
+pub const NUM_BOUNCES: u32 = 10;
+
+/// What the kernel writes instead of the radiance, to find out why a scene looks wrong.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum DebugMode {
+    #[default]
+    Off = 0,
+    /// The shading normal of the first hit.
+    Normals = 1,
+    /// The texture coordinates of the first hit.
+    Uvs = 2,
+    /// A random color for every material.
+    MaterialId = 3,
+    /// The number of segments of the path, up to `NUM_BOUNCES`.
+    Bounces = 4,
+    /// The number of ray-sphere tests of the path.
+    IntersectionTests = 5,
+    /// Magenta for paths whose radiance isn't finite, the dimmed image otherwise.
+    NanInf = 6,
+}
+
+impl DebugMode {
+    pub const ALL: [Self; 7] = [
+        Self::Off,
+        Self::Normals,
+        Self::Uvs,
+        Self::MaterialId,
+        Self::Bounces,
+        Self::IntersectionTests,
+        Self::NanInf,
+    ];
+}
+
+/// Counters the kernel increments.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct RenderStats {
+    pub num_rays: u32,
+    /// Samples that were NaN or infinite and replaced by black.
+    pub num_discarded: u32,
+    /// Samples whose radiance was clamped to the maximum.
+    pub num_clamped: u32,
+}
+
+/// The ray tracing kernel and the per frame data it reads. It doesn't need a window, so
+/// the viewport, the exports and headless renders all share it.
+pub struct PathTracer {
+    targets_bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: Rc<wgpu::ComputePipeline>,
+    camera_buffer: UniformBuffer,
+    sampling_params_buffer: UniformBuffer,
+    _sampler_tables_buffer: StorageBuffer,
+    frame_data_bind_group: wgpu::BindGroup,
+}
+
+impl PathTracer {
+    pub const STORAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    pub const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const NORMAL_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+    /// Creates the pipeline with the embedded kernel.
+    pub fn new(device: &wgpu::Device, pipeline_cache: &mut PipelineCache, gpu_scene: &GpuScene) -> Self {
+        let targets_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ray Tracing Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: Self::STORAGE_FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: Self::ALBEDO_FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: Self::NORMAL_DEPTH_FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        // camera and sampling parameters, updated every frame
+        let camera_buffer = UniformBuffer::new_from_bytes(
+            device,
+            bytemuck::bytes_of(&Camera::default().to_gpu(1.0)),
+            0_u32,
+            Some("camera buffer"),
+        );
+        let sampling_params_buffer = UniformBuffer::new(
+            device,
+            std::mem::size_of::<SamplingParams>() as wgpu::BufferAddress,
+            1_u32,
+            Some("sampling params buffer"),
+        );
+        let sampler_tables_buffer = StorageBuffer::new_from_bytes(
+            device,
+            bytemuck::cast_slice(&sampling::sampler_tables()),
+            2_u32,
+            Some("sampler tables buffer"),
+        );
+
+        let frame_data_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    camera_buffer.layout(wgpu::ShaderStages::COMPUTE),
+                    sampling_params_buffer.layout(wgpu::ShaderStages::COMPUTE),
+                    sampler_tables_buffer.layout(wgpu::ShaderStages::COMPUTE, true),
+                ],
+                label: Some("frame data layout"),
+            });
+        let frame_data_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &frame_data_bind_group_layout,
+            entries: &[
+                camera_buffer.binding(),
+                sampling_params_buffer.binding(),
+                sampler_tables_buffer.binding(),
+            ],
+            label: Some("frame data bind group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ray Tracing Pipeline Layout"),
+            bind_group_layouts: &[&targets_bind_group_layout, gpu_scene.bind_group_layout(), &frame_data_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = pipeline_cache
+            .compute_pipeline(
+                device,
+                "Ray Tracing Pipeline",
+                &pipeline_layout,
+                include_str!("ray_tracing_kernel.wgsl"),
+                "main",
+            )
+            .expect("The embedded kernel should be valid");
+
+        Self {
+            targets_bind_group_layout,
+            pipeline_layout,
+            pipeline,
+            camera_buffer,
+            sampling_params_buffer,
+            _sampler_tables_buffer: sampler_tables_buffer,
+            frame_data_bind_group,
+        }
+    }
+
+    /// Switches to a different kernel source. The current pipeline stays if the new one
+    /// can't be created.
+    pub fn set_kernel(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        source: &str,
+    ) -> Result<(), wgpu::Error> {
+        self.pipeline = pipeline_cache.compute_pipeline(device, "Ray Tracing Pipeline", &self.pipeline_layout, source, "main")?;
+        Ok(())
+    }
+
+    /// The camera of the last frame data, for overlays that draw over the image.
+    pub fn camera_buffer(&self) -> &UniformBuffer {
+        &self.camera_buffer
+    }
+
+    /// Writes the camera and the sampling parameters for the next dispatch. Samples are
+    /// clamped to `max_radiance`, if any.
+    #[allow(clippy::too_many_arguments)]
+    pub fn write_frame_data(
+        &self,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        aspect: f32,
+        frame_idx: u32,
+        sampler: Sampler,
+        debug_mode: DebugMode,
+        max_radiance: Option<f32>,
+    ) {
+        queue.write_buffer(self.camera_buffer.handle(), 0, bytemuck::bytes_of(&camera.to_gpu(aspect)));
+        queue.write_buffer(
+            self.sampling_params_buffer.handle(),
+            0,
+            bytemuck::bytes_of(&SamplingParams {
+                num_bounces: NUM_BOUNCES,
+                frame_idx,
+                sampler: sampler as u32,
+                debug_mode: debug_mode as u32,
+                max_radiance: max_radiance.unwrap_or(0.0),
+                _padding: [0; 3],
+            }),
+        );
+    }
+
+    /// Adds one sample per pixel to the accumulation buffer of the targets.
+    pub fn encode_pass(&self, encoder: &mut wgpu::CommandEncoder, targets: &RenderTargets, gpu_scene: &GpuScene) {
+        let mut ray_trace_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Ray Tracing Pass"),
+        });
+        ray_trace_pass.set_pipeline(&self.pipeline);
+        ray_trace_pass.set_bind_group(0, &targets.bind_group, &[]);
+        ray_trace_pass.set_bind_group(1, gpu_scene.bind_group(), &[]);
+        ray_trace_pass.set_bind_group(2, &self.frame_data_bind_group, &[]);
+        ray_trace_pass.dispatch_workgroups(targets.size.width, targets.size.height, 1);
+    }
+
+    /// Creates targets of the given size. The kernel only writes the AOVs into targets
+    /// that were created with `aovs`, since they take three times as much memory as the
+    /// accumulation buffer.
+    pub fn create_targets(&self, device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>, aovs: bool) -> RenderTargets {
+        RenderTargets::new(device, size, &self.targets_bind_group_layout, aovs)
+    }
+
+    /// Renders the camera's view into a separate accumulation buffer of the given size.
+    /// The result only depends on the scene, the camera, the sample count, the sampler and
+    /// the clamping.
+    #[cfg(test)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_image(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        gpu_scene: &GpuScene,
+        camera: &Camera,
+        size: winit::dpi::PhysicalSize<u32>,
+        samples: u32,
+        sampler: Sampler,
+        max_radiance: Option<f32>,
+    ) -> Result<HdrImage, String> {
+        let targets = self.accumulate(device, queue, gpu_scene, camera, size, samples, sampler, max_radiance, false)?;
+        Ok(HdrImage::read_accumulation(
+            device,
+            queue,
+            targets.accumulation_buffer.handle(),
+            size,
+            samples,
+        ))
+    }
+
+    /// Traces `samples` samples per pixel into new targets of the given size, e.g. for
+    /// reading the image back, denoising it or reading the AOVs.
+    #[allow(clippy::too_many_arguments)]
+    pub fn accumulate(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        gpu_scene: &GpuScene,
+        camera: &Camera,
+        size: winit::dpi::PhysicalSize<u32>,
+        samples: u32,
+        sampler: Sampler,
+        max_radiance: Option<f32>,
+        aovs: bool,
+    ) -> Result<RenderTargets, String> {
+        let limits = device.limits();
+        let pixel_size = if aovs { std::mem::size_of::<GpuAov>() } else { std::mem::size_of::<[f32; 4]>() };
+        let buffer_size = size.width as u64 * size.height as u64 * pixel_size as u64;
+        if size.width.max(size.height) > limits.max_texture_dimension_2d.min(limits.max_compute_workgroups_per_dimension)
+            || buffer_size > (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size)
+        {
+            return Err(format!("{}x{} is too large for this GPU", size.width, size.height));
+        }
+
+        let targets = self.create_targets(device, size, aovs);
+        let aspect = size.width as f32 / size.height as f32;
+        // Every sample needs its own submission, since the frame index is a uniform.
+        for sample_idx in 0..samples {
+            self.write_frame_data(queue, camera, aspect, sample_idx, sampler, DebugMode::Off, max_radiance);
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Still Encoder"),
+            });
+            self.encode_pass(&mut encoder, &targets, gpu_scene);
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+        Ok(targets)
+    }
+}
+
+/// The buffers the kernel writes, which depend on the image size.
+pub struct RenderTargets {
+    pub size: winit::dpi::PhysicalSize<u32>,
+    _color_buffer: wgpu::Texture,
+    /// The tonemapped image of the last sample, for displaying it.
+    pub color_buffer_view: wgpu::TextureView,
+    /// Sums of the samples in xyz and of their squared luminance in w.
+    pub accumulation_buffer: StorageBuffer,
+    pub stats_buffer: StorageBuffer,
+    _albedo_buffer: wgpu::Texture,
+    /// The albedo of the first hit of the first sample.
+    pub albedo_view: wgpu::TextureView,
+    _normal_depth_buffer: wgpu::Texture,
+    /// The normal and the distance of the first hit of the first sample.
+    pub normal_depth_view: wgpu::TextureView,
+    /// A `GpuAov` per pixel, or a single one if the targets were created without AOVs.
+    pub aov_buffer: StorageBuffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl RenderTargets {
+    fn new(
+        device: &wgpu::Device,
+        size: winit::dpi::PhysicalSize<u32>,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        aovs: bool,
+    ) -> Self {
+        let color_buffer = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Color Buffer"),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PathTracer::STORAGE_FORMAT,
+            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[PathTracer::STORAGE_FORMAT],
+        });
+
+        let color_buffer_view = color_buffer.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let feature_buffer = |label, format| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size.width,
+                    height: size.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[format],
+            })
+        };
+        let albedo_buffer = feature_buffer("Albedo Buffer", PathTracer::ALBEDO_FORMAT);
+        let albedo_view = albedo_buffer.create_view(&wgpu::TextureViewDescriptor::default());
+        let normal_depth_buffer = feature_buffer("Normal Depth Buffer", PathTracer::NORMAL_DEPTH_FORMAT);
+        let normal_depth_view = normal_depth_buffer.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let num_pixels = size.width as wgpu::BufferAddress * size.height as wgpu::BufferAddress;
+        let accumulation_buffer = StorageBuffer::new(
+            device,
+            num_pixels * std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+            1_u32,
+            Some("accumulation buffer"),
+        );
+
+        let stats_buffer = StorageBuffer::new(
+            device,
+            std::mem::size_of::<RenderStats>() as wgpu::BufferAddress,
+            2_u32,
+            Some("render stats buffer"),
+        );
+
+        // Storage buffers can't be empty.
+        let num_aovs = if aovs { num_pixels } else { 1 };
+        let aov_buffer = StorageBuffer::new(
+            device,
+            num_aovs * std::mem::size_of::<GpuAov>() as wgpu::BufferAddress,
+            5_u32,
+            Some("aov buffer"),
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ray Tracing Bind Group"),
+            layout: bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&color_buffer_view),
+            },
+            accumulation_buffer.binding(),
+            stats_buffer.binding(),
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&albedo_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&normal_depth_view),
+            },
+            aov_buffer.binding()],
+        });
+
+        Self {
+            size,
+            _color_buffer: color_buffer,
+            color_buffer_view,
+            accumulation_buffer,
+            stats_buffer,
+            _albedo_buffer: albedo_buffer,
+            albedo_view,
+            _normal_depth_buffer: normal_depth_buffer,
+            normal_depth_view,
+            aov_buffer,
+            bind_group,
+        }
+    }
+}
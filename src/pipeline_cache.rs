@@ -0,0 +1,157 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// Creates pipelines and caches them by label and shader source, so that reverting a
+/// hot-reloaded kernel or switching between shader variants doesn't compile anything
+/// twice. A label must always be used with the same pipeline layout.
+pub struct PipelineCache {
+    compute_pipelines: HashMap<u64, Rc<wgpu::ComputePipeline>>,
+    render_pipelines: HashMap<u64, Rc<wgpu::RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self {
+            compute_pipelines: HashMap::new(),
+            render_pipelines: HashMap::new(),
+        }
+    }
+
+    /// Returns the compute pipeline for the given source, compiling it if it isn't cached.
+    /// Pipelines that fail validation are not cached.
+    pub fn compute_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        layout: &wgpu::PipelineLayout,
+        source: &str,
+        entry_point: &str,
+    ) -> Result<Rc<wgpu::ComputePipeline>, wgpu::Error> {
+        let key = Self::key(&[label, source, entry_point]);
+        if let Some(pipeline) = self.compute_pipelines.get(&key) {
+            return Ok(pipeline.clone());
+        }
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            module: &device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            }),
+            entry_point,
+        });
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            return Err(err);
+        }
+
+        let pipeline = Rc::new(pipeline);
+        self.compute_pipelines.insert(key, pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /// Returns a pipeline that draws a fullscreen quad with the `vert_main` and `frag_main`
+    /// entry points of the given source, compiling it if it isn't cached.
+    pub fn fullscreen_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        layout: &wgpu::PipelineLayout,
+        source: &str,
+        format: wgpu::TextureFormat,
+    ) -> Result<Rc<wgpu::RenderPipeline>, wgpu::Error> {
+        self.render_pipeline(device, label, layout, source, format, None, wgpu::BlendState::REPLACE)
+    }
+
+    /// Returns a pipeline that alpha blends triangles from a single vertex buffer over the
+    /// target, using the `vert_main` and `frag_main` entry points of the given source.
+    pub fn overlay_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        layout: &wgpu::PipelineLayout,
+        source: &str,
+        format: wgpu::TextureFormat,
+        vertex_layout: wgpu::VertexBufferLayout<'static>,
+    ) -> Result<Rc<wgpu::RenderPipeline>, wgpu::Error> {
+        self.render_pipeline(
+            device,
+            label,
+            layout,
+            source,
+            format,
+            Some(vertex_layout),
+            wgpu::BlendState::ALPHA_BLENDING,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        layout: &wgpu::PipelineLayout,
+        source: &str,
+        format: wgpu::TextureFormat,
+        vertex_layout: Option<wgpu::VertexBufferLayout<'static>>,
+        blend: wgpu::BlendState,
+    ) -> Result<Rc<wgpu::RenderPipeline>, wgpu::Error> {
+        let key = Self::key(&[label, source, &format!("{:?}", format)]);
+        if let Some(pipeline) = self.render_pipelines.get(&key) {
+            return Ok(pipeline.clone());
+        }
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vert_main",
+                buffers: vertex_layout.as_slice(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: "frag_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            return Err(err);
+        }
+
+        let pipeline = Rc::new(pipeline);
+        self.render_pipelines.insert(key, pipeline.clone());
+        Ok(pipeline)
+    }
+
+    fn key(parts: &[&str]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        parts.hash(&mut hasher);
+        hasher.finish()
+    }
+}
@@ -7,19 +7,49 @@ const PI = 3.1415927f;
 const FRAC_1_PI = 0.31830987f;
 const FRAC_PI_2 = 1.5707964f;
 
+const LUMINANCE = vec3(0.2126f, 0.7152f, 0.0722f);
+
+// The material and sphere index of rays that hit nothing.
+const NO_HIT = 0xffffffffu;
+
 
 @group(0) @binding(0) var color_buffer: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(1) var<storage, read_write> accumulation_buffer: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read_write> renderStats: RenderStats;
+// The features of the first hit, which guide the denoiser.
+@group(0) @binding(3) var albedoBuffer: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(4) var normalDepthBuffer: texture_storage_2d<rgba32float, write>;
+// Only has room for the image if the AOVs were requested.
+@group(0) @binding(5) var<storage, read_write> aovBuffer: array<Aov>;
 
 @group(1) @binding(0) var<storage, read> spheres: array<Sphere>;
 @group(1) @binding(1) var<storage, read> materials: array<Material>;
 @group(1) @binding(2) var<storage, read> textures: array<array<f32, 3>>;
 @group(1) @binding(3) var<storage, read> lights: array<u32>;
 
+@group(2) @binding(0) var<uniform> camera: Camera;
+@group(2) @binding(1) var<uniform> samplingParams: SamplingParams;
+// The Sobol direction numbers of the first SOBOL_DIMENSIONS dimensions, followed by the
+// blue noise mask.
+@group(2) @binding(2) var<storage, read> samplerTables: array<u32>;
+
+const SAMPLER_INDEPENDENT = 0u;
+const SAMPLER_SOBOL = 1u;
+const SAMPLER_BLUE_NOISE = 2u;
+
+const DEBUG_OFF = 0u;
+const DEBUG_NORMALS = 1u;
+const DEBUG_UVS = 2u;
+const DEBUG_MATERIAL_ID = 3u;
+const DEBUG_BOUNCES = 4u;
+const DEBUG_INTERSECTION_TESTS = 5u;
+const DEBUG_NAN = 6u;
+
+const SOBOL_DIMENSIONS = 4u;
+const BLUE_NOISE_OFFSET = 128u;
+const BLUE_NOISE_SIZE = 64u;
 
 
-fn length_squared(v: vec3<f32>) -> f32 {
-    return v.x*v.x + v.y*v.y + v.z*v.z;
-}
 
 @compute @workgroup_size(1,1,1)
 fn main(@builtin(global_invocation_id) GlobalInvocationID : vec3<u32>) {
@@ -27,73 +57,134 @@ fn main(@builtin(global_invocation_id) GlobalInvocationID : vec3<u32>) {
     let screen_size: vec2<u32> = textureDimensions(color_buffer);
     let screen_pos : vec2<i32> = vec2<i32>(i32(GlobalInvocationID.x), i32(GlobalInvocationID.y));
 
-    var rngState = initRng(vec2(GlobalInvocationID.x, GlobalInvocationID.y), screen_size, 0u);
+    var rngState = initRng(vec2(GlobalInvocationID.x, GlobalInvocationID.y), screen_size, samplingParams.frameIdx);
 
-    let aspect_ratio = f32(screen_size.x) / f32(screen_size.y);
-    let viewport_height = f32(screen_size.y) / 500.0;
-    let viewport_width = aspect_ratio * viewport_height;
-    let focal_length = 1.0;
-
-    let origin = vec3<f32>(0.0, 0.0, 0.0);
-    let horizontal = vec3<f32>(viewport_width, 0.0, 0.0);
-    let vertical = vec3<f32>(0.0, viewport_height, 0.0);
-    let upper_left_corner = origin - horizontal/2.0 + vertical/2.0 - vec3<f32>(0.0, 0.0, focal_length);
+    let u = (f32(screen_pos.x) + rngNextFloat(&rngState)) / f32(screen_size.x);
+    let v = (f32(screen_pos.y) + rngNextFloat(&rngState)) / f32(screen_size.y);
+    let ray = cameraRay(u, v, &rngState);
 
+    var pixel_color: vec3<f32> = rayColor(ray, &rngState);
+    atomicAdd(&renderStats.numRays, numRays);
+
+    // A single NaN or infinite sample would poison the pixel for the rest of the
+    // accumulation, and a single very bright one would stand out for a long time.
+    let brightest = max(pixel_color.x, max(pixel_color.y, pixel_color.z));
+    if isNanOrInf(pixel_color) {
+        pixel_color = vec3(0f);
+        atomicAdd(&renderStats.numDiscarded, 1u);
+    } else if samplingParams.maxRadiance > 0f && brightest > samplingParams.maxRadiance {
+        pixel_color *= samplingParams.maxRadiance / brightest;
+        atomicAdd(&renderStats.numClamped, 1u);
+    }
 
-    let u = f32(screen_pos.x) / (f32(screen_size.x) - 1.0);
-    let v = f32(screen_pos.y) / (f32(screen_size.y) - 1.0);
-    var ray: Ray;
-    ray.origin = origin;
-    ray.direction = normalize(upper_left_corner + u*horizontal - v*vertical - origin);
+    // The features of the first sample stay until the accumulation restarts, so that the
+    // denoised image doesn't flicker.
+    if samplingParams.frameIdx == 0u {
+        textureStore(albedoBuffer, screen_pos, vec4(firstHitAlbedo, 1f));
+        textureStore(normalDepthBuffer, screen_pos, vec4(firstHitNormal, firstHitDepth));
+    }
 
+    let idx = GlobalInvocationID.y * screen_size.x + GlobalInvocationID.x;
+    if samplingParams.frameIdx == 0u && idx < arrayLength(&aovBuffer) {
+        let forward = cross(camera.v.xyz, camera.u.xyz);
+        aovBuffer[idx] = Aov(
+            firstHitAlbedo,
+            firstHitMaterialIdx,
+            firstHitNormal,
+            firstHitSphereIdx,
+            firstHitPosition,
+            dot(firstHitPosition - camera.eye.xyz, forward),
+        );
+    }
 
-    //var pixel_color: vec3<f32> = ray_color(ray);
-    var pixel_color: vec3<f32> = rayColor(ray, &rngState);
-    //let num = f32(screen_pos.x) / f32(screen_size.x);
-    //var pixel_color: vec3<f32> = vec3<f32>(num, num, num);
+    // The w component sums the squared luminance, for the variance the denoiser needs.
+    let luminance = dot(pixel_color, LUMINANCE);
+    var luminanceSqr = luminance * luminance;
+    if samplingParams.frameIdx > 0u {
+        pixel_color += accumulation_buffer[idx].xyz;
+        luminanceSqr += accumulation_buffer[idx].w;
+    }
+    accumulation_buffer[idx] = vec4(pixel_color, luminanceSqr);
+    pixel_color /= f32(samplingParams.frameIdx + 1u);
 
     textureStore(color_buffer, screen_pos, vec4<f32>(pixel_color, 1.0));
 }
 
-fn ray_color(ray: Ray) -> vec3<f32> {
-    var pixel_color: vec3<f32>;
-
+// models
+struct Camera {
+    eye: vec4<f32>,
+    upperLeftCorner: vec4<f32>,
+    horizontal: vec4<f32>,
+    vertical: vec4<f32>,
+    u: vec4<f32>,
+    v: vec4<f32>,
+    lensRadius: f32,
+    apertureBlades: u32,
+    apertureRotation: f32,
+    shutterOpen: f32,
+    shutterClose: f32,
+}
 
-    var didHit: bool = false;
-    for (var i = 0u; i < arrayLength(&spheres); i = i + 1u) {
-        let t = hit(ray, spheres[i]);
-        if (t > 0.0) {
-            let N: vec3<f32> = normalize((ray.origin + normalize(ray.direction) * t) - vec3<f32>(0.0, 0.0, -1.0));
-            pixel_color = 0.5 * vec3<f32>(N.x+1.0, N.y+1.0, N.z+1.0);
-            didHit = true;
-            break;
-        }
-    }
-    if (!didHit) {
-        let t = 0.5 * (ray.direction.y + 1.0);
-        pixel_color = (1.0 - t) * vec3<f32>(1.0, 1.0, 1.0) + t * vec3<f32>(0.5, 0.7, 1.0);
-    }
+struct RenderStats {
+    // Number of ray segments traced since the counters were cleared.
+    numRays: atomic<u32>,
+    // Number of samples that were NaN or infinite and replaced by black.
+    numDiscarded: atomic<u32>,
+    // Number of samples that were brighter than maxRadiance.
+    numClamped: atomic<u32>,
+}
 
-    return pixel_color;
+// Counts the rays traced by this invocation, so that it needs only one atomic.
+var<private> numRays: u32 = 0u;
+// Counts the ray-sphere tests of this invocation, for the cost heatmap.
+var<private> numIntersectionTests: u32 = 0u;
+
+// Written by the first segment of the path.
+var<private> firstHitAlbedo: vec3<f32>;
+var<private> firstHitNormal: vec3<f32>;
+var<private> firstHitDepth: f32;
+var<private> firstHitPosition: vec3<f32>;
+var<private> firstHitMaterialIdx: u32;
+var<private> firstHitSphereIdx: u32;
+var<private> firstHitUv: vec2<f32>;
+
+// The arbitrary output variables of a pixel, from the first hit of the first sample.
+struct Aov {
+    albedo: vec3<f32>,
+    materialIdx: u32,
+    normal: vec3<f32>,
+    sphereIdx: u32,
+    position: vec3<f32>,
+    // The distance along the view direction.
+    depth: f32,
 }
 
-fn hit(ray: Ray, sphere: Sphere) -> f32 {
-    let oc = ray.origin - sphere.center.xyz;
-    let a: f32 = length_squared(ray.direction);
-    let half_b: f32 = dot(ray.direction, oc);
-    let c: f32 = length_squared(oc) - sphere.radius * sphere.radius;
-    let discriminant: f32 = half_b*half_b - a * c;
+struct SamplingParams {
+    numBounces: u32,
+    frameIdx: u32,
+    // One of the SAMPLER_* constants.
+    samplerKind: u32,
+    // One of the DEBUG_* constants.
+    debugMode: u32,
+    // The brightest component of a sample is clamped to this, unless it's 0.
+    maxRadiance: f32,
+}
 
-    if discriminant < 0.0 {
-        return -1.0;
-    } else {
-        return (-half_b - sqrt(discriminant) ) / a;
-    }
+struct Rng {
+    // The PCG state of the independent sampler.
+    state: u32,
+    pixel: vec2<u32>,
+    sampleIdx: u32,
+    // The number of values drawn so far, which is the dimension of the next one.
+    dimension: u32,
+    // Scrambles the Sobol points of the pixel.
+    seed: u32,
 }
 
-// models
 struct Sphere {
     center: vec4<f32>,
+    // xyz is the linear velocity, w the angular velocity around the sphere's y axis.
+    velocity: vec4<f32>,
     radius: f32,
     material_idx: u32,
 }
@@ -101,6 +192,7 @@ struct Sphere {
 struct Ray {
     direction: vec3<f32>,
     origin: vec3<f32>,
+    time: f32,
 }
 
 struct Scatter {
@@ -110,7 +202,12 @@ struct Scatter {
 
 struct Intersection {
     p: vec3<f32>,
+    // The shading normal, which normal and bump maps perturb.
     n: vec3<f32>,
+    // The geometric normal.
+    ng: vec3<f32>,
+    // Points along the lines of latitude, in the direction of increasing u.
+    tangent: vec3<f32>,
     u: f32,
     v: f32,
     t: f32,
@@ -120,9 +217,13 @@ struct Intersection {
 
 struct Material {
     id: u32,
-    desc1: TextureDescriptor,
-    desc2: TextureDescriptor,
+    // What the textures and x mean depends on the id.
+    textures: array<TextureDescriptor, 9>,
     x: f32,
+    // Both maps are empty unless the material is mapped.
+    normalMap: TextureDescriptor,
+    bumpMap: TextureDescriptor,
+    bumpStrength: f32,
 }
 
 struct TextureDescriptor {
@@ -132,16 +233,44 @@ struct TextureDescriptor {
 }
 
 
+fn cameraRay(s: f32, t: f32, rngState: ptr<function, Rng>) -> Ray {
+    // Thin lens model: rays start on the lens and converge on the focus plane, which
+    // contains the viewport.
+    let lensSample = camera.lensRadius * rngNextInAperture(rngState);
+    let offset = lensSample.x * camera.u.xyz + lensSample.y * camera.v.xyz;
+    let origin = camera.eye.xyz + offset;
+    let focusPoint = camera.upperLeftCorner.xyz + s * camera.horizontal.xyz - t * camera.vertical.xyz;
+    let time = mix(camera.shutterOpen, camera.shutterClose, rngNextFloat(rngState));
+
+    return Ray(normalize(focusPoint - origin), origin, time);
+}
+
+fn sphereCenter(sphere: Sphere, time: f32) -> vec3<f32> {
+    return sphere.center.xyz + time * sphere.velocity.xyz;
+}
+
 fn sphereIntersection(ray: Ray, sphere: Sphere, sphere_idx: u32, t: f32) -> Intersection {
     let p = rayPointAtParameter(ray, t);
-    let n = (1f / sphere.radius) * (p - sphere.center.xyz);
+    let n = (1f / sphere.radius) * (p - sphereCenter(sphere, ray.time));
     let theta = acos(-n.y);
-    let phi = atan2(-n.z, n.x) + PI;
-    let u = 0.5 * FRAC_1_PI * phi;
+    // Spinning the sphere around its y axis only shifts the texture coordinates.
+    let phi = atan2(-n.z, n.x) + PI - ray.time * sphere.velocity.w;
+    let u = fract(0.5 * FRAC_1_PI * phi);
     let v = FRAC_1_PI * theta;
 
     // TODO: passing sphereIdx in here just to pass it to Intersection
-    return Intersection(p, n, u, v, t, sphere.material_idx, sphere_idx);
+    return Intersection(p, n, n, sphereTangent(n), u, v, t, sphere.material_idx, sphere_idx);
+}
+
+// The derivative of the position with respect to u, normalized.
+fn sphereTangent(n: vec3<f32>) -> vec3<f32> {
+    let tangent = vec3(n.z, 0f, -n.x);
+    let tangentLength = length(tangent);
+    // The poles have no line of latitude.
+    if tangentLength < EPSILON {
+        return pixarOnb(n)[0];
+    }
+    return tangent / tangentLength;
 }
 
 fn rayPointAtParameter(ray: Ray, t: f32) -> vec3<f32> {
@@ -150,7 +279,7 @@ fn rayPointAtParameter(ray: Ray, t: f32) -> vec3<f32> {
 
 fn rayIntersectSphere(ray: Ray, sphereIdx: u32, tmin: f32, tmax: f32, hit: ptr<function, Intersection>) -> bool {
     let sphere = spheres[sphereIdx];
-    let oc = ray.origin - sphere.center.xyz;
+    let oc = ray.origin - sphereCenter(sphere, ray.time);
     let a = dot(ray.direction, ray.direction);
     let b = dot(oc, ray.direction);
     let c = dot(oc, oc) - sphere.radius * sphere.radius;
@@ -178,6 +307,7 @@ fn intersect(ray: Ray, intersection: ptr<function, Intersection>) -> bool {
     var closestIntersection = Intersection();
 
     for (var idx = 0u; idx < arrayLength(&spheres); idx = idx + 1u) {
+        numIntersectionTests += 1u;
         var testIntersect = Intersection();
         if rayIntersectSphere(ray, idx, MIN_T, closestT, &testIntersect) {
             closestT = testIntersect.t;
@@ -193,26 +323,46 @@ fn intersect(ray: Ray, intersection: ptr<function, Intersection>) -> bool {
     return false;
 }
 
-fn rayColor(primaryRay: Ray, rngState: ptr<function, u32>) -> vec3<f32> {
+fn rayColor(primaryRay: Ray, rngState: ptr<function, Rng>) -> vec3<f32> {
     var ray = primaryRay;
 
     var color = vec3(0f);
     var throughput = vec3(1f);
 
-    for (var bounce = 0u; bounce < 10u; bounce += 1u) {//bounce < samplingParams.numBounces
+    for (var bounce = 0u; bounce < samplingParams.numBounces; bounce += 1u) {
         var intersection = Intersection();
 
+        numRays += 1u;
         if intersect(ray, &intersection) {
             let material = materials[intersection.material_idx];
+            intersection.n = shadingNormal(ray, intersection, material);
+            if bounce == 0u {
+                firstHitAlbedo = materialAlbedo(material, intersection);
+                firstHitNormal = intersection.n;
+                firstHitDepth = intersection.t;
+                firstHitPosition = intersection.p;
+                firstHitMaterialIdx = intersection.material_idx;
+                firstHitSphereIdx = intersection.sphere_idx;
+                firstHitUv = vec2(intersection.u, intersection.v);
+                // These debug modes only show the first hit.
+                if samplingParams.debugMode >= DEBUG_NORMALS && samplingParams.debugMode <= DEBUG_MATERIAL_ID {
+                    break;
+                }
+            }
 
             if material.id == 4u {
-                let emissionTexture = material.desc1;
+                let emissionTexture = material.textures[0];
                 let emissionColor = textureLookup(emissionTexture, intersection.u, intersection.v);
                 color += throughput * emissionColor;
                 break;
             }
 
             var scatter = scatterRay(ray, intersection, material, rngState);
+            // A shading normal can send rays through the surface, which would leak light.
+            let direction = scatter.ray.direction;
+            if dot(direction, intersection.n) * dot(direction, intersection.ng) < 0f {
+                break;
+            }
             ray = scatter.ray;
             throughput *= scatter.throughput;
         } else {
@@ -220,37 +370,160 @@ fn rayColor(primaryRay: Ray, rngState: ptr<function, u32>) -> vec3<f32> {
             let t = 0.5 * (ray.direction.y + 1.0);
             let sky_color = (1.0 - t) * vec3<f32>(1.0, 1.0, 1.0) + t * vec3<f32>(0.5, 0.7, 1.0);
             color += throughput * sky_color;
+            if bounce == 0u {
+                firstHitAlbedo = sky_color;
+                firstHitNormal = vec3(0f);
+                firstHitDepth = MAX_T;
+                firstHitPosition = rayPointAtParameter(ray, MAX_T);
+                firstHitMaterialIdx = NO_HIT;
+                firstHitSphereIdx = NO_HIT;
+            }
             break;
         }
     }
 
+    return debugColor(color);
+}
+
+// Replaces the radiance of the path with what the debug mode shows.
+fn debugColor(color: vec3<f32>) -> vec3<f32> {
+    let mode = samplingParams.debugMode;
+    let hit = firstHitSphereIdx != NO_HIT;
+    if mode == DEBUG_NORMALS {
+        return select(vec3(0f), 0.5f * firstHitNormal + 0.5f, hit);
+    } else if mode == DEBUG_UVS {
+        return select(vec3(0f), vec3(firstHitUv, 0f), hit);
+    } else if mode == DEBUG_MATERIAL_ID {
+        return select(vec3(0f), idColor(firstHitMaterialIdx), hit);
+    } else if mode == DEBUG_BOUNCES {
+        return heatmap(f32(numRays) / f32(samplingParams.numBounces));
+    } else if mode == DEBUG_INTERSECTION_TESTS {
+        // Every segment tests every sphere, there is no acceleration structure yet.
+        let maxTests = samplingParams.numBounces * arrayLength(&spheres);
+        return heatmap(f32(numIntersectionTests) / f32(maxTests));
+    } else if mode == DEBUG_NAN {
+        if isNanOrInf(color) {
+            return vec3(1f, 0f, 1f);
+        }
+        return vec3(0.25f * dot(color, LUMINANCE));
+    }
     return color;
 }
 
-fn scatterRay(wo: Ray, hit: Intersection, material: Material, rngState: ptr<function, u32>) -> Scatter {
+// Blue for 0 over cyan, green and yellow to red for 1.
+fn heatmap(x: f32) -> vec3<f32> {
+    let t = clamp(x, 0f, 1f);
+    return clamp(1.5f - abs(4f * t - vec3(3f, 2f, 1f)), vec3(0f), vec3(1f));
+}
+
+// A random but stable color for every id.
+fn idColor(id: u32) -> vec3<f32> {
+    let hash = pcgHash(id);
+    return vec3(f32(hash & 0xffu), f32((hash >> 8u) & 0xffu), f32((hash >> 16u) & 0xffu)) / 255f;
+}
+
+// Compares the bits, since comparing a NaN with itself may be optimized away.
+fn isNanOrInf(v: vec3<f32>) -> bool {
+    let exponent = bitcast<vec3<u32>>(v) & vec3(0x7f800000u);
+    return any(exponent == vec3(0x7f800000u));
+}
+
+// Perturbs the normal with the normal and bump maps of the material, if any.
+fn shadingNormal(ray: Ray, hit: Intersection, material: Material) -> vec3<f32> {
+    let hasNormalMap = material.normalMap.width != 0u;
+    let hasBumpMap = material.bumpMap.width != 0u;
+    if !hasNormalMap && !hasBumpMap {
+        return hit.n;
+    }
+
+    // A height field with the gradient g has the normal (-g, 1), so the gradients of the
+    // two maps add up.
+    var offset = vec2(0f);
+    if hasNormalMap {
+        let normal = 2f * textureLookup(material.normalMap, hit.u, hit.v) - 1f;
+        offset = normal.xy / max(EPSILON, normal.z);
+    }
+    if hasBumpMap {
+        let map = material.bumpMap;
+        let height = textureLookup(map, hit.u, hit.v).x;
+        // u wraps around the sphere.
+        let heightU = textureLookup(map, fract(hit.u + 1f / f32(map.width)), hit.v).x;
+        let heightV = textureLookup(map, hit.u, hit.v + 1f / f32(map.height)).x;
+        offset -= material.bumpStrength * vec2(heightU - height, heightV - height);
+    }
+    let n = normalize(shadingFrame(hit) * vec3(offset, 1f));
+
+    // The viewer has to be in front of both surfaces.
+    if dot(ray.direction, n) * dot(ray.direction, hit.n) <= 0f {
+        return hit.n;
+    }
+    return n;
+}
+
+// The color that multiplies the incident light, used as the albedo feature.
+fn materialAlbedo(material: Material, hit: Intersection) -> vec3<f32> {
+    switch material.id {
+        case 0u, 1u, 5u: {
+            return textureLookup(material.textures[0], hit.u, hit.v);
+        }
+
+        case 2u: {
+            return vec3(1f);
+        }
+
+        case 3u: {
+            return textureLookup(checkerboardTexture(material, hit), hit.u, hit.v);
+        }
+
+        case 4u: {
+            return min(textureLookup(material.textures[0], hit.u, hit.v), vec3(1f));
+        }
+
+        default: {
+            return vec3(0.5f, 0.7f, 0.9f);
+        }
+    }
+}
+
+fn scatterRay(wo: Ray, hit: Intersection, material: Material, rngState: ptr<function, Rng>) -> Scatter {
     switch material.id {
         case 0u: {
-            let texture = material.desc1;
-            return scatterMixtureDensity(hit, texture, rngState);
+            let texture = material.textures[0];
+            return scatterMixtureDensity(wo, hit, texture, rngState);
         }
 
         case 1u: {
-            let texture = material.desc1;
+            let texture = material.textures[0];
             let fuzz = material.x;
             return scatterMetal(wo, hit, texture, fuzz, rngState);
         }
 
+        case 2u: {
+            let refractionIndex = material.x;
+            return scatterDielectric(wo, hit, refractionIndex, rngState);
+        }
+
+        case 3u: {
+            let texture = checkerboardTexture(material, hit);
+            return scatterMixtureDensity(wo, hit, texture, rngState);
+        }
+
+        case 5u: {
+            let params = principledParams(material, hit);
+            return scatterPrincipled(wo, hit, params, rngState);
+        }
+
         default: {
-            return scatterMissingMaterial(hit, rngState);
+            return scatterMissingMaterial(wo, hit, rngState);
         }
     }
 }
 
-fn scatterMissingMaterial(hit: Intersection, rngState: ptr<function, u32>) -> Scatter {
+fn scatterMissingMaterial(wo: Ray, hit: Intersection, rngState: ptr<function, Rng>) -> Scatter {
     let scatterDirection = hit.n + rngNextVec3InUnitSphere(rngState);
     // An aggressive pink color to indicate an error
     let albedo = vec3(0.5f, 0.7f, 0.9f);
-    return Scatter(Ray(hit.p, scatterDirection), albedo);
+    return Scatter(Ray(scatterDirection, hit.p, wo.time), albedo);
 }
 
 fn textureLookup(desc: TextureDescriptor, u: f32, v: f32) -> vec3<f32> {
@@ -265,20 +538,20 @@ fn textureLookup(desc: TextureDescriptor, u: f32, v: f32) -> vec3<f32> {
     return vec3(elem[0u], elem[1u], elem[2u]);
 }
 
-fn scatterMixtureDensity(hit: Intersection, albedo: TextureDescriptor, rngState: ptr<function, u32>) -> Scatter {
-    let scatterDirection = sampleMixtureDensity(hit, rngState);
+fn scatterMixtureDensity(wo: Ray, hit: Intersection, albedo: TextureDescriptor, rngState: ptr<function, Rng>) -> Scatter {
+    let scatterDirection = sampleMixtureDensity(hit, wo.time, rngState);
     let materialValue = evalLambertian(hit, albedo, scatterDirection);
     let materialPdf = pdfLambertian(hit, scatterDirection);
-    let lightPdf = pdfLight(hit, scatterDirection);
+    let lightPdf = pdfLight(hit, scatterDirection, wo.time);
     let throughput = materialValue / max(EPSILON, (0.5f * materialPdf + 0.5f * lightPdf));
-    return Scatter(Ray(hit.p, scatterDirection), throughput);
+    return Scatter(Ray(scatterDirection, hit.p, wo.time), throughput);
 }
 
-fn sampleMixtureDensity(hit: Intersection, rngState: ptr<function, u32>) -> vec3<f32> {
+fn sampleMixtureDensity(hit: Intersection, time: f32, rngState: ptr<function, Rng>) -> vec3<f32> {
     if rngNextFloat(rngState) < 0.5f {
         return sampleLambertian(hit, rngState);
     } else {
-        return sampleLight(hit, rngState);
+        return sampleLight(hit, time, rngState);
     }
 }
 
@@ -286,7 +559,7 @@ fn evalLambertian(hit: Intersection, texture: TextureDescriptor, wi: vec3<f32>)
     return textureLookup(texture, hit.u, hit.v) * FRAC_1_PI * max(EPSILON, dot(hit.n, wi));
 }
 
-fn sampleLambertian(hit: Intersection, rngState: ptr<function, u32>) -> vec3<f32> {
+fn sampleLambertian(hit: Intersection, rngState: ptr<function, Rng>) -> vec3<f32> {
     let v = rngNextInCosineWeightedHemisphere(rngState);
     let onb = pixarOnb(hit.n);
     return onb * v;
@@ -296,49 +569,71 @@ fn pdfLambertian(hit: Intersection, wi: vec3<f32>) -> f32 {
     return max(EPSILON, dot(hit.n, wi) * FRAC_1_PI);
 }
 
-fn sampleLight(hit: Intersection, rngState: ptr<function, u32>) -> vec3<f32> {
+fn sampleLight(hit: Intersection, time: f32, rngState: ptr<function, Rng>) -> vec3<f32> {
     // Select a random light using a uniform distribution.
-    let numLights = arrayLength(&lights);   // TODO: what about when there are no lights?
-    let lightIdx = rngNextUintInRange(rngState, 0u, numLights - 1u);
+    // The scene is validated to contain at least one light.
+    let numLights = arrayLength(&lights);
+    let lightIdx = rngNextUintInRange(rngState, 0u, numLights);
     let sphereIdx = lights[lightIdx];
     let sphere = spheres[sphereIdx];
 
-    return sampleHemisphere(hit, sphere, rngState);
+    return sampleHemisphere(hit, sphere, time, rngState);
 }
 
-fn sampleHemisphere(hit: Intersection, sphere: Sphere, rngState: ptr<function, u32>) -> vec3<f32> {
+fn sampleHemisphere(hit: Intersection, sphere: Sphere, time: f32, rngState: ptr<function, Rng>) -> vec3<f32> {
     let v = rngNextInUnitHemisphere(rngState);
 
     // Sample the hemisphere facing the intersection point.
-    let dir = normalize(hit.p - sphere.center.xyz);
+    let center = sphereCenter(sphere, time);
+    let dir = normalize(hit.p - center);
     let onb = pixarOnb(dir);
 
-    let pointOnSphere = sphere.center.xyz + onb * sphere.radius * v;
+    let pointOnSphere = center + onb * sphere.radius * v;
     let toPointOnSphere = pointOnSphere - hit.p;
 
     return normalize(toPointOnSphere);
 }
 
-fn pdfLight(hit: Intersection, wi: vec3<f32>) -> f32 {
-    let ray = Ray(hit.p, wi);
-    var lightHit = Intersection();
+// The density of sampleLight in the direction wi. Occluders don't change which points
+// on the lights are sampled, so only the lights are intersected.
+fn pdfLight(hit: Intersection, wi: vec3<f32>, time: f32) -> f32 {
+    let numLights = arrayLength(&lights);
     var pdf = 0f;
 
-    if intersect(ray, &lightHit) {
-        let sphereIdx = lightHit.sphere_idx;
-        let sphere = spheres[sphereIdx];
-        let numSpheres = arrayLength(&spheres);
-        let toLight = lightHit.p - hit.p;
-        let lengthSqr = dot(toLight, toLight);
-        let cosine = abs(dot(wi, lightHit.n));
-        let areaHalfSphere = 2f * PI * sphere.radius * sphere.radius;
+    for (var lightIdx = 0u; lightIdx < numLights; lightIdx += 1u) {
+        let sphere = spheres[lights[lightIdx]];
+        let center = sphereCenter(sphere, time);
+        let oc = hit.p - center;
+        let a = dot(wi, wi);
+        let b = dot(oc, wi);
+        let c = dot(oc, oc) - sphere.radius * sphere.radius;
+        let discriminant = b * b - a * c;
+
+        if discriminant >= 0f {
+            // Both points where the ray crosses the sphere could have been sampled.
+            pdf += pdfLightPoint(hit, wi, sphere, center, (-b - sqrt(discriminant)) / a);
+            pdf += pdfLightPoint(hit, wi, sphere, center, (-b + sqrt(discriminant)) / a);
+        }
+    }
 
-        // lengthSqr / cosine is the inverse of the geometric factor, as defined in
-        // "MULTIPLE IMPORTANCE SAMPLING 101".
-        pdf = lengthSqr / max(EPSILON, cosine * areaHalfSphere * f32(numSpheres));
+    return pdf / f32(numLights);
+}
+
+fn pdfLightPoint(hit: Intersection, wi: vec3<f32>, sphere: Sphere, center: vec3<f32>, t: f32) -> f32 {
+    let toLight = t * wi;
+    let n = (hit.p + toLight - center) / sphere.radius;
+    // Only the hemisphere facing the intersection point is sampled.
+    if t <= MIN_T || dot(n, hit.p - center) <= 0f {
+        return 0f;
     }
 
-    return pdf;
+    let lengthSqr = dot(toLight, toLight);
+    let cosine = abs(dot(normalize(wi), n));
+    let areaHalfSphere = 2f * PI * sphere.radius * sphere.radius;
+
+    // lengthSqr / cosine is the inverse of the geometric factor, as defined in
+    // "MULTIPLE IMPORTANCE SAMPLING 101".
+    return lengthSqr / max(EPSILON, cosine * areaHalfSphere);
 }
 
 fn pixarOnb(n: vec3<f32>) -> mat3x3<f32> {
@@ -352,16 +647,304 @@ fn pixarOnb(n: vec3<f32>) -> mat3x3<f32> {
     return mat3x3<f32>(u, v, n);
 }
 
-fn scatterMetal(wo: Ray, hit: Intersection, texture: TextureDescriptor, fuzz: f32, rngState: ptr<function, u32>) -> Scatter {
+fn scatterMetal(wo: Ray, hit: Intersection, texture: TextureDescriptor, fuzz: f32, rngState: ptr<function, Rng>) -> Scatter {
     let scatterDirection = reflect(wo.direction, hit.n) + fuzz * rngNextVec3InUnitSphere(rngState);
     let albedo = textureLookup(texture, hit.u, hit.v);
-    return Scatter(Ray(scatterDirection, hit.p), albedo);
+    return Scatter(Ray(scatterDirection, hit.p, wo.time), albedo);
+}
+
+// Reflects or refracts at a smooth interface, choosing by the Fresnel term.
+fn scatterDielectric(wo: Ray, hit: Intersection, refractionIndex: f32, rngState: ptr<function, Rng>) -> Scatter {
+    let d = normalize(wo.direction);
+    let entering = dot(d, hit.ng) < 0f;
+    let n = select(-hit.n, hit.n, entering);
+    let eta = select(refractionIndex, 1f / refractionIndex, entering);
+
+    let cosI = min(1f, dot(-d, n));
+    let sin2T = eta * eta * max(0f, 1f - cosI * cosI);
+    let cosT = sqrt(max(0f, 1f - sin2T));
+    let fresnel = select(fresnelDielectric(cosI, cosT, eta), 1f, sin2T >= 1f);
+
+    var scatterDirection: vec3<f32>;
+    if rngNextFloat(rngState) < fresnel {
+        scatterDirection = reflect(d, n);
+    } else {
+        scatterDirection = eta * d + (eta * cosI - cosT) * n;
+    }
+    return Scatter(Ray(scatterDirection, hit.p, wo.time), vec3(1f));
+}
+
+// Alternates between the even and odd texture in a 3D checker pattern.
+fn checkerboardTexture(material: Material, hit: Intersection) -> TextureDescriptor {
+    let sines = sin(10f * hit.p.x) * sin(10f * hit.p.y) * sin(10f * hit.p.z);
+    if sines < 0f {
+        return material.textures[1];
+    }
+    return material.textures[0];
+}
+
+// The principled BSDF, after Burley, "Physically Based Shading at Disney", 2012.
+
+struct PrincipledParams {
+    baseColor: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    specularTint: f32,
+    clearcoat: f32,
+    sheen: f32,
+    transmission: f32,
+    anisotropy: f32,
+    refractionIndex: f32,
+}
+
+// Keeps the specular lobes from becoming singular.
+const MIN_ALPHA = 0.001f;
+// Neither is a parameter of the material.
+const SHEEN_TINT = 0.5f;
+const CLEARCOAT_ALPHA = 0.01f;
+
+fn principledParams(material: Material, hit: Intersection) -> PrincipledParams {
+    return PrincipledParams(
+        textureLookup(material.textures[0], hit.u, hit.v),
+        textureLookup(material.textures[1], hit.u, hit.v).x,
+        textureLookup(material.textures[2], hit.u, hit.v).x,
+        textureLookup(material.textures[3], hit.u, hit.v).x,
+        textureLookup(material.textures[4], hit.u, hit.v).x,
+        textureLookup(material.textures[5], hit.u, hit.v).x,
+        textureLookup(material.textures[6], hit.u, hit.v).x,
+        textureLookup(material.textures[7], hit.u, hit.v).x,
+        textureLookup(material.textures[8], hit.u, hit.v).x,
+        material.x,
+    );
+}
+
+// The tangent of the hit, made orthogonal to the shading normal, and the normal as the
+// z axis. The bitangent points up in the images of textures.
+fn shadingFrame(hit: Intersection) -> mat3x3<f32> {
+    let t = normalize(hit.tangent - dot(hit.tangent, hit.n) * hit.n);
+    return mat3x3<f32>(t, cross(hit.n, t), hit.n);
+}
+
+// With the probability of the transmission the material is a rough dielectric, otherwise
+// the opaque part of the BSDF is sampled together with the lights, like the Lambertian.
+fn scatterPrincipled(wo: Ray, hit: Intersection, params: PrincipledParams, rngState: ptr<function, Rng>) -> Scatter {
+    let transmissionWeight = (1f - params.metallic) * params.transmission;
+    if rngNextFloat(rngState) < transmissionWeight {
+        return scatterRoughDielectric(wo, hit, params, rngState);
+    }
+
+    let frame = shadingFrame(hit);
+    let toLocal = transpose(frame);
+    let v = toLocal * -wo.direction;
+    var scatterDirection: vec3<f32>;
+    if rngNextFloat(rngState) < 0.5f {
+        scatterDirection = frame * samplePrincipled(params, v, rngState);
+    } else {
+        scatterDirection = sampleLight(hit, wo.time, rngState);
+    }
+
+    let l = toLocal * scatterDirection;
+    let materialValue = evalPrincipled(params, v, l);
+    let materialPdf = pdfPrincipled(params, v, l);
+    let lightPdf = pdfLight(hit, scatterDirection, wo.time);
+    let throughput = materialValue / max(EPSILON, 0.5f * materialPdf + 0.5f * lightPdf);
+    return Scatter(Ray(scatterDirection, hit.p, wo.time), throughput);
+}
+
+// The alphas of the specular lobe along the tangent and the bitangent.
+fn anisotropicAlpha(params: PrincipledParams) -> vec2<f32> {
+    let aspect = sqrt(1f - 0.9f * params.anisotropy);
+    let alpha = params.roughness * params.roughness;
+    return max(vec2(MIN_ALPHA), vec2(alpha / aspect, alpha * aspect));
+}
+
+// The probabilities of sampling the diffuse, specular and clearcoat lobes.
+fn lobeWeights(params: PrincipledParams) -> vec3<f32> {
+    let weights = vec3(1f - params.metallic, 1f, 0.25f * params.clearcoat);
+    return weights / (weights.x + weights.y + weights.z);
+}
+
+// v and l are in the shading frame and point away from the surface. Returns the BSDF
+// times the cosine of l.
+fn evalPrincipled(params: PrincipledParams, v: vec3<f32>, l: vec3<f32>) -> vec3<f32> {
+    if v.z <= 0f || l.z <= 0f {
+        return vec3(0f);
+    }
+    let h = normalize(l + v);
+    let lDotH = dot(l, h);
+
+    let luminance = dot(params.baseColor, LUMINANCE);
+    let tint = select(vec3(1f), params.baseColor / luminance, luminance > 0f);
+    let specularColor = mix(
+        params.specular * 0.08f * mix(vec3(1f), tint, params.specularTint),
+        params.baseColor,
+        params.metallic,
+    );
+    let sheenColor = mix(vec3(1f), tint, SHEEN_TINT);
+
+    // Diffuse with retroreflection at grazing angles.
+    let fl = schlickWeight(l.z);
+    let fv = schlickWeight(v.z);
+    let fd90 = 0.5f + 2f * lDotH * lDotH * params.roughness;
+    let fd = mix(1f, fd90, fl) * mix(1f, fd90, fv);
+
+    let fh = schlickWeight(lDotH);
+    let fSheen = fh * params.sheen * sheenColor;
+
+    let alpha = anisotropicAlpha(params);
+    let ds = gtr2Anisotropic(h, alpha);
+    let fs = mix(specularColor, vec3(1f), fh);
+    let gs = smithGAnisotropic(l, alpha) * smithGAnisotropic(v, alpha);
+
+    let dr = gtr1(h.z, CLEARCOAT_ALPHA);
+    let fr = mix(0.04f, 1f, fh);
+    let gr = smithGGgx(l.z, 0.25f) * smithGGgx(v.z, 0.25f);
+
+    let diffuse = (FRAC_1_PI * fd * params.baseColor + fSheen) * (1f - params.metallic);
+    let f = diffuse + gs * ds * fs + vec3(0.25f * params.clearcoat * gr * fr * dr);
+    return f * l.z;
+}
+
+fn samplePrincipled(params: PrincipledParams, v: vec3<f32>, rngState: ptr<function, Rng>) -> vec3<f32> {
+    let weights = lobeWeights(params);
+    let lobe = rngNextFloat(rngState);
+    if lobe < weights.x {
+        return rngNextInCosineWeightedHemisphere(rngState);
+    }
+
+    let r1 = rngNextFloat(rngState);
+    let r2 = rngNextFloat(rngState);
+    var h: vec3<f32>;
+    if lobe < weights.x + weights.y {
+        h = sampleGgx(anisotropicAlpha(params), r1, r2);
+    } else {
+        h = sampleGtr1(CLEARCOAT_ALPHA, r1, r2);
+    }
+    return reflect(-v, h);
+}
+
+fn pdfPrincipled(params: PrincipledParams, v: vec3<f32>, l: vec3<f32>) -> f32 {
+    if v.z <= 0f || l.z <= 0f {
+        return 0f;
+    }
+    let weights = lobeWeights(params);
+    let h = normalize(l + v);
+    // The density of the half vector, transformed to the density of the reflection.
+    let jacobian = 1f / max(EPSILON, 4f * dot(v, h));
+
+    let diffusePdf = l.z * FRAC_1_PI;
+    let specularPdf = gtr2Anisotropic(h, anisotropicAlpha(params)) * h.z * jacobian;
+    let clearcoatPdf = gtr1(h.z, CLEARCOAT_ALPHA) * h.z * jacobian;
+    return weights.x * diffusePdf + weights.y * specularPdf + weights.z * clearcoatPdf;
+}
+
+// Samples a microfacet normal, rough reflection or refraction through it depending on
+// the Fresnel term. Refraction is tinted by the square root of the base color, so that
+// passing through a sphere tints by the base color.
+fn scatterRoughDielectric(wo: Ray, hit: Intersection, params: PrincipledParams, rngState: ptr<function, Rng>) -> Scatter {
+    let v = -wo.direction;
+    let entering = dot(v, hit.ng) > 0f;
+    let n = select(-hit.n, hit.n, entering);
+    let eta = select(params.refractionIndex, 1f / params.refractionIndex, entering);
+
+    let alpha = max(MIN_ALPHA, params.roughness * params.roughness);
+    let r1 = rngNextFloat(rngState);
+    let r2 = rngNextFloat(rngState);
+    let h = pixarOnb(n) * sampleGgx(vec2(alpha), r1, r2);
+
+    let cosI = dot(v, h);
+    let sin2T = eta * eta * max(0f, 1f - cosI * cosI);
+    let cosT = sqrt(max(0f, 1f - sin2T));
+    let fresnel = select(fresnelDielectric(cosI, cosT, eta), 1f, sin2T >= 1f);
+
+    var scatterDirection: vec3<f32>;
+    var tint = vec3(1f);
+    if rngNextFloat(rngState) < fresnel {
+        scatterDirection = reflect(-v, h);
+    } else {
+        scatterDirection = eta * -v + (eta * cosI - cosT) * h;
+        tint = sqrt(params.baseColor);
+    }
+
+    // Walter et al., "Microfacet Models for Refraction through Rough Surfaces", eq. 41.
+    let cosL = dot(scatterDirection, n);
+    let reflected = dot(scatterDirection, hit.ng) * dot(v, hit.ng) > 0f;
+    let valid = cosI > 0f && (dot(scatterDirection, h) > 0f) == reflected;
+    let g = smithG1Ggx(dot(v, n), alpha) * smithG1Ggx(abs(cosL), alpha);
+    let weight = abs(cosI) * g / max(EPSILON, dot(v, n) * dot(h, n));
+    return Scatter(Ray(scatterDirection, hit.p, wo.time), select(vec3(0f), weight * tint, valid));
+}
+
+fn fresnelDielectric(cosI: f32, cosT: f32, eta: f32) -> f32 {
+    let rs = (eta * cosI - cosT) / (eta * cosI + cosT);
+    let rp = (eta * cosT - cosI) / (eta * cosT + cosI);
+    return 0.5f * (rs * rs + rp * rp);
+}
+
+fn schlickWeight(cosine: f32) -> f32 {
+    let m = clamp(1f - cosine, 0f, 1f);
+    let m2 = m * m;
+    return m2 * m2 * m;
+}
+
+fn gtr1(nDotH: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let t = 1f + (alpha2 - 1f) * nDotH * nDotH;
+    return (alpha2 - 1f) / (PI * log(alpha2) * t);
+}
+
+fn gtr2Anisotropic(h: vec3<f32>, alpha: vec2<f32>) -> f32 {
+    let x = h.x / alpha.x;
+    let y = h.y / alpha.y;
+    let t = x * x + y * y + h.z * h.z;
+    return 1f / (PI * alpha.x * alpha.y * t * t);
+}
+
+// Burley's form of the Smith term, which includes the 1 / (4 n.l n.v) of the BRDF.
+fn smithGAnisotropic(w: vec3<f32>, alpha: vec2<f32>) -> f32 {
+    let x = w.x * alpha.x;
+    let y = w.y * alpha.y;
+    return 1f / (w.z + sqrt(x * x + y * y + w.z * w.z));
+}
+
+fn smithGGgx(nDotV: f32, alpha: f32) -> f32 {
+    let a = alpha * alpha;
+    let b = nDotV * nDotV;
+    return 1f / (nDotV + sqrt(a + b - a * b));
+}
+
+// The usual masking function, without the 1 / (2 n.v).
+fn smithG1Ggx(nDotV: f32, alpha: f32) -> f32 {
+    let cos2 = max(EPSILON, nDotV * nDotV);
+    let tan2 = (1f - cos2) / cos2;
+    return 2f / (1f + sqrt(1f + alpha * alpha * tan2));
+}
+
+// Samples a half vector proportional to D(h) h.z in the shading frame.
+fn sampleGgx(alpha: vec2<f32>, r1: f32, r2: f32) -> vec3<f32> {
+    let phi = atan2(alpha.y * sin(2f * PI * r1), alpha.x * cos(2f * PI * r1));
+    let cosPhi = cos(phi);
+    let sinPhi = sin(phi);
+    let invAlpha2 = cosPhi * cosPhi / (alpha.x * alpha.x) + sinPhi * sinPhi / (alpha.y * alpha.y);
+    let tan2Theta = r2 / ((1f - r2) * invAlpha2);
+    let cosTheta = 1f / sqrt(1f + tan2Theta);
+    let sinTheta = sqrt(max(0f, 1f - cosTheta * cosTheta));
+    return vec3(sinTheta * cosPhi, sinTheta * sinPhi, cosTheta);
+}
+
+fn sampleGtr1(alpha: f32, r1: f32, r2: f32) -> vec3<f32> {
+    let alpha2 = alpha * alpha;
+    let cosTheta = sqrt(max(0f, (1f - pow(alpha2, 1f - r2)) / (1f - alpha2)));
+    let sinTheta = sqrt(max(0f, 1f - cosTheta * cosTheta));
+    let phi = 2f * PI * r1;
+    return vec3(sinTheta * cos(phi), sinTheta * sin(phi), cosTheta);
 }
 
 
 // random number generation
 
-fn rngNextInCosineWeightedHemisphere(state: ptr<function, u32>) -> vec3<f32> {
+fn rngNextInCosineWeightedHemisphere(state: ptr<function, Rng>) -> vec3<f32> {
     let r1 = rngNextFloat(state);
     let r2 = rngNextFloat(state);
     let sqrt_r2 = sqrt(r2);
@@ -374,7 +957,7 @@ fn rngNextInCosineWeightedHemisphere(state: ptr<function, u32>) -> vec3<f32> {
     return vec3<f32>(x, y, z);
 }
 
-fn rngNextInUnitHemisphere(state: ptr<function, u32>) -> vec3<f32> {
+fn rngNextInUnitHemisphere(state: ptr<function, Rng>) -> vec3<f32> {
     let r1 = rngNextFloat(state);
     let r2 = rngNextFloat(state);
 
@@ -388,7 +971,7 @@ fn rngNextInUnitHemisphere(state: ptr<function, u32>) -> vec3<f32> {
     return vec3(x, y, z);
 }
 
-fn rngNextVec3InUnitDisk(state: ptr<function, u32>) -> vec3<f32> {
+fn rngNextVec3InUnitDisk(state: ptr<function, Rng>) -> vec3<f32> {
     // Generate numbers uniformly in a disk:
     // https://stats.stackexchange.com/a/481559
 
@@ -402,42 +985,145 @@ fn rngNextVec3InUnitDisk(state: ptr<function, u32>) -> vec3<f32> {
     return vec3(x, y, 0f);
 }
 
-fn rngNextVec3InUnitSphere(state: ptr<function, u32>) -> vec3<f32> {
+fn rngNextInAperture(state: ptr<function, Rng>) -> vec2<f32> {
+    let numBlades = camera.apertureBlades;
+    if numBlades < 3u {
+        return rngNextVec3InUnitDisk(state).xy;
+    }
+
+    // The aperture is a regular polygon inscribed in the unit circle. Pick one of the
+    // triangles spanned by the center and two adjacent corners, then sample it uniformly.
+    let blade = rngNextUintInRange(state, 0u, numBlades);
+    let angleStep = 2f * PI / f32(numBlades);
+    let alpha = camera.apertureRotation + f32(blade) * angleStep;
+    let p0 = vec2(cos(alpha), sin(alpha));
+    let p1 = vec2(cos(alpha + angleStep), sin(alpha + angleStep));
+
+    var r1 = rngNextFloat(state);
+    var r2 = rngNextFloat(state);
+    if r1 + r2 > 1f {
+        r1 = 1f - r1;
+        r2 = 1f - r2;
+    }
+
+    return r1 * p0 + r2 * p1;
+}
+
+fn rngNextVec3InUnitSphere(state: ptr<function, Rng>) -> vec3<f32> {
     // probability density is uniformly distributed over r^3
     let r = pow(rngNextFloat(state), 0.33333f);
-    let theta = PI * rngNextFloat(state);
+    // cos(theta) is uniformly distributed, since all zones of equal height have the same area
+    let cosTheta = 1f - 2f * rngNextFloat(state);
+    let sinTheta = sqrt(max(0f, 1f - cosTheta * cosTheta));
     let phi = 2f * PI * rngNextFloat(state);
 
-    let x = r * sin(theta) * cos(phi);
-    let y = r * sin(theta) * sin(phi);
-    let z = r * cos(theta);
+    let x = r * sinTheta * cos(phi);
+    let y = r * sinTheta * sin(phi);
+    let z = r * cosTheta;
 
     return vec3(x, y, z);
 }
 
-fn rngNextUintInRange(state: ptr<function, u32>, min: u32, max: u32) -> u32 {
-    rngNextInt(state);
-    return min + (*state) % (max - min);
+fn rngNextUintInRange(state: ptr<function, Rng>, min: u32, max: u32) -> u32 {
+    let range = max - min;
+    if samplingParams.samplerKind == SAMPLER_INDEPENDENT {
+        (*state).dimension += 1u;
+        rngNextInt(state);
+        return min + (*state).state % range;
+    }
+
+    // The remainder would only keep the low bits, which aren't stratified.
+    let idx = u32(rngNextFloat(state) * f32(range));
+    if idx >= range && range > 0u {
+        return max - 1u;
+    }
+    return min + idx;
 }
 
-fn rngNextFloat(state: ptr<function, u32>) -> f32 {
+fn rngNextFloat(state: ptr<function, Rng>) -> f32 {
+    let dimension = (*state).dimension;
+    (*state).dimension += 1u;
+
+    if samplingParams.samplerKind == SAMPLER_SOBOL {
+        return sobolOwen((*state).sampleIdx, dimension, (*state).seed);
+    }
+    if samplingParams.samplerKind == SAMPLER_BLUE_NOISE {
+        // Every pixel uses the same points, shifted by the blue noise mask.
+        return fract(sobolOwen((*state).sampleIdx, dimension, 0u) + blueNoise((*state).pixel, dimension));
+    }
+
     rngNextInt(state);
-    return f32(*state) / f32(0xffffffffu);
+    return f32((*state).state) / f32(0xffffffffu);
 }
 
-fn initRng(pixel: vec2<u32>, resolution: vec2<u32>, frame: u32) -> u32 {
+fn initRng(pixel: vec2<u32>, resolution: vec2<u32>, frame: u32) -> Rng {
     // Adapted from https://github.com/boksajak/referencePT
-    let seed = dot(pixel, vec2<u32>(1u, resolution.x)) ^ jenkinsHash(frame);
-    return jenkinsHash(seed);
+    let pixelIdx = dot(pixel, vec2<u32>(1u, resolution.x));
+    let seed = pixelIdx ^ jenkinsHash(frame);
+    return Rng(jenkinsHash(seed), pixel, frame, 0u, pcgHash(pixelIdx));
 }
 
-fn rngNextInt(state: ptr<function, u32>) {
+fn rngNextInt(state: ptr<function, Rng>) {
     // PCG random number generator
     // Based on https://www.shadertoy.com/view/XlGcRh
 
-    let oldState = *state + 747796405u + 2891336453u;
+    let oldState = (*state).state + 747796405u + 2891336453u;
     let word = ((oldState >> ((oldState >> 28u) + 4u)) ^ oldState) * 277803737u;
-    *state = (word >> 22u) ^ word;
+    (*state).state = (word >> 22u) ^ word;
+}
+
+fn sobolOwen(index: u32, dimension: u32, seed: u32) -> f32 {
+    // Padded Sobol: the dimensions are grouped into points of the first Sobol dimensions,
+    // and each group uses its own shuffle of the sample indices.
+    // Burley, "Practical Hash-based Owen Scrambling", https://jcgt.org/published/0009/04/01/
+    let group = dimension / SOBOL_DIMENSIONS;
+    let component = dimension % SOBOL_DIMENSIONS;
+    let groupSeed = hashCombine(seed, pcgHash(group));
+    let shuffled = nestedUniformScramble(index, groupSeed);
+    let x = nestedUniformScramble(sobol(shuffled, component), hashCombine(groupSeed, component));
+    return f32(x >> 8u) / 16777216f;
+}
+
+fn sobol(index: u32, dimension: u32) -> u32 {
+    var x = 0u;
+    var i = index;
+    var bit = 0u;
+    while i != 0u {
+        if (i & 1u) != 0u {
+            x ^= samplerTables[dimension * 32u + bit];
+        }
+        i >>= 1u;
+        bit += 1u;
+    }
+    return x;
+}
+
+fn nestedUniformScramble(input: u32, seed: u32) -> u32 {
+    var x = reverseBits(input) + seed;
+    x ^= x * 0x6c50b47cu;
+    x ^= x * 0xb82f1e52u;
+    x ^= x * 0xc7afe638u;
+    x ^= x * 0x8d22f6e6u;
+    return reverseBits(x);
+}
+
+fn blueNoise(pixel: vec2<u32>, dimension: u32) -> f32 {
+    // Each dimension reads the tiled mask at a different offset, so that they aren't correlated.
+    let offset = pcgHash(dimension);
+    let x = (pixel.x + offset) % BLUE_NOISE_SIZE;
+    let y = (pixel.y + (offset >> 16u)) % BLUE_NOISE_SIZE;
+    let rank = samplerTables[BLUE_NOISE_OFFSET + y * BLUE_NOISE_SIZE + x];
+    return (f32(rank) + 0.5) / f32(BLUE_NOISE_SIZE * BLUE_NOISE_SIZE);
+}
+
+fn pcgHash(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn hashCombine(seed: u32, v: u32) -> u32 {
+    return seed ^ (v + 0x9e3779b9u + (seed << 6u) + (seed >> 2u));
 }
 
 fn jenkinsHash(input: u32) -> u32 {
@@ -1,38 +1,214 @@
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use winit::event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::window::Window;
 
-use crate::scene::{Material, GpuMaterial};
+use crate::aov::{AovChannel, AovImage};
+use crate::camera::{Camera, CameraController};
+use crate::camera_path::CameraPath;
+use crate::denoiser::{Denoiser, DenoiserTargets};
+use crate::cli::Args;
+use crate::file_watcher::FileWatcher;
+use crate::pipeline_cache::PipelineCache;
+use crate::shader_watcher::ShaderWatcher;
+use crate::gizmo::{GizmoVertex, GizmoView};
+use crate::gpu_scene::GpuScene;
+use crate::gpu_timer::{GpuTimer, GpuTimings, TimedPass};
+use crate::path_tracer::{PathTracer, RenderStats, RenderTargets};
+use crate::screenshot::{self, HdrImage};
 use crate::{fps_counter::FpsCounter, scene::Scene};
-use crate::gui_app::GuiApp;
-use crate::gpu_buffer::StorageBuffer;
-use crate::sphere::Sphere;
+use crate::gui_app::{FrameStats, GuiApp};
+use crate::gpu_buffer::{AsyncReadback, UniformBuffer};
 use egui_wgpu_backend::{RenderPass, ScreenDescriptor};
 use egui_winit_platform::{Platform, PlatformDescriptor};
 
+/// In debug builds the kernel is read from this path instead of the embedded copy, and
+/// reloaded whenever it changes.
+const RAY_TRACING_KERNEL_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/ray_tracing_kernel.wgsl");
+
+#[derive(Error, Debug)]
+pub enum RendererError {
+    #[error("failed to create a surface for the window: {0}")]
+    CreateSurface(#[from] wgpu::CreateSurfaceError),
+    #[error("no {backend} GPU adapter found")]
+    NoAdapter { backend: String },
+    #[error("adapter {idx} doesn't exist, the available adapters are:{available}")]
+    InvalidAdapterIndex { idx: usize, available: String },
+    #[error("{adapter} can't present to the window")]
+    CantPresent { adapter: String },
+    #[error("{adapter} doesn't support {missing}")]
+    UnsupportedFeatures { adapter: String, missing: String },
+    #[error("the {limit} limit of {adapter} is {supported}, but {required} is needed")]
+    LimitsTooLow {
+        adapter: String,
+        limit: &'static str,
+        supported: u64,
+        required: u64,
+    },
+    #[error("failed to create the device: {0}")]
+    RequestDevice(#[from] wgpu::RequestDeviceError),
+    #[error("present mode {mode:?} is not supported, the surface supports {supported:?}")]
+    UnsupportedPresentMode {
+        mode: wgpu::PresentMode,
+        supported: Vec<wgpu::PresentMode>,
+    },
+    #[error(transparent)]
+    Surface(#[from] wgpu::SurfaceError),
+    #[error("failed to draw the GUI: {0}")]
+    Gui(#[from] egui_wgpu_backend::BackendError),
+    #[error("the GPU device was lost")]
+    DeviceLost,
+}
+
+/// Requests a device with the features and limits the kernel needs, and whichever of the
+/// optional features the adapter supports.
+pub async fn request_device(
+    adapter: &wgpu::Adapter,
+    optional_features: wgpu::Features,
+) -> Result<(wgpu::Device, wgpu::Queue), RendererError> {
+    let name = adapter.get_info().name;
+    if !adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+    {
+        return Err(RendererError::UnsupportedFeatures {
+            adapter: name,
+            missing: "compute shaders".to_string(),
+        });
+    }
+
+    let limits = if cfg!(target_arch = "wasm32") {
+        wgpu::Limits::downlevel_webgl2_defaults()
+    } else {
+        wgpu::Limits::default()
+    };
+    let mut too_low = None;
+    limits.check_limits_with_fail_fn(&adapter.limits(), true, |limit, required, supported| {
+        too_low = Some((limit, required, supported));
+    });
+    if let Some((limit, required, supported)) = too_low {
+        return Err(RendererError::LimitsTooLow {
+            adapter: name,
+            limit,
+            supported,
+            required,
+        });
+    }
+
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features: adapter.features() & optional_features,
+                limits,
+                label: Some("Device"),
+            },
+            None,
+        )
+        .await?;
+    Ok((device, queue))
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ScreenParams {
+    channel: u32,
+    width: u32,
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineParams {
+    sphere: [f32; 4],
+    color: [f32; 4],
+    screen_height: f32,
+    width: f32,
+    _padding: [f32; 2],
+}
+
+/// An image sequence being rendered along a camera path, one frame per call to `render`
+/// so that the window stays responsive.
+struct SequenceRender {
+    path: CameraPath,
+    /// The viewport camera, which provides the lens and shutter settings of every frame.
+    camera: Camera,
+    fps: f32,
+    samples: u32,
+    dir: PathBuf,
+    next_frame: u32,
+    num_frames: u32,
+}
+
 pub struct Renderer {
-    pub window: Window,
+    pub window: Rc<Window>,
+    /// The adapter selection, kept to create the device again after it was lost.
+    args: Args,
 
     //instance: wgpu::Instance,
     //adapter: wgpu::Adapter,
     device: wgpu::Device,
-    surface: wgpu::Surface,
-    surface_format: wgpu::TextureFormat,
-    storage_format: wgpu::TextureFormat,
+    /// Set by the error handler of the device once it reports that the device was lost.
+    device_lost: Arc<AtomicBool>,
+    /// Only `None` while the device is created again, or after that failed.
+    surface: Option<wgpu::Surface>,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
 
-    color_buffer: wgpu::Texture,
-    color_buffer_view: wgpu::TextureView,
+    render_targets: RenderTargets,
     sampler: wgpu::Sampler,
-
-    ray_tracing_pipeline: wgpu::ComputePipeline,
-    ray_tracing_bind_group: wgpu::BindGroup,
-    screen_pipeline: wgpu::RenderPipeline,
+    screen_bind_group_layout: wgpu::BindGroupLayout,
+    /// Which channel the screen pass shows.
+    screen_params_buffer: UniformBuffer,
     screen_bind_group: wgpu::BindGroup,
+    denoiser_targets: DenoiserTargets,
+    /// Shows the denoised image instead of the accumulated one.
+    denoised_screen_bind_group: wgpu::BindGroup,
+
+    pipeline_cache: PipelineCache,
+    kernel_watcher: Option<ShaderWatcher>,
+    path_tracer: PathTracer,
+    denoiser: Denoiser,
+    screen_pipeline: Rc<wgpu::RenderPipeline>,
+    outline_pipeline: Rc<wgpu::RenderPipeline>,
+    gizmo_pipeline: Rc<wgpu::RenderPipeline>,
 
     //scene stuff
-    scene_bind_group: wgpu::BindGroup,
-    scene_bind_group_layout: wgpu::BindGroupLayout,
+    scene: Scene,
+    scene_path: PathBuf,
+    scene_watcher: FileWatcher,
+    gpu_scene: GpuScene,
+
+    //camera and sampling stuff
+    camera: Camera,
+    camera_controller: CameraController,
+    outline_buffer: UniformBuffer,
+    outline_bind_group: wgpu::BindGroup,
+    gizmo_vertex_buffer: wgpu::Buffer,
+    frame_idx: u32,
+    /// When the last frame started rendering, for the frame rate cap.
+    last_frame: Instant,
+    /// Set by input and by egui when the GUI has to be drawn again.
+    redraw_requested: bool,
+    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    mouse_pressed: bool,
+    sequence: Option<SequenceRender>,
+
+    //statistics
+    gpu_timer: Option<GpuTimer>,
+    gpu_timings: Option<GpuTimings>,
+    stats_readback: AsyncReadback,
+    /// Rays traced per pixel in the last frame the counters were read back from.
+    rays_per_pixel: Option<f32>,
+    /// NaN or infinite samples and clamped samples since the accumulation restarted.
+    outlier_samples: Option<(u32, u32)>,
 
     //egui stuff
     fps_counter: FpsCounter,
@@ -42,81 +218,52 @@ pub struct Renderer {
 }
 
 impl Renderer {
-    pub async fn new(window: Window, scene: Scene) -> Self {
+    /// Sets up the GPU with the backend, adapter and present mode selected on the
+    /// command line. Fails if they aren't available.
+    pub async fn new(window: Rc<Window>, scene: Scene, scene_path: PathBuf, args: &Args) -> Result<Self, RendererError> {
         // Create the instance, adapter, device, and queue, and setup the surface
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
+            backends: args.backends(),
             dx12_shader_compiler: Default::default(),
         });
 
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
-
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                    label: Some("Device"),
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let surface = unsafe { instance.create_surface(window.as_ref()) }?;
+
+        let adapter = args.request_adapter(&instance, Some(&surface)).await?;
+        log::info!("Using {} ({:?})", adapter.get_info().name, adapter.get_info().backend);
+
+        // Timestamps are only used for the GPU timings in the overlay, which are left out without them.
+        let (device, queue) = request_device(&adapter, wgpu::Features::TIMESTAMP_QUERY).await?;
+
+        // Without a handler wgpu panics on the first error of a lost device.
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost = device_lost.clone();
+        device.on_uncaptured_error(Box::new(move |err| handle_uncaptured_error(&lost, err)));
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
             .formats
             .iter()
             .copied()
-            .filter(|f| f.is_srgb())
-            .next()
+            .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
+            present_mode: args.present_mode(&surface_caps.present_modes)?,
+            alpha_mode: if surface_caps.alpha_modes.contains(&wgpu::CompositeAlphaMode::Opaque) {
+                wgpu::CompositeAlphaMode::Opaque
+            } else {
+                surface_caps.alpha_modes[0]
+            },
             view_formats: vec![],
         };
         surface.configure(&device, &config);
 
-        let storage_format = wgpu::TextureFormat::Rgba8Unorm;
-
-        // Create the color buffer and sampler
-        let color_buffer = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Color Buffer"),
-            size: wgpu::Extent3d {
-                width: size.width,
-                height: size.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: storage_format,
-            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[storage_format],
-        });
-
-        let color_buffer_view = color_buffer.create_view(&wgpu::TextureViewDescriptor::default());
-
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("Color Buffer Sampler"),
             address_mode_u: wgpu::AddressMode::Repeat,
@@ -132,140 +279,9 @@ impl Renderer {
             border_color: None,
         });
 
-        // Create pipelines
-        let ray_tracing_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Ray Tracing Bind Group Layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                    format: storage_format,
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                },
-                count: None,
-            }],
-        });
-
-        let ray_tracing_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Ray Tracing Bind Group"),
-            layout: &ray_tracing_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&color_buffer_view),
-            }],
-        });
-
-
-        // scene stuff (buffers and bind groups)
-        let (scene_bind_group_layout, scene_bind_group) = {
-            let sphere_buffer = StorageBuffer::new_from_bytes(
-                &device,
-                bytemuck::cast_slice(scene.spheres.as_slice()),
-                0_u32,
-                Some("scene buffer"),
-            );
-
-            let mut global_texture_data: Vec<[f32; 3]> = Vec::new();
-            let mut material_data: Vec<GpuMaterial> = Vec::with_capacity(scene.materials.len());
-
-            for material in scene.materials.iter() {
-                let gpu_material = match material {
-                    Material::Lambertian { albedo } => {
-                        GpuMaterial::lambertian(albedo, &mut global_texture_data)
-                    }
-                    Material::Metal { albedo, fuzz } => {
-                        GpuMaterial::metal(albedo, *fuzz, &mut global_texture_data)
-                    }
-                    Material::Dielectric { refraction_index } => {
-                        GpuMaterial::dielectric(*refraction_index)
-                    }
-                    Material::Checkerboard { odd, even } => {
-                        GpuMaterial::checkerboard(odd, even, &mut global_texture_data)
-                    }
-                    Material::Emissive { emit } => {
-                        GpuMaterial::emissive(emit, &mut global_texture_data)
-                    }
-                };
-
-                material_data.push(gpu_material);
-            }
-
-            let material_buffer = StorageBuffer::new_from_bytes(
-                &device,
-                bytemuck::cast_slice(material_data.as_slice()),
-                1_u32,
-                Some("materials buffer"),
-            );
-
-            let texture_buffer = StorageBuffer::new_from_bytes(
-                &device,
-                bytemuck::cast_slice(global_texture_data.as_slice()),
-                2_u32,
-                Some("textures buffer"),
-            );
-
-            let light_indices: Vec<u32> = scene
-                .spheres
-                .iter()
-                .enumerate()
-                .filter(|(_, s)| {
-                    matches!(
-                        scene.materials[s.material_idx as usize],
-                        Material::Emissive { .. }
-                    )
-                })
-                .map(|(idx, _)| idx as u32)
-                .collect();
-
-            let light_buffer = StorageBuffer::new_from_bytes(
-                &device,
-                bytemuck::cast_slice(light_indices.as_slice()),
-                3_u32,
-                Some("lights buffer"),
-            );
-
-            let scene_bind_group_layout =
-                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        sphere_buffer.layout(wgpu::ShaderStages::COMPUTE, true),
-                        material_buffer.layout(wgpu::ShaderStages::COMPUTE, true),
-                        texture_buffer.layout(wgpu::ShaderStages::COMPUTE, true),
-                        light_buffer.layout(wgpu::ShaderStages::COMPUTE, true),
-                    ],
-                    label: Some("scene layout"),
-                });
-            let scene_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &scene_bind_group_layout,
-                entries: &[
-                    sphere_buffer.binding(),
-                    material_buffer.binding(),
-                    texture_buffer.binding(),
-                    light_buffer.binding(),
-                ],
-                label: Some("scene bind group"),
-            });
-
-            (scene_bind_group_layout, scene_bind_group)
-        };
-
-
-        let ray_tracing_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Ray Tracing Pipeline Layout"),
-            bind_group_layouts: &[&ray_tracing_bind_group_layout, &scene_bind_group_layout],
-            push_constant_ranges: &[],
-        });
-
-        let ray_tracing_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Ray Tracing Pipeline"),
-            layout: Some(&ray_tracing_pipeline_layout),
-            module: &device.create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some("Ray Tracing Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("ray_tracing_kernel.wgsl").into()),
-            }),
-            entry_point: "main",
-        });
-
+        // Bind group layouts (and the pipelines built from them) don't depend on the
+        // window size, so they are created once and only the bind groups are recreated
+        // on resize.
         let screen_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Screen Bind Group Layout"),
             entries: &[wgpu::BindGroupLayoutEntry {
@@ -283,120 +299,441 @@ impl Renderer {
                     multisampled: false,
                 },
                 count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             }],
         });
+        let screen_params_buffer = UniformBuffer::new(
+            &device,
+            std::mem::size_of::<ScreenParams>() as wgpu::BufferAddress,
+            2_u32,
+            Some("screen params buffer"),
+        );
 
-        let screen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Screen Bind Group"),
-            layout: &screen_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Sampler(&sampler),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::TextureView(&color_buffer_view),
-            }],
+        // scene stuff (buffers and bind groups)
+        let gpu_scene = GpuScene::new(&device, &scene);
+        let scene_watcher = FileWatcher::new(Self::scene_dependencies(&scene_path, &scene));
+
+        let mut pipeline_cache = PipelineCache::new();
+        let mut gui_app = GuiApp::new();
+        gui_app.present_modes.extend(&surface_caps.present_modes);
+        gui_app.present_mode = config.present_mode;
+        gui_app.max_fps = args.max_fps;
+        gui_app.max_samples = args.max_samples;
+        gui_app.on_demand = args.on_demand;
+        gui_app.sampler = args.sampler;
+        gui_app.denoise = args.denoise;
+        gui_app.max_radiance = args.max_radiance;
+
+        let mut path_tracer = PathTracer::new(&device, &mut pipeline_cache, &gpu_scene);
+        let kernel_watcher = cfg!(debug_assertions).then(|| ShaderWatcher::new(RAY_TRACING_KERNEL_PATH));
+        if let Some(kernel_watcher) = &kernel_watcher {
+            let loaded = kernel_watcher.load().and_then(|source| {
+                path_tracer
+                    .set_kernel(&device, &mut pipeline_cache, &source)
+                    .map_err(|err| err.to_string())
+            });
+            if let Err(err) = loaded {
+                log::warn!("Falling back to the embedded kernel:\n{}", err);
+                gui_app.shader_error = Some(err);
+            }
+        }
+
+        let denoiser = Denoiser::new(&device, &mut pipeline_cache);
+
+        let render_targets = path_tracer.create_targets(&device, size, gui_app.aov_channel != AovChannel::Beauty);
+        let denoiser_targets = denoiser.create_targets(&device, &render_targets);
+        let screen_bind_group = Self::create_screen_bind_group(
+            &device,
+            &screen_bind_group_layout,
+            &sampler,
+            &screen_params_buffer,
+            &render_targets,
+            &render_targets.color_buffer_view,
+        );
+        let denoised_screen_bind_group = Self::create_screen_bind_group(
+            &device,
+            &screen_bind_group_layout,
+            &sampler,
+            &screen_params_buffer,
+            &render_targets,
+            &denoiser_targets.display_view,
+        );
+
+        // camera, updated every frame
+        let camera = Camera::default();
+        let camera_controller = CameraController::new(4.0, 0.4);
+
+        // The selection outline reads the same camera as the kernel.
+        let outline_buffer = UniformBuffer::new(
+            &device,
+            std::mem::size_of::<OutlineParams>() as wgpu::BufferAddress,
+            1_u32,
+            Some("outline buffer"),
+        );
+        let outline_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    path_tracer.camera_buffer().layout(wgpu::ShaderStages::FRAGMENT),
+                    outline_buffer.layout(wgpu::ShaderStages::FRAGMENT),
+                ],
+                label: Some("outline layout"),
+            });
+        let outline_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &outline_bind_group_layout,
+            entries: &[path_tracer.camera_buffer().binding(), outline_buffer.binding()],
+            label: Some("outline bind group"),
         });
 
+        // Create pipelines
         let screen_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Screen Pipeline Layout"),
             bind_group_layouts: &[&screen_bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Screen Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("screen_shader.wgsl").into()),
+        let outline_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[&outline_bind_group_layout],
+            push_constant_ranges: &[],
         });
 
-        let screen_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Screen Pipeline"),
-            layout: Some(&screen_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader_module,
-                entry_point: "vert_main",
-                buffers: &[],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_module,
-                entry_point: "frag_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
+        let gizmo_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Gizmo Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        let gizmo_vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            size: (GizmoVertex::MAX_VERTICES * std::mem::size_of::<GizmoVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            label: Some("gizmo vertex buffer"),
         });
 
+        let screen_pipeline = pipeline_cache
+            .fullscreen_pipeline(
+                &device,
+                "Screen Pipeline",
+                &screen_pipeline_layout,
+                include_str!("screen_shader.wgsl"),
+                surface_format,
+            )
+            .expect("The embedded screen shader should be valid");
+
+        let outline_pipeline = pipeline_cache
+            .fullscreen_pipeline(
+                &device,
+                "Outline Pipeline",
+                &outline_pipeline_layout,
+                include_str!("outline_shader.wgsl"),
+                surface_format,
+            )
+            .expect("The embedded outline shader should be valid");
+
+        let gizmo_pipeline = pipeline_cache
+            .overlay_pipeline(
+                &device,
+                "Gizmo Pipeline",
+                &gizmo_pipeline_layout,
+                include_str!("gizmo_shader.wgsl"),
+                surface_format,
+                GizmoVertex::layout(),
+            )
+            .expect("The embedded gizmo shader should be valid");
+
+        let gpu_timer = GpuTimer::new(&device, &queue);
+        if gpu_timer.is_none() {
+            log::info!("Timestamp queries are not supported, GPU timings are disabled");
+        }
+        let stats_readback = AsyncReadback::new(
+            &device,
+            std::mem::size_of::<RenderStats>() as wgpu::BufferAddress,
+            Some("render stats readback buffer"),
+        );
+
         // egui stuff
         let fps_counter = FpsCounter::new();
         let platform: Platform = Platform::new(PlatformDescriptor {
-            physical_width: size.width as u32,
-            physical_height: size.height as u32,
+            physical_width: size.width,
+            physical_height: size.height,
             scale_factor: window.scale_factor(),
             font_definitions: egui::FontDefinitions::default(),
             style: Default::default(),
         });
-        let gui_app = GuiApp::new();
         let egui_renderpass = RenderPass::new(&device, surface_format, 1);
 
-        Renderer {
+        Ok(Renderer {
             window,
+            args: args.clone(),
+            device_lost,
             //adapter,
-            surface_format,
-            storage_format,
             //instance,
-            surface,
+            surface: Some(surface),
             device,
             queue,
             config,
             size,
-            color_buffer,
-            color_buffer_view,
+            render_targets,
             sampler,
-            ray_tracing_bind_group,
-            ray_tracing_pipeline,
+            screen_bind_group_layout,
+            screen_params_buffer,
             screen_bind_group,
+            denoiser_targets,
+            denoised_screen_bind_group,
+            pipeline_cache,
+            kernel_watcher,
+            path_tracer,
+            denoiser,
             screen_pipeline,
+            outline_pipeline,
+            gizmo_pipeline,
             fps_counter,
             platform,
             gui_app,
             egui_renderpass,
-            scene_bind_group,
-            scene_bind_group_layout,
+            scene,
+            scene_path,
+            scene_watcher,
+            gpu_scene,
+            camera,
+            camera_controller,
+            outline_buffer,
+            outline_bind_group,
+            gizmo_vertex_buffer,
+            frame_idx: 0_u32,
+            last_frame: Instant::now(),
+            redraw_requested: true,
+            cursor_position: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            mouse_pressed: false,
+            sequence: None,
+            gpu_timer,
+            gpu_timings: None,
+            stats_readback,
+            rays_per_pixel: None,
+            outlier_samples: None,
+        })
+    }
+
+    /// Collects the statistics for the overlay, picking up GPU readbacks that arrived.
+    /// Once converged no rays are traced, and the counters of the last traced frame are kept.
+    fn frame_stats(&mut self, traced: bool) -> FrameStats {
+        if let Some(timings) = self.gpu_timer.as_mut().and_then(|timer| timer.try_read(&self.device)) {
+            self.gpu_timings = Some(timings);
+        }
+        if let Some(stats) = self.stats_readback.try_read::<RenderStats>(&self.device) {
+            let num_pixels = self.size.width as f32 * self.size.height as f32;
+            self.rays_per_pixel = Some(stats[0].num_rays as f32 / num_pixels);
+            self.outlier_samples = Some((stats[0].num_discarded, stats[0].num_clamped));
+        }
+
+        // Without timestamps the ray tracing time is approximated by the frame time.
+        let ray_tracing_time = self
+            .gpu_timings
+            .map_or(self.fps_counter.average_frame_time(), |timings| timings.ray_tracing);
+        let num_pixels = self.size.width as f32 * self.size.height as f32;
+
+        FrameStats {
+            fps: self.fps_counter.average_fps(),
+            frame_time: self.fps_counter.average_frame_time(),
+            frame_times: self.fps_counter.frame_times().collect(),
+            gpu_timings: self.gpu_timings,
+            samples_per_pixel: self.frame_idx,
+            rays_per_second: self
+                .rays_per_pixel
+                .filter(|_| traced)
+                .map(|rays| rays * num_pixels / ray_tracing_time),
+            // Every sample is one path, so the rays per pixel are the average path length.
+            average_path_length: self.rays_per_pixel,
+            discarded_samples: self.outlier_samples.map(|(discarded, _)| discarded),
+            clamped_samples: self
+                .outlier_samples
+                .filter(|_| self.gui_app.max_radiance.is_some())
+                .map(|(_, clamped)| clamped),
         }
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+    /// Creates a bind group for showing `view` or the AOVs of the targets.
+    fn create_screen_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        params_buffer: &UniformBuffer,
+        targets: &RenderTargets,
+        view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Screen Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            params_buffer.binding(),
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: targets.aov_buffer.handle().as_entire_binding(),
+            }],
+        })
+    }
+
+    /// Whether the sample limit was reached and dispatching more work would change nothing.
+    fn is_converged(&self) -> bool {
+        self.gui_app.max_samples.is_some_and(|max_samples| self.frame_idx >= max_samples)
+    }
+
+    /// Returns when the next frame should be rendered, or `None` if it would look the same
+    /// as the last one and the GUI only renders on demand.
+    pub fn next_frame_time(&self) -> Option<Instant> {
+        let dirty = self.redraw_requested || !self.is_converged() || self.sequence.is_some() || self.gui_app.playing;
+        if self.gui_app.on_demand && !dirty {
+            return None;
+        }
+        let min_frame_time = self
+            .gui_app
+            .max_fps
+            .map_or(Duration::ZERO, |max_fps| Duration::from_secs_f32(1.0 / max_fps as f32));
+        Some(self.last_frame + min_frame_time)
+    }
+
+    fn surface(&self) -> &wgpu::Surface {
+        self.surface.as_ref().expect("The surface only goes away with the device")
+    }
+
+    fn check_device(&self) -> Result<(), RendererError> {
+        if self.device_lost.load(Ordering::Relaxed) {
+            Err(RendererError::DeviceLost)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Creates the device and everything on it again after it was lost, and uploads the
+    /// scene. The scene edits, the camera and the GUI settings are kept.
+    pub fn recover(&mut self) -> Result<(), RendererError> {
+        // A window can only have one swapchain, so the old surface has to be gone before the
+        // new device configures its own.
+        self.surface = None;
+        let mut renderer = pollster::block_on(Self::new(
+            self.window.clone(),
+            self.scene.clone(),
+            self.scene_path.clone(),
+            &self.args,
+        ))?;
+
+        renderer.camera = self.camera.clone();
+        std::mem::swap(&mut renderer.camera_controller, &mut self.camera_controller);
+        std::mem::swap(&mut renderer.fps_counter, &mut self.fps_counter);
+        renderer.sequence = self.sequence.take();
+        renderer.cursor_position = self.cursor_position;
+        // The GUI context stays new, since its font texture lived on the old device.
+        let present_modes = std::mem::take(&mut renderer.gui_app.present_modes);
+        std::mem::swap(&mut renderer.gui_app, &mut self.gui_app);
+        renderer.gui_app.present_modes = present_modes;
+        if renderer.gui_app.present_mode != renderer.config.present_mode {
+            if renderer.gui_app.present_modes.contains(&renderer.gui_app.present_mode) {
+                renderer.config.present_mode = renderer.gui_app.present_mode;
+                renderer.surface().configure(&renderer.device, &renderer.config);
+            } else {
+                renderer.gui_app.present_mode = renderer.config.present_mode;
+            }
+        }
+
+        *self = renderer;
+        log::info!("Recreated the GPU device");
+        Ok(())
+    }
+
+    /// Renders and presents a frame. After `RendererError::DeviceLost` the renderer has
+    /// to be recovered before it can render again.
+    pub fn render(&mut self) -> Result<(), RendererError> {
+        // Submitting to a lost device panics, so nothing is recorded once it's gone.
+        self.check_device()?;
+        let output = self.surface().get_current_texture()?;
+        self.last_frame = Instant::now();
+        self.redraw_requested = false;
+        let texture_view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // The overlays read the camera even when no more samples are traced.
+        let aspect = self.size.width as f32 / self.size.height as f32;
+        self.path_tracer
+            .write_frame_data(
+                &self.queue,
+                &self.camera,
+                aspect,
+                self.frame_idx,
+                self.gui_app.sampler,
+                self.gui_app.debug_mode,
+                self.gui_app.max_radiance,
+            );
+
+        self.queue.write_buffer(
+            self.screen_params_buffer.handle(),
+            0,
+            bytemuck::bytes_of(&ScreenParams {
+                channel: self.gui_app.aov_channel as u32,
+                width: self.size.width,
+                _padding: [0; 2],
+            }),
+        );
+
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
 
-        {
-            let mut ray_trace_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Ray Tracing Pass"),
-            });
-            ray_trace_pass.set_pipeline(&self.ray_tracing_pipeline);
-            ray_trace_pass.set_bind_group(0, &self.ray_tracing_bind_group, &[]);
-            ray_trace_pass.set_bind_group(1, &self.scene_bind_group, &[]);
-            ray_trace_pass.dispatch_workgroups(self.size.width, self.size.height, 1);
+        // The ray count is per frame, the outlier counts add up over the accumulation.
+        let traced = !self.is_converged();
+        if traced {
+            let num_rays_size = wgpu::BufferSize::new(std::mem::size_of::<u32>() as u64);
+            let stats_clear_size = if self.frame_idx == 0 { None } else { num_rays_size };
+            encoder.clear_buffer(self.render_targets.stats_buffer.handle(), 0, stats_clear_size);
+        }
+        if let Some(timer) = &self.gpu_timer {
+            timer.begin(&mut encoder, TimedPass::RayTracing);
+        }
+        if traced {
+            self.path_tracer.encode_pass(&mut encoder, &self.render_targets, &self.gpu_scene);
+            self.frame_idx += 1;
+        }
+        if let Some(timer) = &self.gpu_timer {
+            timer.end(&mut encoder, TimedPass::RayTracing);
+            timer.begin(&mut encoder, TimedPass::Denoise);
+        }
+        let show_denoised = self.gui_app.denoise && self.frame_idx > 0;
+        if show_denoised {
+            self.denoiser.encode(
+                &mut encoder,
+                &self.queue,
+                &self.denoiser_targets,
+                self.frame_idx,
+                &self.gui_app.denoise_settings,
+            );
+        }
+        if let Some(timer) = &self.gpu_timer {
+            timer.end(&mut encoder, TimedPass::Denoise);
+            timer.begin(&mut encoder, TimedPass::Screen);
         }
-
-        let output = self.surface.get_current_texture()?;
-        let texture_view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Screen Pass"),
@@ -416,15 +753,98 @@ impl Renderer {
                 depth_stencil_attachment: None,
             });
             render_pass.set_pipeline(&self.screen_pipeline);
-            render_pass.set_bind_group(0, &self.screen_bind_group, &[]);
+            let screen_bind_group = if show_denoised { &self.denoised_screen_bind_group } else { &self.screen_bind_group };
+            render_pass.set_bind_group(0, screen_bind_group, &[]);
             render_pass.draw(0..6, 0..1);
         }
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.end(&mut encoder, TimedPass::Screen);
+            timer.resolve(&mut encoder);
+        }
+        if traced {
+            self.stats_readback.copy_from(&mut encoder, self.render_targets.stats_buffer.handle());
+        }
+        if let Some(sphere) = self.gui_app.selected_sphere.map(|idx| self.scene.spheres[idx]) {
+            let center = sphere.center_at(self.camera.shutter_open);
+            self.queue.write_buffer(
+                self.outline_buffer.handle(),
+                0,
+                bytemuck::bytes_of(&OutlineParams {
+                    sphere: [center.x, center.y, center.z, sphere.radius],
+                    color: [1.0, 0.65, 0.0, 1.0],
+                    screen_height: self.size.height as f32,
+                    width: 2.0,
+                    _padding: [0.0; 2],
+                }),
+            );
+
+            let mut outline_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Outline Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &texture_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            outline_pass.set_pipeline(&self.outline_pipeline);
+            outline_pass.set_bind_group(0, &self.outline_bind_group, &[]);
+            outline_pass.draw(0..6, 0..1);
+            drop(outline_pass);
+
+            let vertices = self.gui_app.gizmo.vertices(&GizmoView::new(&self.camera, self.size), &sphere);
+            self.queue.write_buffer(&self.gizmo_vertex_buffer, 0, bytemuck::cast_slice(vertices.as_slice()));
+
+            let mut gizmo_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Gizmo Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &texture_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            gizmo_pass.set_pipeline(&self.gizmo_pipeline);
+            gizmo_pass.set_vertex_buffer(0, self.gizmo_vertex_buffer.slice(..));
+            gizmo_pass.draw(0..vertices.len() as u32, 0..1);
+        }
         // egui render pass
         
         self.platform.begin_frame();
-        self.gui_app.ui(&self.platform.context(), self.fps_counter.average_fps(), self.fps_counter.average_frame_time());
+        let stats = self.frame_stats(traced);
+        let changes = self.gui_app.ui(
+            &self.platform.context(),
+            &stats,
+            &mut self.camera,
+            &mut self.scene,
+            &self.scene_path,
+        );
+        if changes.camera || changes.sampler || changes.debug_mode || changes.max_radiance {
+            self.reset_accumulation();
+        }
+        if changes.aov_channel {
+            // The AOVs are only written into targets that have room for them, and only
+            // by the first sample.
+            self.create_targets();
+            self.reset_accumulation();
+        }
+        if changes.scene {
+            self.upload_scene();
+        }
+        if let Some(path) = changes.save_to {
+            self.save_scene(path);
+        }
+
 
         let full_output = self.platform.end_frame(Some(&self.window));
+        // egui asks for another frame while it animates or reacts to a click.
+        self.redraw_requested |= full_output.repaint_after.is_zero();
         let paint_jobs = self.platform.context().tessellate(full_output.shapes);
 
         let screen_descriptor = ScreenDescriptor {
@@ -433,7 +853,7 @@ impl Renderer {
             scale_factor: self.window.scale_factor() as f32,
         };
         let tdelta: egui::TexturesDelta = full_output.textures_delta;
-        self.egui_renderpass.add_textures(&self.device, &self.queue, &tdelta).expect("Failed to add textures");
+        self.egui_renderpass.add_textures(&self.device, &self.queue, &tdelta)?;
         self.egui_renderpass.update_buffers(&self.device, &self.queue, &paint_jobs, &screen_descriptor);
         self.egui_renderpass.execute(
             &mut encoder,
@@ -441,12 +861,40 @@ impl Renderer {
             &paint_jobs,
             &screen_descriptor,
             None
-        ).unwrap();
+        )?;
 
+        self.check_device()?;
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.map();
+        }
+        self.stats_readback.map();
 
-        self.egui_renderpass.remove_textures(tdelta).expect("Failed to remove textures");
+        self.egui_renderpass.remove_textures(tdelta)?;
+        self.check_device()?;
+
+        // Exports reuse the frame uniforms, so they have to wait until the frame is submitted.
+        if changes.screenshot {
+            self.screenshot();
+        }
+        if changes.render_still {
+            self.render_still(self.gui_app.still_scale, self.gui_app.still_samples);
+        }
+        if changes.export_aovs {
+            self.export_aovs(self.gui_app.still_scale, self.gui_app.still_samples);
+        }
+        if changes.render_sequence {
+            self.start_sequence();
+        }
+        if changes.present_mode {
+            self.config.present_mode = self.gui_app.present_mode;
+            self.surface().configure(&self.device, &self.config);
+        }
+        if changes.cancel_sequence {
+            self.finish_sequence(Err("Cancelled the sequence".to_string()));
+        }
+        self.render_sequence_frame();
 
         Ok(())
     }
@@ -455,145 +903,528 @@ impl Renderer {
         self.size = new_size;
         self.config.width = new_size.width;
         self.config.height = new_size.height;
-        self.surface.configure(&self.device, &self.config);
+        // Without a surface the device is lost for good, and the size is only kept.
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
 
-        // Create a new color buffer with the new size
-        self.color_buffer = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Color Buffer"),
-            size: wgpu::Extent3d {
-                width: self.size.width,
-                height: self.size.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: self.storage_format,
-            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[self.storage_format],
-        });
+        // Only the size dependent resources need to be recreated, the pipelines stay valid.
+        self.create_targets();
+        self.reset_accumulation();
+    }
 
-        self.color_buffer_view = self.color_buffer.create_view(&wgpu::TextureViewDescriptor::default());
+    /// Recreates the render targets and everything that refers to them.
+    fn create_targets(&mut self) {
+        let aovs = self.gui_app.aov_channel != AovChannel::Beauty;
+        self.render_targets = self.path_tracer.create_targets(&self.device, self.size, aovs);
+        self.denoiser_targets = self.denoiser.create_targets(&self.device, &self.render_targets);
+        self.screen_bind_group = Self::create_screen_bind_group(
+            &self.device,
+            &self.screen_bind_group_layout,
+            &self.sampler,
+            &self.screen_params_buffer,
+            &self.render_targets,
+            &self.render_targets.color_buffer_view,
+        );
+        self.denoised_screen_bind_group = Self::create_screen_bind_group(
+            &self.device,
+            &self.screen_bind_group_layout,
+            &self.sampler,
+            &self.screen_params_buffer,
+            &self.render_targets,
+            &self.denoiser_targets.display_view,
+        );
+    }
 
-        // Create pipelines
+    pub fn update(&mut self, _delta_time: f32) {
+        self.fps_counter.update(_delta_time);
+        //println!("FPS: {}", self.fps_counter.average_fps());
 
-        let ray_tracing_bind_group_layout = self.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Ray Tracing Bind Group Layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                    format: self.storage_format,
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                },
-                count: None,
-            }],
-        });
+        let dt = std::time::Duration::from_secs_f32(_delta_time);
+        if self.camera_controller.update_camera(&mut self.camera, dt) {
+            self.reset_accumulation();
+        }
 
-        self.ray_tracing_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Ray Tracing Bind Group"),
-            layout: &ray_tracing_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&self.color_buffer_view),
-            }],
-        });
+        if self.gui_app.playing {
+            let duration = self.gui_app.camera_path.duration();
+            self.gui_app.playhead = (self.gui_app.playhead + _delta_time).min(duration);
+            self.gui_app.playing = self.gui_app.playhead < duration;
+            if let Some(keyframe) = self.gui_app.camera_path.sample(self.gui_app.playhead) {
+                keyframe.apply(&mut self.camera);
+                self.reset_accumulation();
+            }
+        }
 
-        let ray_tracing_pipeline_layout = self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Ray Tracing Pipeline Layout"),
-            bind_group_layouts: &[&ray_tracing_bind_group_layout, &self.scene_bind_group_layout],
-            push_constant_ranges: &[],
-        });
+        if self.scene_watcher.poll() {
+            self.reload_scene();
+        }
 
-        self.ray_tracing_pipeline = self.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Ray Tracing Pipeline"),
-            layout: Some(&ray_tracing_pipeline_layout),
-            module: &self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some("Ray Tracing Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("ray_tracing_kernel.wgsl").into()),
-            }),
-            entry_point: "main",
-        });
+        let kernel_update = self.kernel_watcher.as_mut().and_then(ShaderWatcher::poll);
+        match kernel_update {
+            Some(Ok(source)) => self.reload_ray_tracing_kernel(&source),
+            Some(Err(err)) => {
+                log::warn!("Failed to reload the ray tracing kernel:\n{}", err);
+                self.gui_app.shader_error = Some(err);
+            }
+            None => {}
+        }
+    }
 
-        let screen_bind_group_layout = self.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Screen Bind Group Layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Texture {
-                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    multisampled: false,
-                },
-                count: None,
-            }],
-        });
+    /// Switches the ray tracing pipeline to validated WGSL source. The previous pipeline
+    /// keeps running if the new one can't be created.
+    fn reload_ray_tracing_kernel(&mut self, source: &str) {
+        match self.path_tracer.set_kernel(&self.device, &mut self.pipeline_cache, source) {
+            Ok(()) => {
+                log::info!("Reloaded the ray tracing kernel");
+                self.gui_app.shader_error = None;
+                self.reset_accumulation();
+            }
+            Err(err) => {
+                log::warn!("Failed to create the ray tracing pipeline: {}", err);
+                self.gui_app.shader_error = Some(err.to_string());
+            }
+        }
+    }
 
-        self.screen_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Screen Bind Group"),
-            layout: &screen_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Sampler(&self.sampler),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::TextureView(&self.color_buffer_view),
-            }],
-        });
+    /// Loads the scene file again and replaces the scene resources. If the file can't be
+    /// loaded, the error is shown in the GUI and the last good scene stays on screen.
+    fn reload_scene(&mut self) {
+        match Scene::load(&self.scene_path) {
+            Ok(scene) => {
+                log::info!("Reloaded scene {}", self.scene_path.display());
+                self.gpu_scene.update(&self.device, &self.queue, &scene);
+                self.scene_watcher.watch(Self::scene_dependencies(&self.scene_path, &scene));
+                self.scene = scene;
+                self.gui_app.scene_error = None;
+                self.gui_app.selected_sphere = self.gui_app.selected_sphere.filter(|&idx| idx < self.scene.spheres.len());
+                self.reset_accumulation();
+            }
+            Err(err) => {
+                log::warn!("Failed to reload scene {}: {}", self.scene_path.display(), err);
+                self.gui_app.scene_error = Some(err.to_string());
+            }
+        }
+    }
 
-        let screen_pipeline_layout = self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Screen Pipeline Layout"),
-            bind_group_layouts: &[&screen_bind_group_layout],
-            push_constant_ranges: &[],
+    /// Saves the samples accumulated so far as a PNG and an EXR, denoised if the viewport is.
+    pub fn screenshot(&mut self) {
+        let image = if self.gui_app.denoise && self.frame_idx > 0 {
+            self.denoiser.denoise_image(
+                &self.device,
+                &self.queue,
+                &self.denoiser_targets,
+                self.frame_idx,
+                &self.gui_app.denoise_settings,
+            )
+        } else {
+            HdrImage::read_accumulation(
+                &self.device,
+                &self.queue,
+                self.render_targets.accumulation_buffer.handle(),
+                self.size,
+                self.frame_idx,
+            )
+        };
+        self.save_image(&image, "screenshot");
+    }
+
+    /// Renders the current view offscreen at `scale` times the window resolution with
+    /// `samples` samples per pixel, and saves it like a screenshot.
+    pub fn render_still(&mut self, scale: u32, samples: u32) {
+        let size = winit::dpi::PhysicalSize::new(scale * self.size.width, scale * self.size.height);
+        log::info!("Rendering a {}x{} still with {} samples per pixel", size.width, size.height, samples);
+
+        match self.render_offscreen(size, samples) {
+            Ok(image) => self.save_image(&image, "still"),
+            Err(err) => self.gui_app.export_status = Some(Err(err)),
+        }
+    }
+
+    /// Renders the current view offscreen like `render_still`, and saves it with its AOVs
+    /// as a multilayer EXR.
+    pub fn export_aovs(&mut self, scale: u32, samples: u32) {
+        let size = winit::dpi::PhysicalSize::new(scale * self.size.width, scale * self.size.height);
+        log::info!("Rendering a {}x{} still with AOVs and {} samples per pixel", size.width, size.height, samples);
+
+        let path = screenshot::next_screenshot_path("aovs").with_extension("exr");
+        let saved = self.render_offscreen_with_aovs(size, samples, true).and_then(|(image, aov_image)| {
+            let aov_image = aov_image.expect("The AOVs were requested");
+            std::fs::create_dir_all(path.parent().expect("The path is in the screenshot directory"))
+                .map_err(|err| err.to_string())?;
+            aov_image.save_exr(&image, &path).map_err(|err| err.to_string())
         });
-        
-        let shader_module = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Screen Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("screen_shader.wgsl").into()),
+        self.gui_app.export_status = Some(match saved {
+            Ok(()) => {
+                log::info!("Saved {}", path.display());
+                Ok(format!("Saved {}", path.display()))
+            }
+            Err(err) => {
+                log::warn!("Failed to export the AOVs: {}", err);
+                Err(format!("Failed to export the AOVs: {}", err))
+            }
         });
+    }
 
-        self.screen_pipeline = self.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Screen Pipeline"),
-            layout: Some(&screen_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader_module,
-                entry_point: "vert_main",
-                buffers: &[],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_module,
-                entry_point: "frag_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: self.surface_format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
+    /// Renders the current view into a separate accumulation buffer of the given size, and
+    /// denoises it if the viewport is denoised.
+    fn render_offscreen(&self, size: winit::dpi::PhysicalSize<u32>, samples: u32) -> Result<HdrImage, String> {
+        self.render_offscreen_with_aovs(size, samples, false).map(|(image, _)| image)
+    }
+
+    /// Like `render_offscreen`, and also reads back the AOVs if `aovs` is set.
+    fn render_offscreen_with_aovs(
+        &self,
+        size: winit::dpi::PhysicalSize<u32>,
+        samples: u32,
+        aovs: bool,
+    ) -> Result<(HdrImage, Option<AovImage>), String> {
+        let targets = self.path_tracer.accumulate(
+            &self.device,
+            &self.queue,
+            &self.gpu_scene,
+            &self.camera,
+            size,
+            samples,
+            self.gui_app.sampler,
+            self.gui_app.max_radiance,
+            aovs,
+        )?;
+        let image = if self.gui_app.denoise {
+            let denoiser_targets = self.denoiser.create_targets(&self.device, &targets);
+            self.denoiser.denoise_image(
+                &self.device,
+                &self.queue,
+                &denoiser_targets,
+                samples,
+                &self.gui_app.denoise_settings,
+            )
+        } else {
+            HdrImage::read_accumulation(&self.device, &self.queue, targets.accumulation_buffer.handle(), size, samples)
+        };
+        let aov_image = aovs.then(|| AovImage::read(&self.device, &self.queue, targets.aov_buffer.handle(), size));
+        Ok((image, aov_image))
+    }
+
+    fn start_sequence(&mut self) {
+        let path = self.gui_app.camera_path.clone();
+        let fps = self.gui_app.sequence_fps;
+        let num_frames = (path.duration() * fps).floor() as u32 + 1;
+        log::info!("Rendering {} frames to {}", num_frames, self.gui_app.sequence_dir);
+
+        self.sequence = Some(SequenceRender {
+            path,
+            camera: self.camera.clone(),
+            fps,
+            samples: self.gui_app.sequence_samples,
+            dir: PathBuf::from(&self.gui_app.sequence_dir),
+            next_frame: 0,
+            num_frames,
         });
+        self.gui_app.playing = false;
+        self.gui_app.sequence_progress = Some((0, num_frames));
     }
 
-    pub fn update(&mut self, _delta_time: f32) {
-        self.fps_counter.update(_delta_time);
-        //println!("FPS: {}", self.fps_counter.average_fps());
+    /// Renders and saves the next frame of the sequence, if one is being rendered.
+    fn render_sequence_frame(&mut self) {
+        let Some(mut sequence) = self.sequence.take() else {
+            return;
+        };
+
+        // Scene time runs with the path, so moving spheres move across the frames.
+        let time = sequence.next_frame as f32 / sequence.fps;
+        let mut camera = sequence.camera.clone();
+        if let Some(keyframe) = sequence.path.sample(time) {
+            keyframe.apply(&mut camera);
+        }
+        camera.shutter_open += time;
+        camera.shutter_close += time;
+
+        let viewport_camera = std::mem::replace(&mut self.camera, camera);
+        let image = self.render_offscreen(self.size, sequence.samples);
+        self.camera = viewport_camera;
+
+        let path = sequence.dir.join(format!("frame_{:05}.png", sequence.next_frame));
+        let saved = image.and_then(|image| {
+            std::fs::create_dir_all(&sequence.dir).map_err(|err| err.to_string())?;
+            image.save_png(&path, self.gui_app.exposure).map_err(|err| err.to_string())
+        });
+        if let Err(err) = saved {
+            self.finish_sequence(Err(format!("Failed to render {}: {}", path.display(), err)));
+            return;
+        }
+
+        sequence.next_frame += 1;
+        self.gui_app.sequence_progress = Some((sequence.next_frame, sequence.num_frames));
+        if sequence.next_frame == sequence.num_frames {
+            self.finish_sequence(Ok(format!("Saved {} frames to {}", sequence.num_frames, sequence.dir.display())));
+        } else {
+            self.sequence = Some(sequence);
+        }
+    }
+
+    fn finish_sequence(&mut self, status: Result<String, String>) {
+        match &status {
+            Ok(message) => log::info!("{}", message),
+            Err(message) => log::warn!("{}", message),
+        }
+        self.sequence = None;
+        self.gui_app.sequence_progress = None;
+        self.gui_app.sequence_status = Some(status);
+    }
+
+    fn save_image(&mut self, image: &HdrImage, prefix: &str) {
+        match image.save(screenshot::next_screenshot_path(prefix), self.gui_app.exposure) {
+            Ok([png_path, exr_path]) => {
+                log::info!("Saved {} and {}", png_path.display(), exr_path.display());
+                self.gui_app.export_status = Some(Ok(format!("Saved {}", png_path.with_extension("").display())));
+            }
+            Err(err) => {
+                log::warn!("Failed to save the image: {}", err);
+                self.gui_app.export_status = Some(Err(format!("Failed to save the image: {}", err)));
+            }
+        }
+    }
+
+    /// Saves the scene and makes the file it was saved to the one that is watched and
+    /// saved to from now on.
+    fn save_scene(&mut self, path: PathBuf) {
+        match self.scene.save(&path) {
+            Ok(()) => {
+                log::info!("Saved scene to {}", path.display());
+                self.scene_path = path;
+                // Watching the file again skips the reload our own write would trigger.
+                self.scene_watcher.watch(Self::scene_dependencies(&self.scene_path, &self.scene));
+                self.gui_app.save_error = None;
+            }
+            Err(err) => {
+                log::warn!("Failed to save scene to {}: {}", path.display(), err);
+                self.gui_app.save_error = Some(format!("Failed to save {}: {}", path.display(), err));
+            }
+        }
+    }
+
+    /// Returns the scene file and the texture files it references.
+    fn scene_dependencies(scene_path: &Path, scene: &Scene) -> Vec<PathBuf> {
+        let mut paths = vec![scene_path.to_path_buf()];
+        paths.extend(scene.texture_paths());
+        paths
+    }
+
+    /// Uploads the scene after it was edited in the GUI. Edits that break the scene
+    /// invariants are kept in the editor but not uploaded until they are fixed.
+    fn upload_scene(&mut self) {
+        match self.scene.validate() {
+            Ok(()) => {
+                self.gpu_scene.update(&self.device, &self.queue, &self.scene);
+                self.gui_app.edit_error = None;
+                self.reset_accumulation();
+            }
+            Err(err) => self.gui_app.edit_error = Some(err.to_string()),
+        }
+    }
+
+    /// Handles window input for the camera. Returns true if the event was consumed.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        // Every window event may change what the GUI shows.
+        self.redraw_requested = true;
+        let ctx = self.platform.context();
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } if !ctx.wants_keyboard_input() => {
+                if *key == VirtualKeyCode::F12 {
+                    if *state == ElementState::Pressed {
+                        self.screenshot();
+                    }
+                    return true;
+                }
+                self.camera_controller.process_keyboard(*key, *state)
+            }
+            WindowEvent::MouseWheel { delta, .. } if !ctx.wants_pointer_input() => {
+                self.camera_controller.process_scroll(delta);
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = *position;
+                self.move_gizmo()
+            }
+            WindowEvent::MouseInput {
+                button: MouseButton::Right,
+                state,
+                ..
+            } => {
+                self.mouse_pressed = *state == ElementState::Pressed && !ctx.wants_pointer_input();
+                true
+            }
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state: ElementState::Pressed,
+                ..
+            } if !ctx.wants_pointer_input() => {
+                if self.gui_app.pick_focus {
+                    self.pick_focus();
+                } else if !self.grab_gizmo() {
+                    self.select_at_cursor();
+                }
+                true
+            }
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state: ElementState::Released,
+                ..
+            } => self.gui_app.gizmo.end_drag(),
+            _ => false,
+        }
+    }
+
+    /// Rotates the camera while the right mouse button is held down.
+    pub fn mouse_motion(&mut self, delta: (f64, f64)) {
+        if self.mouse_pressed {
+            self.camera_controller.process_mouse(delta.0, delta.1);
+        }
+    }
+
+    /// Discards the accumulated samples, e.g. after the camera or the scene changed.
+    pub fn reset_accumulation(&mut self) {
+        self.frame_idx = 0_u32;
+    }
+
+    /// Returns the pinhole ray through the cursor.
+    fn cursor_ray(&self) -> (glm::Vec3, glm::Vec3) {
+        let aspect = self.size.width as f32 / self.size.height as f32;
+        let s = self.cursor_position.x as f32 / self.size.width as f32;
+        let t = self.cursor_position.y as f32 / self.size.height as f32;
+        self.camera.primary_ray(aspect, s, t)
+    }
+
+    /// Sets the focus distance to the object under the cursor.
+    fn pick_focus(&mut self) {
+        let (origin, direction) = self.cursor_ray();
+
+        if let Some(hit) = self.scene.raycast(&origin, &direction, self.camera.shutter_open) {
+            self.camera.focus_at(&(origin + hit.t * direction));
+            self.reset_accumulation();
+        }
+
+        self.gui_app.pick_focus = false;
+    }
+
+    fn cursor(&self) -> glm::Vec2 {
+        glm::vec2(self.cursor_position.x as f32, self.cursor_position.y as f32)
+    }
+
+    /// Starts dragging the gizmo of the selected sphere if the cursor is on one of its axes.
+    fn grab_gizmo(&mut self) -> bool {
+        let Some(idx) = self.gui_app.selected_sphere else {
+            return false;
+        };
+        let view = GizmoView::new(&self.camera, self.size);
+        self.gui_app.gizmo.begin_drag(&view, &self.scene.spheres[idx], &self.cursor())
+    }
+
+    /// Drags the selected sphere with the gizmo, or highlights the axis under the cursor.
+    /// Returns true while dragging.
+    fn move_gizmo(&mut self) -> bool {
+        let Some(idx) = self.gui_app.selected_sphere else {
+            return false;
+        };
+        let cursor = self.cursor();
+        let view = GizmoView::new(&self.camera, self.size);
+
+        if !self.gui_app.gizmo.is_dragging() {
+            self.gui_app.gizmo.hover(&view, &self.scene.spheres[idx], &cursor);
+            return false;
+        }
+
+        if self.gui_app.gizmo.drag(&view, &mut self.scene.spheres[idx], &cursor) {
+            self.gpu_scene.update_sphere(&self.queue, &self.scene, idx);
+            self.reset_accumulation();
+        }
+        true
+    }
+
+    /// Selects the sphere under the cursor, or clears the selection when clicking on the sky.
+    fn select_at_cursor(&mut self) {
+        let (origin, direction) = self.cursor_ray();
+        let hit = self.scene.raycast(&origin, &direction, self.camera.shutter_open);
+
+        if let Some(hit) = &hit {
+            log::info!("Selected sphere {} with material {}", hit.sphere_idx, hit.material_idx);
+        }
+        self.gui_app.selected_sphere = hit.map(|hit| hit.sphere_idx);
+    }
+}
+
+/// Handles the errors outside of error scopes. Without a handler wgpu panics on them, but
+/// a lost device can be created again and the other errors only spoil a frame.
+fn handle_uncaptured_error(device_lost: &AtomicBool, err: wgpu::Error) {
+    if is_device_lost(&err) {
+        device_lost.store(true, Ordering::Relaxed);
+    } else {
+        log::error!("Uncaptured wgpu error: {}", err);
+    }
+}
+
+/// Whether the error comes from using a device that was lost, e.g. after a driver reset.
+fn is_device_lost(err: &wgpu::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(err) = source {
+        if let Some(wgpu::core::device::DeviceError::Lost) = err.downcast_ref() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn validation_error(cause: wgpu::core::device::DeviceError) -> wgpu::Error {
+        wgpu::Error::Validation {
+            description: cause.to_string(),
+            source: Box::new(cause),
+        }
+    }
+
+    #[test]
+    fn only_a_lost_device_is_reported_as_lost() {
+        let device_lost = AtomicBool::new(false);
+        handle_uncaptured_error(&device_lost, validation_error(wgpu::core::device::DeviceError::Invalid));
+        handle_uncaptured_error(&device_lost, validation_error(wgpu::core::device::DeviceError::OutOfMemory));
+        assert!(!device_lost.load(Ordering::Relaxed));
+        handle_uncaptured_error(&device_lost, validation_error(wgpu::core::device::DeviceError::Lost));
+        assert!(device_lost.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    #[ignore = "opens a window, which needs a display"]
+    fn render_submits_nothing_on_a_lost_device() {
+        use winit::platform::x11::EventLoopBuilderExtX11;
+
+        let event_loop = winit::event_loop::EventLoopBuilder::new().with_any_thread(true).build();
+        let window = winit::window::WindowBuilder::new()
+            .with_visible(false)
+            .build(&event_loop)
+            .expect("Failed to open a window");
+        let scene_path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/furnace.ron"));
+        let scene = Scene::load(&scene_path).expect("Failed to load the scene");
+        let args = Args::parse_from(["rt03".as_ref(), scene_path.as_os_str()]);
+        let mut renderer = pollster::block_on(Renderer::new(Rc::new(window), scene, scene_path, &args))
+            .expect("Failed to create the renderer");
+        renderer.device.poll(wgpu::Maintain::Wait);
+
+        renderer.device_lost.store(true, Ordering::Relaxed);
+        assert!(matches!(renderer.render(), Err(RendererError::DeviceLost)));
+        assert_eq!(renderer.frame_idx, 0);
+        assert!(renderer.device.poll(wgpu::Maintain::Poll), "The frame was submitted");
     }
 }
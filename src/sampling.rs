@@ -0,0 +1,776 @@
+use std::f32::consts::PI;
+use std::sync::OnceLock;
+
+/// How the kernel generates the random numbers of a path. The discriminants are the
+/// values of `samplingParams.samplerKind`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[repr(u32)]
+pub enum Sampler {
+    /// Independent random numbers, hashed from the pixel and the sample index.
+    #[default]
+    Independent = 0,
+    /// Owen scrambled Sobol points, scrambled differently in every pixel.
+    Sobol = 1,
+    /// The same Sobol points in every pixel, shifted by a blue noise mask. The remaining
+    /// noise is mostly high frequency, which looks smoother at low sample counts.
+    BlueNoise = 2,
+}
+
+/// Number of dimensions with their own Sobol direction numbers. Higher dimensions reuse
+/// them with a shuffled sample index.
+pub const SOBOL_DIMENSIONS: usize = 4;
+
+/// Direction numbers of the first Sobol dimensions. The first one is the van der Corput
+/// sequence, the others use the primitive polynomials and initial numbers of Joe and Kuo,
+/// "Constructing Sobol sequences with better two-dimensional projections".
+pub const SOBOL_DIRECTIONS: [[u32; 32]; SOBOL_DIMENSIONS] = sobol_directions();
+
+const fn sobol_directions() -> [[u32; 32]; SOBOL_DIMENSIONS] {
+    // Degree, coefficients and initial direction numbers of dimensions 2 to 4.
+    const POLYNOMIALS: [(usize, u32, [u32; 3]); SOBOL_DIMENSIONS - 1] =
+        [(1, 0, [1, 0, 0]), (2, 1, [1, 3, 0]), (3, 1, [1, 3, 1])];
+
+    let mut directions = [[0_u32; 32]; SOBOL_DIMENSIONS];
+    let mut i = 0;
+    while i < 32 {
+        directions[0][i] = 1 << (31 - i);
+        i += 1;
+    }
+
+    let mut d = 1;
+    while d < SOBOL_DIMENSIONS {
+        let (s, a, m) = POLYNOMIALS[d - 1];
+        let v = &mut directions[d];
+        let mut i = 0;
+        while i < 32 {
+            if i < s {
+                v[i] = m[i] << (31 - i);
+            } else {
+                v[i] = v[i - s] ^ (v[i - s] >> s);
+                let mut k = 1;
+                while k < s {
+                    if (a >> (s - 1 - k)) & 1 == 1 {
+                        v[i] ^= v[i - k];
+                    }
+                    k += 1;
+                }
+            }
+            i += 1;
+        }
+        d += 1;
+    }
+    directions
+}
+
+/// Width and height of the blue noise mask, which is tiled over the image.
+pub const BLUE_NOISE_SIZE: u32 = 64;
+
+/// The ranks of a blue noise mask, row by row. Every value in `0..BLUE_NOISE_SIZE^2`
+/// appears once. It's generated on first use.
+pub fn blue_noise_mask() -> &'static [u32] {
+    static MASK: OnceLock<Vec<u32>> = OnceLock::new();
+    MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE as usize))
+}
+
+/// The tables `samplerTables` in the kernel reads: the Sobol direction numbers followed
+/// by the blue noise mask.
+pub fn sampler_tables() -> Vec<u32> {
+    SOBOL_DIRECTIONS
+        .iter()
+        .flatten()
+        .chain(blue_noise_mask())
+        .copied()
+        .collect()
+}
+
+/// The random number generator of the kernel. Every method consumes the same random
+/// numbers in the same order as its WGSL counterpart, so that both produce the same
+/// sample sequence. Changes have to be made to both.
+#[derive(Clone, Copy, Debug)]
+pub struct Rng {
+    sampler: Sampler,
+    /// The PCG state of the independent sampler.
+    state: u32,
+    pixel: [u32; 2],
+    sample_idx: u32,
+    /// The number of values drawn so far, which is the dimension of the next one.
+    dimension: u32,
+    /// Scrambles the Sobol points of the pixel.
+    seed: u32,
+}
+
+impl Rng {
+    /// An independent sampler, see [`Rng::with_sampler`].
+    pub fn new(x: u32, y: u32, width: u32, sample_idx: u32) -> Self {
+        Self::with_sampler(Sampler::Independent, x, y, width, sample_idx)
+    }
+
+    /// `initRng`: seeds the generator for a pixel and a sample.
+    pub fn with_sampler(sampler: Sampler, x: u32, y: u32, width: u32, sample_idx: u32) -> Self {
+        // Adapted from https://github.com/boksajak/referencePT
+        let pixel_idx = x.wrapping_add(y.wrapping_mul(width));
+        let seed = pixel_idx ^ jenkins_hash(sample_idx);
+        Self {
+            sampler,
+            state: jenkins_hash(seed),
+            pixel: [x, y],
+            sample_idx,
+            dimension: 0,
+            seed: pcg_hash(pixel_idx),
+        }
+    }
+
+    /// `rngNextInt`: advances the PCG state.
+    fn next_int(&mut self) {
+        // PCG random number generator
+        // Based on https://www.shadertoy.com/view/XlGcRh
+        let old_state = self.state.wrapping_add(747796405).wrapping_add(2891336453);
+        let word = ((old_state >> ((old_state >> 28) + 4)) ^ old_state).wrapping_mul(277803737);
+        self.state = (word >> 22) ^ word;
+    }
+
+    /// `rngNextFloat`: a number in [0, 1].
+    pub fn next_float(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        match self.sampler {
+            Sampler::Independent => {
+                self.next_int();
+                self.state as f32 / 0xffffffff_u32 as f32
+            }
+            Sampler::Sobol => sobol_owen(self.sample_idx, dimension, self.seed),
+            Sampler::BlueNoise => {
+                let x = sobol_owen(self.sample_idx, dimension, 0) + blue_noise(self.pixel, dimension);
+                x - x.floor()
+            }
+        }
+    }
+
+    /// `rngNextUintInRange`: a number in [min, max). An empty range gives `min`.
+    pub fn next_uint_in_range(&mut self, min: u32, max: u32) -> u32 {
+        let range = max - min;
+        if self.sampler == Sampler::Independent {
+            self.dimension += 1;
+            self.next_int();
+            return min + self.state.checked_rem(range).unwrap_or(0);
+        }
+
+        // The remainder would only keep the low bits, which aren't stratified.
+        let idx = (self.next_float() * range as f32) as u32;
+        if idx >= range && range > 0 {
+            max - 1
+        } else {
+            min + idx
+        }
+    }
+
+    /// `rngNextInCosineWeightedHemisphere`: a direction around +z.
+    pub fn next_in_cosine_weighted_hemisphere(&mut self) -> glm::Vec3 {
+        let r1 = self.next_float();
+        let r2 = self.next_float();
+        let sqrt_r2 = r2.sqrt();
+
+        let z = (1_f32 - r2).sqrt();
+        let phi = 2_f32 * PI * r1;
+        glm::vec3(phi.cos() * sqrt_r2, phi.sin() * sqrt_r2, z)
+    }
+
+    /// `rngNextInUnitHemisphere`: a direction around +z.
+    pub fn next_in_unit_hemisphere(&mut self) -> glm::Vec3 {
+        let r1 = self.next_float();
+        let r2 = self.next_float();
+
+        let phi = 2_f32 * PI * r1;
+        let sin_theta = (1_f32 - r2 * r2).sqrt();
+        glm::vec3(phi.cos() * sin_theta, phi.sin() * sin_theta, r2)
+    }
+
+    /// `rngNextVec3InUnitDisk`: a point in the unit disk in the xy plane.
+    pub fn next_vec3_in_unit_disk(&mut self) -> glm::Vec3 {
+        // r^2 is distributed as U(0, 1).
+        let r = self.next_float().sqrt();
+        let alpha = 2_f32 * PI * self.next_float();
+        glm::vec3(r * alpha.cos(), r * alpha.sin(), 0_f32)
+    }
+
+    /// `rngNextInAperture`: a point on a regular polygon with `num_blades` corners inscribed
+    /// in the unit circle, or in the unit disk for less than three blades.
+    pub fn next_in_aperture(&mut self, num_blades: u32, rotation: f32) -> glm::Vec2 {
+        if num_blades < 3 {
+            return self.next_vec3_in_unit_disk().xy();
+        }
+
+        let blade = self.next_uint_in_range(0, num_blades);
+        let angle_step = 2_f32 * PI / num_blades as f32;
+        let alpha = rotation + blade as f32 * angle_step;
+        let p0 = glm::vec2(alpha.cos(), alpha.sin());
+        let p1 = glm::vec2((alpha + angle_step).cos(), (alpha + angle_step).sin());
+
+        let mut r1 = self.next_float();
+        let mut r2 = self.next_float();
+        if r1 + r2 > 1_f32 {
+            r1 = 1_f32 - r1;
+            r2 = 1_f32 - r2;
+        }
+        r1 * p0 + r2 * p1
+    }
+
+    /// `rngNextVec3InUnitSphere`: a point in the unit ball.
+    pub fn next_vec3_in_unit_sphere(&mut self) -> glm::Vec3 {
+        // The density is uniform over r^3 and over cos(theta).
+        let r = self.next_float().powf(0.33333);
+        let cos_theta = 1_f32 - 2_f32 * self.next_float();
+        let sin_theta = (1_f32 - cos_theta * cos_theta).max(0_f32).sqrt();
+        let phi = 2_f32 * PI * self.next_float();
+        glm::vec3(
+            r * sin_theta * phi.cos(),
+            r * sin_theta * phi.sin(),
+            r * cos_theta,
+        )
+    }
+}
+
+/// `jenkinsHash`: Bob Jenkins' one-at-a-time hash of a single word.
+pub fn jenkins_hash(input: u32) -> u32 {
+    let mut x = input;
+    x = x.wrapping_add(x << 10);
+    x ^= x >> 6;
+    x = x.wrapping_add(x << 3);
+    x ^= x >> 11;
+    x = x.wrapping_add(x << 15);
+    x
+}
+
+/// `pixarOnb`: an orthonormal basis with `n` as the third column.
+pub fn pixar_onb(n: &glm::Vec3) -> glm::Mat3 {
+    // https://www.jcgt.org/published/0006/01/01/paper-lowres.pdf
+    let s = if n.z >= 0_f32 { 1_f32 } else { -1_f32 };
+    let a = -1_f32 / (s + n.z);
+    let b = n.x * n.y * a;
+    let u = glm::vec3(1_f32 + s * n.x * n.x * a, s * b, -s * n.x);
+    let v = glm::vec3(b, s + n.y * n.y * a, -n.y);
+    glm::Mat3::from_columns(&[u, v, *n])
+}
+
+/// `pcgHash`: a stateless PCG permutation of a single word.
+pub fn pcg_hash(input: u32) -> u32 {
+    let state = input.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn hash_combine(seed: u32, v: u32) -> u32 {
+    seed ^ v
+        .wrapping_add(0x9e3779b9)
+        .wrapping_add(seed << 6)
+        .wrapping_add(seed >> 2)
+}
+
+/// `sobol`: the Sobol point with the given index in one of the first dimensions.
+pub fn sobol(index: u32, dimension: u32) -> u32 {
+    let directions = &SOBOL_DIRECTIONS[dimension as usize];
+    (0..32)
+        .filter(|bit| (index >> bit) & 1 == 1)
+        .fold(0, |x, bit| x ^ directions[bit])
+}
+
+/// `nestedUniformScramble`: an Owen scramble of the bits of `x`. From Burley, "Practical
+/// Hash-based Owen Scrambling".
+pub fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+/// `sobolOwen`: a scrambled Sobol number in [0, 1) for any dimension. Dimensions are
+/// grouped into points of the first Sobol dimensions, and each group uses its own
+/// shuffle of the sample indices.
+pub fn sobol_owen(index: u32, dimension: u32, seed: u32) -> f32 {
+    let group = dimension / SOBOL_DIMENSIONS as u32;
+    let component = dimension % SOBOL_DIMENSIONS as u32;
+    let group_seed = hash_combine(seed, pcg_hash(group));
+    let shuffled = nested_uniform_scramble(index, group_seed);
+    let x = nested_uniform_scramble(sobol(shuffled, component), hash_combine(group_seed, component));
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+/// `blueNoise`: the value of the blue noise mask at the pixel, in (0, 1). Each dimension
+/// reads the tiled mask at a different offset, so that they aren't correlated.
+pub fn blue_noise(pixel: [u32; 2], dimension: u32) -> f32 {
+    let offset = pcg_hash(dimension);
+    let x = pixel[0].wrapping_add(offset) % BLUE_NOISE_SIZE;
+    let y = pixel[1].wrapping_add(offset >> 16) % BLUE_NOISE_SIZE;
+    let rank = blue_noise_mask()[(y * BLUE_NOISE_SIZE + x) as usize];
+    (rank as f32 + 0.5) / (BLUE_NOISE_SIZE * BLUE_NOISE_SIZE) as f32
+}
+
+/// Ranks the pixels of a toroidal `size` x `size` mask with the void and cluster method
+/// of Ulichney, "The void-and-cluster method for dither array generation".
+fn void_and_cluster(size: usize) -> Vec<u32> {
+    let n = size * size;
+    let mut rng = Rng::new(0, 0, 1, 0);
+
+    // Start with a tenth of the pixels set at random, then move the tightest cluster into
+    // the largest void until that doesn't change anything.
+    let mut pattern = vec![false; n];
+    let mut energy = Energy::new(size);
+    let mut num_set = 0;
+    while num_set < n / 10 {
+        let idx = rng.next_uint_in_range(0, n as u32) as usize;
+        if !pattern[idx] {
+            pattern[idx] = true;
+            energy.splat(idx, 1_f32);
+            num_set += 1;
+        }
+    }
+    loop {
+        let cluster = energy.extreme(&pattern, true, f32::gt);
+        pattern[cluster] = false;
+        energy.splat(cluster, -1_f32);
+        let void = energy.extreme(&pattern, false, f32::lt);
+        pattern[void] = true;
+        energy.splat(void, 1_f32);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0_u32; n];
+
+    // The pixels of the initial pattern are ranked by removing the tightest clusters.
+    let (mut removed, mut removed_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..num_set).rev() {
+        let cluster = removed_energy.extreme(&removed, true, f32::gt);
+        removed[cluster] = false;
+        removed_energy.splat(cluster, -1_f32);
+        ranks[cluster] = rank as u32;
+    }
+
+    // Up to half of the pixels are ranked by filling the largest voids.
+    for rank in num_set..n / 2 {
+        let void = energy.extreme(&pattern, false, f32::lt);
+        pattern[void] = true;
+        energy.splat(void, 1_f32);
+        ranks[void] = rank as u32;
+    }
+
+    // The remaining pixels are the minority now, so the roles swap: the tightest cluster
+    // of unset pixels is filled first.
+    let mut unset_energy = Energy::new(size);
+    for idx in (0..n).filter(|idx| !pattern[*idx]) {
+        unset_energy.splat(idx, 1_f32);
+    }
+    for rank in n / 2..n {
+        let cluster = unset_energy.extreme(&pattern, false, f32::gt);
+        pattern[cluster] = true;
+        unset_energy.splat(cluster, -1_f32);
+        ranks[cluster] = rank as u32;
+    }
+
+    ranks
+}
+
+/// The sum of Gaussians centered on the set pixels of a toroidal mask.
+#[derive(Clone)]
+struct Energy {
+    size: usize,
+    values: Vec<f32>,
+}
+
+impl Energy {
+    const SIGMA: f32 = 1.5;
+    /// The Gaussian is negligible beyond four sigmas.
+    const RADIUS: isize = 6;
+
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            values: vec![0_f32; size * size],
+        }
+    }
+
+    fn splat(&mut self, idx: usize, weight: f32) {
+        let size = self.size as isize;
+        let (x, y) = ((idx % self.size) as isize, (idx / self.size) as isize);
+        for dy in -Self::RADIUS..=Self::RADIUS {
+            for dx in -Self::RADIUS..=Self::RADIUS {
+                let distance_sqr = (dx * dx + dy * dy) as f32;
+                let target = (y + dy).rem_euclid(size) * size + (x + dx).rem_euclid(size);
+                self.values[target as usize] += weight * (-distance_sqr / (2_f32 * Self::SIGMA * Self::SIGMA)).exp();
+            }
+        }
+    }
+
+    /// The first pixel with the given state whose energy is the most extreme by `better`.
+    fn extreme(&self, pattern: &[bool], state: bool, better: fn(&f32, &f32) -> bool) -> usize {
+        let mut best = None;
+        for (idx, value) in self.values.iter().enumerate().filter(|(idx, _)| pattern[*idx] == state) {
+            if best.is_none_or(|(_, best_value)| better(value, best_value)) {
+                best = Some((idx, value));
+            }
+        }
+        best.expect("the pattern contains a pixel with the state").0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NUM_SAMPLES: usize = 200_000;
+
+    /// Pearson's chi-square statistic of a histogram whose bins are equally likely.
+    fn chi_square(histogram: &[u32]) -> f64 {
+        let total: u32 = histogram.iter().sum();
+        let expected = total as f64 / histogram.len() as f64;
+        histogram
+            .iter()
+            .map(|&observed| (observed as f64 - expected).powi(2) / expected)
+            .sum()
+    }
+
+    /// Fails if the histogram is unlikely to come from a uniform distribution over its bins.
+    /// The critical value at p = 0.001 comes from the Wilson-Hilferty approximation. The
+    /// seeds are fixed, so a test either always passes or always fails.
+    fn assert_uniform(histogram: &[u32], what: &str) {
+        let df = (histogram.len() - 1) as f64;
+        let z = 3.09;
+        let critical = df * (1.0 - 2.0 / (9.0 * df) + z * (2.0 / (9.0 * df)).sqrt()).powi(3);
+        let statistic = chi_square(histogram);
+        assert!(
+            statistic < critical,
+            "{} isn't uniform: chi-square {:.1} with {} degrees of freedom, critical value {:.1}",
+            what,
+            statistic,
+            df,
+            critical
+        );
+    }
+
+    /// Maps values in [0, 1] to one of `n` bins.
+    fn bin(x: f32, n: usize) -> usize {
+        ((x * n as f32) as usize).min(n - 1)
+    }
+
+    /// `phi` of the direction, mapped to [0, 1].
+    fn azimuth(v: &glm::Vec3) -> f32 {
+        0.5 + 0.5 * v.y.atan2(v.x) / PI
+    }
+
+    fn assert_orthonormal(m: &glm::Mat3) {
+        let error = (m.transpose() * m - glm::Mat3::identity()).abs().max();
+        assert!(error < 1e-5, "{} isn't orthonormal", m);
+    }
+
+    #[test]
+    fn jenkins_hash_of_pixel_indices_is_uniform() {
+        let mut histogram = [0_u32; 256];
+        for i in 0..NUM_SAMPLES as u32 {
+            histogram[(jenkins_hash(i) >> 24) as usize] += 1;
+        }
+        assert_uniform(&histogram, "the high byte of jenkinsHash");
+    }
+
+    #[test]
+    fn first_float_of_neighbouring_pixels_is_uniform() {
+        let mut histogram = [0_u32; 64];
+        for frame in 0..4 {
+            for y in 0..256 {
+                for x in 0..256 {
+                    histogram[bin(Rng::new(x, y, 256, frame).next_float(), 64)] += 1;
+                }
+            }
+        }
+        assert_uniform(&histogram, "the first float of each pixel");
+    }
+
+    #[test]
+    fn uint_in_range_is_uniform() {
+        let mut rng = Rng::new(1, 2, 3, 4);
+        let mut histogram = [0_u32; 7];
+        for _ in 0..NUM_SAMPLES {
+            let x = rng.next_uint_in_range(3, 10);
+            assert!((3..10).contains(&x));
+            histogram[(x - 3) as usize] += 1;
+        }
+        assert_uniform(&histogram, "rngNextUintInRange");
+        assert_eq!(rng.next_uint_in_range(5, 5), 5);
+    }
+
+    #[test]
+    fn cosine_weighted_hemisphere_is_uniform_in_cos_squared_and_phi() {
+        let mut rng = Rng::new(0, 0, 1, 0);
+        let mut histogram = [0_u32; 16 * 16];
+        for _ in 0..NUM_SAMPLES {
+            let v = rng.next_in_cosine_weighted_hemisphere();
+            assert!((v.norm() - 1.0).abs() < 1e-5 && v.z >= 0.0);
+            // The pdf cos(theta) / PI gives cos^2(theta) a uniform distribution.
+            histogram[16 * bin(v.z * v.z, 16) + bin(azimuth(&v), 16)] += 1;
+        }
+        assert_uniform(&histogram, "rngNextInCosineWeightedHemisphere");
+    }
+
+    #[test]
+    fn unit_hemisphere_is_uniform_in_cos_and_phi() {
+        let mut rng = Rng::new(0, 0, 1, 1);
+        let mut histogram = [0_u32; 16 * 16];
+        for _ in 0..NUM_SAMPLES {
+            let v = rng.next_in_unit_hemisphere();
+            assert!((v.norm() - 1.0).abs() < 1e-5 && v.z >= 0.0);
+            histogram[16 * bin(v.z, 16) + bin(azimuth(&v), 16)] += 1;
+        }
+        assert_uniform(&histogram, "rngNextInUnitHemisphere");
+    }
+
+    #[test]
+    fn unit_disk_is_uniform() {
+        let mut rng = Rng::new(0, 0, 1, 2);
+        let mut histogram = [0_u32; 16 * 16];
+        for _ in 0..NUM_SAMPLES {
+            let v = rng.next_vec3_in_unit_disk();
+            let r2 = v.norm_squared();
+            assert!(r2 <= 1.0 + 1e-5 && v.z == 0.0);
+            // The area within radius r grows with r^2.
+            histogram[16 * bin(r2, 16) + bin(azimuth(&v), 16)] += 1;
+        }
+        assert_uniform(&histogram, "rngNextVec3InUnitDisk");
+    }
+
+    #[test]
+    fn unit_sphere_is_uniform() {
+        let mut rng = Rng::new(0, 0, 1, 3);
+        let mut histogram = [0_u32; 8 * 8 * 8];
+        for _ in 0..NUM_SAMPLES {
+            let v = rng.next_vec3_in_unit_sphere();
+            let r = v.norm();
+            assert!(r <= 1.0 + 1e-5);
+            // The volume within radius r grows with r^3, the area of a spherical zone with
+            // its height.
+            let cos_theta = 0.5 + 0.5 * v.z / r;
+            histogram[64 * bin(r.powi(3), 8) + 8 * bin(cos_theta, 8) + bin(azimuth(&v), 8)] += 1;
+        }
+        assert_uniform(&histogram, "rngNextVec3InUnitSphere");
+    }
+
+    #[test]
+    fn aperture_is_uniform_over_the_polygon() {
+        for num_blades in 3..8 {
+            let rotation = 0.3;
+            let angle_step = 2.0 * PI / num_blades as f32;
+            let mut rng = Rng::new(num_blades, 0, 1, 4);
+            let mut histogram = vec![0_u32; num_blades as usize * 8 * 8];
+            for _ in 0..NUM_SAMPLES {
+                let p = rng.next_in_aperture(num_blades, rotation);
+                // Find the triangle between the center and an edge that contains p, and the
+                // coordinates of p along its sides.
+                let angle = (p.y.atan2(p.x) - rotation).rem_euclid(2.0 * PI);
+                let blade = ((angle / angle_step) as u32).min(num_blades - 1);
+                let alpha = rotation + blade as f32 * angle_step;
+                let p0 = glm::vec2(alpha.cos(), alpha.sin());
+                let p1 = glm::vec2((alpha + angle_step).cos(), (alpha + angle_step).sin());
+                let ab = glm::mat2(p0.x, p1.x, p0.y, p1.y).try_inverse().unwrap() * p;
+                let (a, b) = (ab.x, ab.y);
+                assert!(a >= -1e-4 && b >= -1e-4 && a + b <= 1.0 + 1e-4, "{} is outside the aperture", p);
+
+                // The area within distance s of the center grows with s^2, and every
+                // direction from the center towards the edge is as likely.
+                let s = a + b;
+                let t = if s > 0.0 { b / s } else { 0.0 };
+                histogram[64 * blade as usize + 8 * bin(s * s, 8) + bin(t, 8)] += 1;
+            }
+            assert_uniform(&histogram, &format!("rngNextInAperture with {} blades", num_blades));
+        }
+    }
+
+    /// Fails unless each of the `2^bits` intervals of equal size contains one of the points.
+    fn assert_stratified(points: &[f32], bits: u32, what: &str) {
+        let mut histogram = vec![0_u32; 1 << bits];
+        for x in points {
+            histogram[bin(*x, 1 << bits)] += 1;
+        }
+        assert!(histogram.iter().all(|count| *count == 1), "{} isn't stratified: {:?}", what, histogram);
+    }
+
+    #[test]
+    fn sobol_directions_match_the_published_ones() {
+        // From the table in Burley, "Practical Hash-based Owen Scrambling".
+        assert_eq!(
+            SOBOL_DIRECTIONS[1][..8],
+            [0x80000000, 0xc0000000, 0xa0000000, 0xf0000000, 0x88000000, 0xcc000000, 0xaa000000, 0xff000000]
+        );
+        assert_eq!(SOBOL_DIRECTIONS[1][31], 0xffffffff);
+        // The initial numbers are odd, so direction number i ends at bit 31 - i.
+        for directions in SOBOL_DIRECTIONS {
+            for (i, v) in directions.iter().enumerate() {
+                assert_eq!(v.trailing_zeros(), 31 - i as u32);
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_points_are_a_net() {
+        // The first two dimensions form a (0, m, 2)-net: every elementary interval with an
+        // area of 1 / 2^m contains exactly one of the first 2^m points.
+        let m = 8;
+        let points: Vec<(u32, u32)> = (0..1 << m).map(|i| (sobol(i, 0), sobol(i, 1))).collect();
+        for x_bits in 0..=m {
+            let y_bits = m - x_bits;
+            let mut histogram = vec![0_u32; 1 << m];
+            for (x, y) in &points {
+                let cell_x = x.checked_shr(32 - x_bits).unwrap_or(0);
+                let cell_y = y.checked_shr(32 - y_bits).unwrap_or(0);
+                histogram[((cell_y << x_bits) | cell_x) as usize] += 1;
+            }
+            assert!(histogram.iter().all(|count| *count == 1), "not a net for {}x{} cells", 1 << x_bits, 1 << y_bits);
+        }
+    }
+
+    #[test]
+    fn scrambled_sobol_points_are_stratified_in_every_dimension() {
+        for seed in [0, 1, 0xdeadbeef] {
+            for dimension in 0..16 {
+                let points: Vec<f32> = (0..256).map(|i| sobol_owen(i, dimension, seed)).collect();
+                assert_stratified(&points, 8, &format!("dimension {} with seed {}", dimension, seed));
+            }
+        }
+    }
+
+    #[test]
+    fn scrambling_decorrelates_pixels() {
+        // The first point of every pixel is a different one.
+        let mut histogram = [0_u32; 64];
+        for pixel in 0..NUM_SAMPLES as u32 {
+            histogram[bin(sobol_owen(0, 0, pcg_hash(pixel)), 64)] += 1;
+        }
+        assert_uniform(&histogram, "the first Sobol point of the pixels");
+    }
+
+    #[test]
+    fn blue_noise_samples_are_evenly_spaced_in_every_pixel() {
+        for pixel in [[0, 0], [17, 3], [63, 63], [64, 1000]] {
+            for dimension in [0, 1, 5, 12] {
+                let points: Vec<f32> = (0..256)
+                    .map(|i| {
+                        let mut rng = Rng::with_sampler(Sampler::BlueNoise, pixel[0], pixel[1], 640, i);
+                        (0..dimension).for_each(|_| {
+                            rng.next_float();
+                        });
+                        rng.next_float()
+                    })
+                    .collect();
+                // The shift moves the strata, but keeps the points evenly spaced.
+                let mut sorted = points.clone();
+                sorted.sort_by(f32::total_cmp);
+                let wrap_gap = sorted[0] + 1.0 - sorted[255];
+                let max_gap = sorted.windows(2).map(|w| w[1] - w[0]).fold(wrap_gap, f32::max);
+                assert!(max_gap <= 2.0 / 256.0, "dimension {} of pixel {:?} has a gap of {}", dimension, pixel, max_gap);
+            }
+        }
+    }
+
+    #[test]
+    fn uint_in_range_of_low_discrepancy_samplers_is_stratified() {
+        for sampler in [Sampler::Sobol, Sampler::BlueNoise] {
+            let mut histogram = [0_u32; 6];
+            for i in 0..60 {
+                let x = Rng::with_sampler(sampler, 3, 4, 10, i).next_uint_in_range(2, 8);
+                histogram[(x - 2) as usize] += 1;
+            }
+            // 60 isn't a power of two, so the counts can be off by one.
+            assert!(histogram.iter().all(|count| (9..=11).contains(count)), "{:?}: {:?}", sampler, histogram);
+            assert_eq!(Rng::with_sampler(sampler, 0, 0, 1, 0).next_uint_in_range(5, 5), 5);
+        }
+    }
+
+    #[test]
+    fn blue_noise_mask_is_a_permutation_without_low_frequencies() {
+        let mask = blue_noise_mask();
+        let n = (BLUE_NOISE_SIZE * BLUE_NOISE_SIZE) as usize;
+        let mut sorted = mask.to_vec();
+        sorted.sort_unstable();
+        assert!(sorted.iter().copied().eq(0..n as u32));
+
+        // Averaging 4x4 blocks removes the high frequencies. For white noise the block
+        // means have 1/16 of the variance of the values, for blue noise a fraction of that.
+        let size = BLUE_NOISE_SIZE as usize;
+        let value = |x: usize, y: usize| (mask[y * size + x] as f64 + 0.5) / n as f64;
+        let block_means: Vec<f64> = (0..size / 4)
+            .flat_map(|by| (0..size / 4).map(move |bx| (by, bx)))
+            .map(|(by, bx)| (0..16).map(|i| value(4 * bx + i % 4, 4 * by + i / 4)).sum::<f64>() / 16.0)
+            .collect();
+        let variance = block_means.iter().map(|m| (m - 0.5).powi(2)).sum::<f64>() / block_means.len() as f64;
+        let white_noise_variance = 1.0 / 12.0 / 16.0;
+        assert!(variance < 0.25 * white_noise_variance, "{} vs {} for white noise", variance, white_noise_variance);
+    }
+
+    #[test]
+    fn cosine_weighted_hemisphere_matches_its_pdf() {
+        // E[1 / pdf] over the samples is the solid angle of the hemisphere.
+        let mut rng = Rng::new(0, 0, 1, 5);
+        let mut sum = 0_f64;
+        for _ in 0..NUM_SAMPLES {
+            let v = rng.next_in_cosine_weighted_hemisphere();
+            // Same clamping as `pdfLambertian`, which barely matters for the integral.
+            sum += 1.0 / (v.z / PI).max(1e-3) as f64;
+        }
+        let solid_angle = sum / NUM_SAMPLES as f64;
+        assert!((solid_angle / (2.0 * std::f64::consts::PI) - 1.0).abs() < 0.02, "{}", solid_angle);
+    }
+
+    #[test]
+    fn lambertian_pdf_integrates_to_one() {
+        // Midpoint rule over the hemisphere, in spherical coordinates.
+        let n = 512;
+        let d_theta = 0.5 * std::f64::consts::PI / n as f64;
+        let integral: f64 = (0..n)
+            .map(|i| {
+                let theta = (i as f64 + 0.5) * d_theta;
+                let pdf = theta.cos() * std::f64::consts::FRAC_1_PI;
+                pdf * theta.sin() * d_theta * 2.0 * std::f64::consts::PI
+            })
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-4, "{}", integral);
+    }
+
+    #[test]
+    fn pixar_onb_is_orthonormal_around_n() {
+        let mut rng = Rng::new(0, 0, 1, 6);
+        let mut normals: Vec<glm::Vec3> = (0..10_000)
+            .map(|_| {
+                let v = rng.next_vec3_in_unit_sphere();
+                v / v.norm().max(1e-6)
+            })
+            .collect();
+        // The branch on the sign of z and the poles are the interesting cases.
+        normals.extend([
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec3(0.0, 0.0, -1.0),
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(0.0, 1.0, 0.0),
+            glm::vec3(0.0, 1.0, -1e-7).normalize(),
+            glm::vec3(0.6, 0.0, 0.8),
+        ]);
+
+        for n in normals.iter().filter(|n| n.norm() > 0.5) {
+            let onb = pixar_onb(n);
+            assert_orthonormal(&onb);
+            assert!((onb.column(2) - n).norm() < 1e-6);
+            // Right handed, so that mapping +z samples keeps their orientation.
+            assert!((onb.determinant() - 1.0).abs() < 1e-4, "{} is left handed", onb);
+        }
+    }
+
+    #[test]
+    fn pixar_onb_maps_the_hemisphere_around_z_to_the_one_around_n() {
+        let mut rng = Rng::new(0, 0, 1, 7);
+        let n = glm::vec3(-0.48, 0.6, -0.64);
+        for _ in 0..10_000 {
+            let v = rng.next_in_cosine_weighted_hemisphere();
+            let w = pixar_onb(&n) * v;
+            assert!((w.dot(&n) - v.z).abs() < 1e-5);
+        }
+    }
+}
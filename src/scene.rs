@@ -1,63 +1,465 @@
+use std::path::{Path, PathBuf};
+
 use crate::sphere::Sphere;
 
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct Scene {
-    pub spheres: Vec<Sphere>,
     pub materials: Vec<Material>,
+    pub spheres: Vec<Sphere>,
+}
+
+impl Scene {
+    /// Loads a scene from a RON file, including the textures it references.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+        let source = std::fs::read_to_string(path)?;
+        let scene: Scene = ron::from_str(&source)?;
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    /// Writes the scene to a RON file. Image textures are written as their path and scale,
+    /// so the file stays small and editable by hand.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SceneError> {
+        let config = ron::ser::PrettyConfig::new().indentor("    ".to_string());
+        let source = ron::ser::to_string_pretty(self, config)?;
+        std::fs::write(path, source)?;
+        Ok(())
+    }
+
+    /// Checks the invariants the kernel relies on.
+    pub fn validate(&self) -> Result<(), SceneError> {
+        if self.spheres.is_empty() {
+            return Err(SceneError::Invalid("the scene contains no spheres".to_string()));
+        }
+
+        for (idx, material) in self.materials.iter().enumerate() {
+            if matches!(material, Material::Mapped { .. }) && matches!(material.base(), Material::Mapped { .. }) {
+                return Err(SceneError::Invalid(format!(
+                    "material {} maps a material that is already mapped",
+                    idx
+                )));
+            }
+        }
+
+        for (idx, sphere) in self.spheres.iter().enumerate() {
+            if sphere.material_idx as usize >= self.materials.len() {
+                return Err(SceneError::Invalid(format!(
+                    "sphere {} uses material {}, but there are only {} materials",
+                    idx,
+                    sphere.material_idx,
+                    self.materials.len()
+                )));
+            }
+        }
+
+        if self.light_indices().is_empty() {
+            return Err(SceneError::Invalid(
+                "the scene needs at least one sphere with an emissive material".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the indices of the spheres with an emissive material.
+    pub fn light_indices(&self) -> Vec<u32> {
+        self.spheres
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| {
+                matches!(
+                    self.materials[s.material_idx as usize].base(),
+                    Material::Emissive { .. }
+                )
+            })
+            .map(|(idx, _)| idx as u32)
+            .collect()
+    }
+
+    /// Returns the paths of all image files referenced by the materials.
+    pub fn texture_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for material in self.materials.iter() {
+            for path in material.textures().into_iter().filter_map(Texture::path) {
+                if !paths.iter().any(|p| p == path) {
+                    paths.push(path.to_path_buf());
+                }
+            }
+        }
+        paths
+    }
+
+    /// Encodes the materials for the kernel. Returns the materials and the texture data
+    /// their descriptors point into.
+    pub fn gpu_materials(&self) -> (Vec<GpuMaterial>, Vec<[f32; 3]>) {
+        let mut global_texture_data: Vec<[f32; 3]> = Vec::new();
+        let mut material_data: Vec<GpuMaterial> = Vec::with_capacity(self.materials.len());
+
+        for material in self.materials.iter() {
+            let gpu_material = GpuMaterial::new(material, &mut global_texture_data);
+            material_data.push(gpu_material);
+        }
+
+        (material_data, global_texture_data)
+    }
+
+    // Same bounds as `MIN_T` and `MAX_T` in the kernel.
+    const MIN_T: f32 = 0.001_f32;
+    const MAX_T: f32 = 1000_f32;
+
+    /// Returns the closest sphere hit by the ray at the given time.
+    pub fn raycast(&self, origin: &glm::Vec3, direction: &glm::Vec3, time: f32) -> Option<RayHit> {
+        let mut closest = None;
+        let mut closest_t = Self::MAX_T;
+
+        for (idx, sphere) in self.spheres.iter().enumerate() {
+            if let Some(t) = sphere.intersect(origin, direction, time, Self::MIN_T, closest_t) {
+                closest_t = t;
+                closest = Some(RayHit {
+                    t,
+                    sphere_idx: idx,
+                    material_idx: sphere.material_idx as usize,
+                });
+            }
+        }
+
+        closest
+    }
 }
 
+/// The closest intersection found by [`Scene::raycast`].
+pub struct RayHit {
+    pub t: f32,
+    pub sphere_idx: usize,
+    pub material_idx: usize,
+}
+
+// Scenes have few materials, so the size of the principled one doesn't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub enum Material {
     Lambertian { albedo: Texture },
     Metal { albedo: Texture, fuzz: f32 },
     Dielectric { refraction_index: f32 },
     Checkerboard { even: Texture, odd: Texture },
     Emissive { emit: Texture },
+    /// Another material with its shading normal perturbed by a tangent space normal map,
+    /// a bump map or both. The slopes of the two add up.
+    Mapped {
+        material: Box<Material>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        normal_map: Option<Texture>,
+        /// Heights are read from the red channel.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bump_map: Option<Texture>,
+        /// The slope of the surface is the height difference of neighboring texels times
+        /// the strength.
+        #[serde(default = "Material::default_bump_strength")]
+        bump_strength: f32,
+    },
+    /// A physically based material after Burley's "Physically Based Shading at Disney".
+    /// The parameters other than the base color are in [0, 1] and read from the red
+    /// channel of their textures, so they can be written as `Value(0.5)`.
+    Principled {
+        #[serde(default = "Material::default_base_color")]
+        base_color: Texture,
+        #[serde(default = "Texture::zero")]
+        metallic: Texture,
+        #[serde(default = "Material::default_roughness")]
+        roughness: Texture,
+        /// The reflectance at normal incidence of dielectrics, 0.5 is 4%.
+        #[serde(default = "Material::default_specular")]
+        specular: Texture,
+        /// Tints the specular reflection of dielectrics towards the base color.
+        #[serde(default = "Texture::zero")]
+        specular_tint: Texture,
+        /// Strength of a second, white and nearly smooth specular layer.
+        #[serde(default = "Texture::zero")]
+        clearcoat: Texture,
+        /// Strength of the retroreflection at grazing angles, as on cloth.
+        #[serde(default = "Texture::zero")]
+        sheen: Texture,
+        /// Blends towards a rough dielectric that refracts light tinted by the base color.
+        #[serde(default = "Texture::zero")]
+        transmission: Texture,
+        /// Stretches the highlights along the lines of latitude of the sphere.
+        #[serde(default = "Texture::zero")]
+        anisotropy: Texture,
+        #[serde(default = "Material::default_refraction_index")]
+        refraction_index: f32,
+    },
+}
+
+impl Material {
+    /// A principled material with the default parameters and the given base color.
+    pub fn principled(base_color: Texture) -> Self {
+        Material::Principled {
+            base_color,
+            metallic: Texture::zero(),
+            roughness: Material::default_roughness(),
+            specular: Material::default_specular(),
+            specular_tint: Texture::zero(),
+            clearcoat: Texture::zero(),
+            sheen: Texture::zero(),
+            transmission: Texture::zero(),
+            anisotropy: Texture::zero(),
+            refraction_index: Material::default_refraction_index(),
+        }
+    }
+
+    fn default_base_color() -> Texture {
+        Texture::new_from_color(glm::vec3(0.8, 0.8, 0.8))
+    }
+
+    fn default_roughness() -> Texture {
+        Texture::new_from_value(0.5)
+    }
+
+    fn default_specular() -> Texture {
+        Texture::new_from_value(0.5)
+    }
+
+    fn default_refraction_index() -> f32 {
+        1.5
+    }
+
+    fn default_bump_strength() -> f32 {
+        1_f32
+    }
+
+    /// The material a mapped material perturbs the normal of, or the material itself.
+    pub fn base(&self) -> &Material {
+        match self {
+            Material::Mapped { material, .. } => material,
+            material => material,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Material::Lambertian { .. } => "Lambertian",
+            Material::Metal { .. } => "Metal",
+            Material::Dielectric { .. } => "Dielectric",
+            Material::Checkerboard { .. } => "Checkerboard",
+            Material::Emissive { .. } => "Emissive",
+            Material::Mapped { .. } => "Mapped",
+            Material::Principled { .. } => "Principled",
+        }
+    }
+
+    pub fn textures(&self) -> Vec<&Texture> {
+        match self {
+            Material::Lambertian { albedo } => vec![albedo],
+            Material::Metal { albedo, .. } => vec![albedo],
+            Material::Dielectric { .. } => vec![],
+            Material::Checkerboard { even, odd } => vec![even, odd],
+            Material::Emissive { emit } => vec![emit],
+            Material::Mapped { material, normal_map, bump_map, .. } => {
+                let mut textures = material.textures();
+                textures.extend(normal_map);
+                textures.extend(bump_map);
+                textures
+            }
+            Material::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                specular_tint,
+                clearcoat,
+                sheen,
+                transmission,
+                anisotropy,
+                ..
+            } => vec![
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                specular_tint,
+                clearcoat,
+                sheen,
+                transmission,
+                anisotropy,
+            ],
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SceneError {
+    #[error(transparent)]
+    FileIoError(#[from] std::io::Error),
+    #[error(transparent)]
+    ParseError(#[from] ron::error::SpannedError),
+    #[error(transparent)]
+    SerializeError(#[from] ron::Error),
+    #[error("invalid scene: {0}")]
+    Invalid(String),
 }
 
 use image::RgbaImage;
 use thiserror::Error;
 
+/// How a texture is described in a scene file.
+#[derive(serde::Deserialize, serde::Serialize)]
+enum TextureSource {
+    Color([f32; 3]),
+    /// A constant gray, for the parameters that are a single number.
+    Value(f32),
+    Image {
+        path: PathBuf,
+        #[serde(
+            default = "TextureSource::default_scale",
+            skip_serializing_if = "TextureSource::is_default_scale"
+        )]
+        scale: f32,
+    },
+}
+
+impl TextureSource {
+    fn default_scale() -> f32 {
+        1_f32
+    }
+
+    fn is_default_scale(scale: &f32) -> bool {
+        *scale == Self::default_scale()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(try_from = "TextureSource")]
 pub struct Texture {
     dimensions: (u32, u32),
+    /// Image data isn't scaled, so that changing the scale doesn't lose precision.
     data: Vec<[f32; 3]>,
+    path: Option<PathBuf>,
+    scale: f32,
+    /// Whether the constant was given as a `Value`, so that it's written back as one.
+    is_value: bool,
+}
+
+impl TryFrom<TextureSource> for Texture {
+    type Error = TextureError;
+
+    fn try_from(source: TextureSource) -> Result<Self, Self::Error> {
+        match source {
+            TextureSource::Color(color) => Ok(Self::new_from_color(glm::make_vec3(&color))),
+            TextureSource::Value(value) => Ok(Self::new_from_value(value)),
+            TextureSource::Image { path, scale } => Self::new_from_scaled_image(path, scale),
+        }
+    }
+}
+
+// Textures are written as the source they were created from instead of the decoded data.
+impl serde::Serialize for Texture {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let source = match &self.path {
+            Some(path) => TextureSource::Image {
+                path: path.clone(),
+                scale: self.scale,
+            },
+            None if self.is_value => TextureSource::Value(self.data[0][0]),
+            None => TextureSource::Color(self.data[0]),
+        };
+        source.serialize(serializer)
+    }
 }
 
 impl Texture {
-    pub fn new_from_image(path: &str) -> Result<Self, TextureError> {
+    #[allow(dead_code)]
+    pub fn new_from_image<P: AsRef<Path>>(path: P) -> Result<Self, TextureError> {
         Self::new_from_scaled_image(path, 1_f32)
     }
 
-    pub fn new_from_scaled_image(path: &str, scale: f32) -> Result<Self, TextureError> {
+    pub fn new_from_scaled_image<P: AsRef<Path>>(path: P, scale: f32) -> Result<Self, TextureError> {
         use std::fs::*;
         use std::io::BufReader;
 
-        let file = File::open(path)?;
-        let pixels: RgbaImage =
-            image::load(BufReader::new(file), image::ImageFormat::Jpeg)?.into_rgba8();
-        let tex_scale = scale / 255_f32;
+        if scale.is_nan() || scale <= 0_f32 {
+            return Err(TextureError::InvalidScale(scale));
+        }
+
+        let file = File::open(path.as_ref())?;
+        let pixels: RgbaImage = image::io::Reader::new(BufReader::new(file))
+            .with_guessed_format()?
+            .decode()?
+            .into_rgba8();
         let dimensions = pixels.dimensions();
         let data = pixels
             .pixels()
             .map(|p| -> [f32; 3] {
                 [
-                    tex_scale * (p[0] as f32),
-                    tex_scale * (p[1] as f32),
-                    tex_scale * (p[2] as f32),
+                    p[0] as f32 / 255_f32,
+                    p[1] as f32 / 255_f32,
+                    p[2] as f32 / 255_f32,
                 ]
             })
             .collect();
+        let path = Some(path.as_ref().to_path_buf());
 
-        Ok(Self { dimensions, data })
+        Ok(Self { dimensions, data, path, scale, is_value: false })
     }
 
     pub fn new_from_color(color: glm::Vec3) -> Self {
         let data = vec![[color.x, color.y, color.z]];
         let dimensions = (1_u32, 1_u32);
 
-        Self { dimensions, data }
+        Self { dimensions, data, path: None, scale: 1_f32, is_value: false }
     }
 
-    pub fn as_slice(&self) -> &[[f32; 3]] {
-        self.data.as_slice()
+    pub fn new_from_value(value: f32) -> Self {
+        Self {
+            is_value: true,
+            ..Self::new_from_color(glm::vec3(value, value, value))
+        }
+    }
+
+    fn zero() -> Self {
+        Self::new_from_value(0_f32)
+    }
+
+    pub fn set_value(&mut self, value: f32) {
+        self.set_color(glm::vec3(value, value, value));
+        self.is_value = true;
+    }
+
+    /// The color of a constant texture, or `None` for image textures.
+    pub fn color(&self) -> Option<glm::Vec3> {
+        match self.path {
+            Some(_) => None,
+            None => Some(glm::make_vec3(&self.data[0])),
+        }
+    }
+
+    pub fn set_color(&mut self, color: glm::Vec3) {
+        self.data = vec![[color.x, color.y, color.z]];
+        self.dimensions = (1_u32, 1_u32);
+        self.path = None;
+        self.scale = 1_f32;
+        self.is_value = false;
+    }
+
+    /// The factor the image data is multiplied with.
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Rescales the image data, e.g. to change the strength of an emissive image. The
+    /// scale has to be positive.
+    pub fn set_scale(&mut self, scale: f32) {
+        debug_assert!(scale > 0_f32, "invalid texture scale {}", scale);
+        self.scale = scale;
+    }
+
+    /// The image file the texture was loaded from, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The texels as the kernel reads them, with the scale applied.
+    pub fn texels(&self) -> impl Iterator<Item = [f32; 3]> + '_ {
+        self.data.iter().map(|texel| texel.map(|c| self.scale * c))
     }
 
     pub fn dimensions(&self) -> (u32, u32) {
@@ -71,14 +473,16 @@ pub enum TextureError {
     FileIoError(#[from] std::io::Error),
     #[error(transparent)]
     ImageLoadError(#[from] image::ImageError),
+    #[error("the scale of an image texture has to be positive, not {0}")]
+    InvalidScale(f32),
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct TextureDescriptor {
-    width: u32,
-    height: u32,
-    offset: u32,
+pub struct TextureDescriptor {
+    pub width: u32,
+    pub height: u32,
+    pub offset: u32,
 }
 
 impl TextureDescriptor {
@@ -91,41 +495,83 @@ impl TextureDescriptor {
     }
 }
 
+/// A material as the kernel reads it. What the textures and `x` mean depends on `id`.
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct GpuMaterial {
-    id: u32,
-    desc1: TextureDescriptor,
-    desc2: TextureDescriptor,
-    x: f32,
+    pub id: u32,
+    pub textures: [TextureDescriptor; GpuMaterial::MAX_TEXTURES],
+    pub x: f32,
+    /// Empty if the material has no normal map.
+    pub normal_map: TextureDescriptor,
+    /// Empty if the material has no bump map.
+    pub bump_map: TextureDescriptor,
+    pub bump_strength: f32,
 }
 
 impl GpuMaterial {
-    pub fn lambertian(albedo: &Texture, global_texture_data: &mut Vec<[f32; 3]>) -> Self {
-        Self {
-            id: 0_u32,
-            desc1: Self::append_to_global_texture_data(albedo, global_texture_data),
-            desc2: TextureDescriptor::empty(),
-            x: 0_f32,
+    /// The principled material has the most parameters.
+    pub const MAX_TEXTURES: usize = 9;
+
+    pub fn new(material: &Material, global_texture_data: &mut Vec<[f32; 3]>) -> Self {
+        match material {
+            Material::Lambertian { albedo } => {
+                GpuMaterial::lambertian(albedo, global_texture_data)
+            }
+            Material::Metal { albedo, fuzz } => {
+                GpuMaterial::metal(albedo, *fuzz, global_texture_data)
+            }
+            Material::Dielectric { refraction_index } => {
+                GpuMaterial::dielectric(*refraction_index)
+            }
+            Material::Checkerboard { odd, even } => {
+                GpuMaterial::checkerboard(odd, even, global_texture_data)
+            }
+            Material::Emissive { emit } => {
+                GpuMaterial::emissive(emit, global_texture_data)
+            }
+            Material::Mapped { material, normal_map, bump_map, bump_strength } => {
+                let mut gpu_material = GpuMaterial::new(material, global_texture_data);
+                let mut append = |texture: &Option<Texture>| match texture {
+                    Some(texture) => Self::append_to_global_texture_data(texture, global_texture_data),
+                    None => TextureDescriptor::empty(),
+                };
+                gpu_material.normal_map = append(normal_map);
+                gpu_material.bump_map = append(bump_map);
+                gpu_material.bump_strength = *bump_strength;
+                gpu_material
+            }
+            Material::Principled { .. } => {
+                GpuMaterial::principled(material, global_texture_data)
+            }
         }
     }
 
-    pub fn metal(albedo: &Texture, fuzz: f32, global_texture_data: &mut Vec<[f32; 3]>) -> Self {
+    fn with_textures(id: u32, textures: &[&Texture], x: f32, global_texture_data: &mut Vec<[f32; 3]>) -> Self {
+        let mut descriptors = [TextureDescriptor::empty(); Self::MAX_TEXTURES];
+        for (descriptor, texture) in descriptors.iter_mut().zip(textures) {
+            *descriptor = Self::append_to_global_texture_data(texture, global_texture_data);
+        }
         Self {
-            id: 1_u32,
-            desc1: Self::append_to_global_texture_data(albedo, global_texture_data),
-            desc2: TextureDescriptor::empty(),
-            x: fuzz,
+            id,
+            textures: descriptors,
+            x,
+            normal_map: TextureDescriptor::empty(),
+            bump_map: TextureDescriptor::empty(),
+            bump_strength: 0_f32,
         }
     }
 
+    pub fn lambertian(albedo: &Texture, global_texture_data: &mut Vec<[f32; 3]>) -> Self {
+        Self::with_textures(0_u32, &[albedo], 0_f32, global_texture_data)
+    }
+
+    pub fn metal(albedo: &Texture, fuzz: f32, global_texture_data: &mut Vec<[f32; 3]>) -> Self {
+        Self::with_textures(1_u32, &[albedo], fuzz, global_texture_data)
+    }
+
     pub fn dielectric(refraction_index: f32) -> Self {
-        Self {
-            id: 2_u32,
-            desc1: TextureDescriptor::empty(),
-            desc2: TextureDescriptor::empty(),
-            x: refraction_index,
-        }
+        Self::with_textures(2_u32, &[], refraction_index, &mut Vec::new())
     }
 
     pub fn checkerboard(
@@ -133,21 +579,20 @@ impl GpuMaterial {
         odd: &Texture,
         global_texture_data: &mut Vec<[f32; 3]>,
     ) -> Self {
-        Self {
-            id: 3_u32,
-            desc1: Self::append_to_global_texture_data(even, global_texture_data),
-            desc2: Self::append_to_global_texture_data(odd, global_texture_data),
-            x: 0_f32,
-        }
+        Self::with_textures(3_u32, &[even, odd], 0_f32, global_texture_data)
     }
 
     pub fn emissive(emit: &Texture, global_texture_data: &mut Vec<[f32; 3]>) -> Self {
-        Self {
-            id: 4_u32,
-            desc1: Self::append_to_global_texture_data(emit, global_texture_data),
-            desc2: TextureDescriptor::empty(),
-            x: 0_f32,
-        }
+        Self::with_textures(4_u32, &[emit], 0_f32, global_texture_data)
+    }
+
+    /// The textures are in the order of `Material::textures`, which is the order of the
+    /// `PRINCIPLED_*` constants in the kernel.
+    pub fn principled(material: &Material, global_texture_data: &mut Vec<[f32; 3]>) -> Self {
+        let Material::Principled { refraction_index, .. } = material else {
+            panic!("{} is not a principled material", material.name());
+        };
+        Self::with_textures(5_u32, &material.textures(), *refraction_index, global_texture_data)
     }
 
     fn append_to_global_texture_data(
@@ -156,7 +601,7 @@ impl GpuMaterial {
     ) -> TextureDescriptor {
         let dimensions = texture.dimensions();
         let offset = global_texture_data.len() as u32;
-        global_texture_data.extend_from_slice(texture.as_slice());
+        global_texture_data.extend(texture.texels());
         TextureDescriptor {
             width: dimensions.0,
             height: dimensions.1,
@@ -164,3 +609,101 @@ impl GpuMaterial {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(scene: &Scene, name: &str) -> Scene {
+        let path = std::env::temp_dir().join(format!("{}-{}.ron", name, std::process::id()));
+        scene.save(&path).expect("Failed to save the scene");
+        let loaded = Scene::load(&path);
+        std::fs::remove_file(&path).ok();
+        loaded.expect("Failed to load the saved scene")
+    }
+
+    #[test]
+    fn example_scene_round_trips() {
+        let scene = Scene::load("assets/scene.ron").unwrap();
+        assert_eq!(round_trip(&scene, "example_scene"), scene);
+    }
+
+    #[test]
+    fn edited_scene_round_trips() {
+        let scene = Scene {
+            materials: vec![
+                Material::Emissive {
+                    emit: Texture::new_from_scaled_image("assets/sun.jpeg", 5_f32).unwrap(),
+                },
+                Material::Metal {
+                    albedo: Texture::new_from_color(glm::vec3(0.1, 0.2, 0.3)),
+                    fuzz: 0.25,
+                },
+                Material::Dielectric { refraction_index: 1.33 },
+                Material::Principled {
+                    base_color: Texture::new_from_color(glm::vec3(0.9, 0.6, 0.2)),
+                    metallic: Texture::new_from_value(1.0),
+                    roughness: Texture::new_from_scaled_image("assets/moon.jpeg", 0.5).unwrap(),
+                    specular: Texture::new_from_value(0.5),
+                    specular_tint: Texture::new_from_value(0.0),
+                    clearcoat: Texture::new_from_value(0.25),
+                    sheen: Texture::new_from_value(0.0),
+                    transmission: Texture::new_from_value(0.0),
+                    anisotropy: Texture::new_from_value(0.5),
+                    refraction_index: 1.45,
+                },
+                Material::Mapped {
+                    material: Box::new(Material::Lambertian {
+                        albedo: Texture::new_from_color(glm::vec3(0.5, 0.5, 0.5)),
+                    }),
+                    normal_map: Some(Texture::new_from_image("assets/ripples_normal.png").unwrap()),
+                    bump_map: None,
+                    bump_strength: 1.0,
+                },
+            ],
+            spheres: vec![
+                Sphere::new(glm::vec3(1.0, 2.0, 3.0), 0.5, 0),
+                Sphere::new(glm::vec3(-1.0, 0.0, -2.0), 2.0, 1)
+                    .with_velocity(glm::vec3(0.5, 0.0, 0.0), 1.5),
+                Sphere::new(glm::vec3(0.0, -100.0, 0.0), 99.0, 2),
+                Sphere::new(glm::vec3(2.0, 0.0, 0.0), 0.5, 3),
+            ],
+        };
+
+        assert_eq!(round_trip(&scene, "edited_scene"), scene);
+    }
+
+    #[test]
+    fn image_scale_is_applied_without_drift() {
+        let mut texture = Texture::new_from_image("assets/sun.jpeg").unwrap();
+        let texels: Vec<[f32; 3]> = texture.texels().collect();
+        for scale in [0.1, 3.7, 1e-3, 1.0] {
+            texture.set_scale(scale);
+        }
+        assert_eq!(texture.texels().collect::<Vec<_>>(), texels);
+
+        texture.set_scale(2.0);
+        assert!(texture.texels().zip(&texels).all(|(a, b)| a == b.map(|c| 2.0 * c)));
+    }
+
+    #[test]
+    fn non_positive_image_scale_is_rejected() {
+        for scale in [0.0, -1.0, f32::NAN] {
+            assert!(matches!(
+                Texture::new_from_scaled_image("assets/sun.jpeg", scale),
+                Err(TextureError::InvalidScale(_))
+            ));
+        }
+        let err = ron::from_str::<Texture>(r#"Image(path: "assets/sun.jpeg", scale: 0.0)"#).unwrap_err();
+        assert!(err.to_string().contains("has to be positive"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn saved_scene_references_images_by_path() {
+        let scene = Scene::load("assets/scene.ron").unwrap();
+        let source = ron::ser::to_string(&scene).unwrap();
+
+        assert!(source.contains("\"assets/sun.jpeg\""));
+        assert!(source.len() < 4096, "texture data was written to the file");
+    }
+}
@@ -1,5 +1,27 @@
 @group(0) @binding(0) var screen_sampler : sampler;
 @group(0) @binding(1) var color_buffer : texture_2d<f32>;
+@group(0) @binding(2) var<uniform> screenParams : ScreenParams;
+@group(0) @binding(3) var<storage, read> aovBuffer : array<Aov>;
+
+const CHANNEL_BEAUTY = 0u;
+
+const NO_HIT = 0xffffffffu;
+// The depth that is shown half as bright as the near plane.
+const HALF_DEPTH = 5f;
+
+struct ScreenParams {
+    channel: u32,
+    width: u32,
+}
+
+struct Aov {
+    albedo: vec3<f32>,
+    materialIdx: u32,
+    normal: vec3<f32>,
+    sphereIdx: u32,
+    position: vec3<f32>,
+    depth: f32,
+}
 
 struct VertexOutput {
     @builtin(position) Position : vec4<f32>,
@@ -33,7 +55,45 @@ fn vert_main(@builtin(vertex_index) VertexIndex : u32) -> VertexOutput {
     return output;
 }
 
+// A random but stable color for every id.
+fn idColor(id: u32) -> vec3<f32> {
+    var hash = id * 747796405u + 2891336453u;
+    hash = ((hash >> ((hash >> 28u) + 4u)) ^ hash) * 277803737u;
+    hash = (hash >> 22u) ^ hash;
+    return vec3(f32(hash & 0xffu), f32((hash >> 8u) & 0xffu), f32((hash >> 16u) & 0xffu)) / 255f;
+}
+
+fn aovColor(aov: Aov) -> vec3<f32> {
+    let hit = aov.sphereIdx != NO_HIT;
+    // The values of `AovChannel`.
+    switch screenParams.channel {
+        case 1u: {
+            return aov.albedo;
+        }
+        case 2u: {
+            return 0.5f * aov.normal + 0.5f;
+        }
+        case 3u: {
+            return select(vec3(0f), fract(aov.position), hit);
+        }
+        case 4u: {
+            return select(vec3(0f), vec3(HALF_DEPTH / (HALF_DEPTH + aov.depth)), hit);
+        }
+        case 5u: {
+            return select(vec3(0f), idColor(aov.materialIdx), hit);
+        }
+        default: {
+            return select(vec3(0f), idColor(aov.sphereIdx), hit);
+        }
+    }
+}
+
 @fragment
-fn frag_main(@location(0) TexCoord : vec2<f32>) -> @location(0) vec4<f32> {
-  return textureSample(color_buffer, screen_sampler, TexCoord);
+fn frag_main(@builtin(position) FragCoord : vec4<f32>, @location(0) TexCoord : vec2<f32>) -> @location(0) vec4<f32> {
+  if screenParams.channel == CHANNEL_BEAUTY {
+    return textureSample(color_buffer, screen_sampler, TexCoord);
+  }
+  // The AOVs are shown unfiltered, the ids can't be interpolated.
+  let pixel = vec2<u32>(FragCoord.xy);
+  return vec4(aovColor(aovBuffer[pixel.y * screenParams.width + pixel.x]), 1.0);
 }
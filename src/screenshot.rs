@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+
+use crate::gpu_buffer;
+
+/// A linear HDR image read back from an accumulation buffer.
+pub struct HdrImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+    pub fn new(width: u32, height: u32, pixels: Vec<[f32; 3]>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        Self { width, height, pixels }
+    }
+
+    /// Copies the accumulation buffer to the CPU and divides the sums by the number of samples.
+    pub fn read_accumulation(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        accumulation_buffer: &wgpu::Buffer,
+        size: winit::dpi::PhysicalSize<u32>,
+        num_samples: u32,
+    ) -> Self {
+        let scale = 1_f32 / num_samples.max(1) as f32;
+        let pixels = gpu_buffer::read_buffer::<[f32; 4]>(device, queue, accumulation_buffer)
+            .iter()
+            .map(|p| [scale * p[0], scale * p[1], scale * p[2]])
+            .collect();
+
+        Self {
+            width: size.width,
+            height: size.height,
+            pixels,
+        }
+    }
+
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        winit::dpi::PhysicalSize::new(self.width, self.height)
+    }
+
+    pub fn pixels(&self) -> &[[f32; 3]] {
+        &self.pixels
+    }
+
+    /// Writes the image scaled by `2^exposure`, tone mapped with the ACES filmic curve and
+    /// sRGB encoded. Unlike clamping, the curve keeps detail in highlights.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P, exposure: f32) -> Result<(), image::ImageError> {
+        let exposure_scale = exposure.exp2();
+        let data = self
+            .pixels
+            .iter()
+            .flat_map(|p| p.map(|c| linear_to_srgb8(aces_filmic(exposure_scale * c))))
+            .collect();
+        image::RgbImage::from_raw(self.width, self.height, data)
+            .expect("The pixel count matches the dimensions")
+            .save(path)
+    }
+
+    /// Writes the linear radiance without any clamping.
+    pub fn save_exr<P: AsRef<Path>>(&self, path: P) -> Result<(), image::ImageError> {
+        let data = self.pixels.iter().flatten().copied().collect();
+        image::Rgb32FImage::from_raw(self.width, self.height, data)
+            .expect("The pixel count matches the dimensions")
+            .save(path)
+    }
+
+    /// Writes both a PNG with the given exposure and an EXR next to each other and returns
+    /// the paths.
+    pub fn save<P: AsRef<Path>>(&self, path_without_extension: P, exposure: f32) -> Result<[PathBuf; 2], image::ImageError> {
+        let png_path = path_without_extension.as_ref().with_extension("png");
+        let exr_path = path_without_extension.as_ref().with_extension("exr");
+        if let Some(dir) = png_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        self.save_png(&png_path, exposure)?;
+        self.save_exr(&exr_path)?;
+        Ok([png_path, exr_path])
+    }
+}
+
+#[cfg(test)]
+impl HdrImage {
+    pub fn open_exr<P: AsRef<Path>>(path: P) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_rgb32f();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.pixels().map(|p| p.0).collect(),
+        })
+    }
+}
+
+/// Narkowicz's fit of the ACES filmic tone mapping curve, which maps [0, ∞) to [0, 1).
+/// NaNs map to black.
+fn aces_filmic(x: f32) -> f32 {
+    let x = x.max(0_f32);
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn linear_to_srgb8(x: f32) -> u8 {
+    let x = x.clamp(0_f32, 1_f32);
+    let srgb = if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1_f32 / 2.4) - 0.055
+    };
+    (255_f32 * srgb).round() as u8
+}
+
+/// Returns a new path in the screenshot directory, without an extension.
+pub fn next_screenshot_path(prefix: &str) -> PathBuf {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    PathBuf::from("screenshots").join(format!("{}-{}", prefix, timestamp.as_millis()))
+}
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use crate::file_watcher::FileWatcher;
+
+/// Watches a WGSL file on disk and validates it with naga whenever it changes, so that
+/// broken shaders never reach pipeline creation.
+pub struct ShaderWatcher {
+    path: PathBuf,
+    watcher: FileWatcher,
+}
+
+impl ShaderWatcher {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let watcher = FileWatcher::new([path.clone()]);
+        Self { path, watcher }
+    }
+
+    /// Reads and validates the shader. On failure, returns a message with the
+    /// offending line numbers.
+    pub fn load(&self) -> Result<String, String> {
+        let source = std::fs::read_to_string(&self.path)
+            .map_err(|err| format!("{}: {}", self.path.display(), err))?;
+        validate(&source, &self.path.to_string_lossy())?;
+        Ok(source)
+    }
+
+    /// Returns the result of loading the shader again if the file changed.
+    pub fn poll(&mut self) -> Option<Result<String, String>> {
+        if self.watcher.poll() {
+            Some(self.load())
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses and validates WGSL source, returning the errors rendered like compiler diagnostics.
+pub fn validate(source: &str, path: &str) -> Result<(), String> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|err| err.emit_to_string_with_path(source, path))?;
+
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|err| err.emit_to_string_with_path(source, path))?;
+
+    Ok(())
+}
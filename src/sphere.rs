@@ -1,7 +1,10 @@
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable, serde::Deserialize, serde::Serialize)]
+#[serde(from = "SphereDescription", into = "SphereDescription")]
 pub struct Sphere {
     pub center: glm::Vec4,
+    /// The xyz components are the linear velocity, w is the angular velocity around the y axis.
+    pub velocity: glm::Vec4,
     pub radius: f32,
     pub material_idx: u32,
     _padding: [u32; 2],
@@ -11,9 +14,89 @@ impl Sphere {
     pub fn new(center: glm::Vec3, radius: f32, material_idx: u32) -> Self {
         Self {
             center: glm::vec3_to_vec4(&center),
+            velocity: glm::Vec4::zeros(),
             radius,
             material_idx,
             _padding: [0; 2],
         }
     }
-}
\ No newline at end of file
+
+    /// Makes the sphere move with the given linear velocity and spin around its y axis
+    /// with the given angular velocity in radians per unit of time.
+    pub fn with_velocity(mut self, linear: glm::Vec3, angular: f32) -> Self {
+        self.velocity = glm::vec4(linear.x, linear.y, linear.z, angular);
+        self
+    }
+
+    pub fn center_at(&self, time: f32) -> glm::Vec3 {
+        self.center.xyz() + time * self.velocity.xyz()
+    }
+
+    /// Returns the ray parameter of the closest intersection in `(tmin, tmax)`,
+    /// mirroring `rayIntersectSphere` in the kernel.
+    pub fn intersect(
+        &self,
+        origin: &glm::Vec3,
+        direction: &glm::Vec3,
+        time: f32,
+        tmin: f32,
+        tmax: f32,
+    ) -> Option<f32> {
+        let oc = origin - self.center_at(time);
+        let a = glm::dot(direction, direction);
+        let b = glm::dot(&oc, direction);
+        let c = glm::dot(&oc, &oc) - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+
+        if discriminant < 0_f32 {
+            return None;
+        }
+
+        let t = (-b - discriminant.sqrt()) / a;
+        if t < tmax && t > tmin {
+            return Some(t);
+        }
+
+        let t = (-b + discriminant.sqrt()) / a;
+        if t < tmax && t > tmin {
+            return Some(t);
+        }
+
+        None
+    }
+}
+
+/// How a sphere is described in a scene file.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct SphereDescription {
+    center: [f32; 3],
+    radius: f32,
+    material: u32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    velocity: [f32; 3],
+    #[serde(default, skip_serializing_if = "is_zero")]
+    angular_velocity: f32,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+impl From<SphereDescription> for Sphere {
+    fn from(desc: SphereDescription) -> Self {
+        Sphere::new(glm::make_vec3(&desc.center), desc.radius, desc.material)
+            .with_velocity(glm::make_vec3(&desc.velocity), desc.angular_velocity)
+    }
+}
+
+impl From<Sphere> for SphereDescription {
+    fn from(sphere: Sphere) -> Self {
+        Self {
+            center: sphere.center.xyz().into(),
+            radius: sphere.radius,
+            material: sphere.material_idx,
+            velocity: sphere.velocity.xyz().into(),
+            angular_velocity: sphere.velocity.w,
+        }
+    }
+}